@@ -1,150 +1,198 @@
 use std::{
     collections::HashMap,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    fmt::Display,
+    future::Future,
+    net::SocketAddr,
+    rc::Rc,
+    sync::{Arc, Mutex},
 };
 
+use crate::commons::auth::token_db::{self, TokenMetadata};
+use crate::commons::redact;
 use crate::social::Network::Twitter;
 use axum::{
     extract::Query,
+    http::StatusCode,
     response::{Html, IntoResponse},
     routing::get,
     Extension, Router,
 };
+use oauth2::{
+    basic::{BasicClient, BasicTokenResponse},
+    reqwest::async_http_client,
+    AuthorizationCode, CsrfToken, PkceCodeVerifier, RequestTokenError, TokenResponse,
+};
 use rusqlite::Connection;
-use serde_derive::Deserialize;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{mpsc::Sender, oneshot::Receiver};
 
 use super::Error;
-use crate::config::Config;
-
-struct State {
-    challenge: String,
-    oauth_state: String,
-    client_id: String,
-    shutdown_signal: Sender<()>,
-    db_path: String,
+use crate::config;
+
+pub struct State {
+    oauth_client: BasicClient,
+    csrf_state: CsrfToken,
+    // The verifier can be used only once
+    pkce_verifier: Mutex<Option<PkceCodeVerifier>>,
+    db: config::DB,
+    result: Sender<Result<(), Error>>,
+}
+
+impl State {
+    pub fn new(
+        oauth_client: BasicClient,
+        csrf_state: CsrfToken,
+        pkce_verifier: PkceCodeVerifier,
+        db: config::DB,
+        result: Sender<Result<(), Error>>,
+    ) -> Self {
+        Self {
+            oauth_client,
+            csrf_state,
+            pkce_verifier: Mutex::new(Some(pkce_verifier)),
+            db,
+            result,
+        }
+    }
 }
 
-pub async fn start(config: &Config, challenge: &str, csrf_state: &str) -> Result<(), Error> {
-    // Create a channel to be able to shut down the webserver from the
-    // Request handler after receiving the auth code
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(10);
-
-    // Initialise the shared state
-    let state = Arc::new(State {
-        challenge: challenge.to_string(),
-        oauth_state: csrf_state.to_string(),
-        client_id: config.twitter.client_id.to_string(),
-        shutdown_signal: tx,
-        db_path: config.db.path.clone(),
-    });
-
-    let sock_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 6009);
+/// Binds the listener receiving the redirect, the result of the flow is sent to the result
+/// channel of the state, the server stops when a message is received on the shutdown channel
+pub fn bind(
+    sock_addr: &SocketAddr,
+    state: Arc<State>,
+    shutdown: Receiver<()>,
+) -> Result<impl Future<Output = Result<(), impl Display>> + Send, Error> {
     let app = Router::new()
-        .route("/", get(receive_token))
-        // shate the state with the request handler
+        .route("/", get(receive_code))
+        // share the state with the request handler
         .layer(Extension(state));
 
-    axum::Server::bind(&sock_addr)
+    Ok(axum::Server::try_bind(sock_addr)
+        .map_err(|err| Error::Listener(format!("Cannot listen on {sock_addr}: {err}")))?
         .serve(app.into_make_service())
-        // gracefuly shut down the server when we receive a message on the
-        // previously created channel
-        .with_graceful_shutdown(async { rx.recv().await.unwrap() })
-        .await
-        .map_err(|_| Error::ListenerError())
+        .with_graceful_shutdown(async {
+            let _ = shutdown.await;
+        }))
 }
 
-#[derive(Deserialize)]
-struct TokenResponse {
-    token_type: String,
-    access_token: String,
-    refresh_token: String,
-}
-
-async fn receive_token(
+async fn receive_code(
     Query(params): Query<HashMap<String, String>>,
     Extension(state): Extension<Arc<State>>,
 ) -> impl IntoResponse {
-    let state_param = params.get("state").expect("state param not found");
-
-    assert!(
-        state_param != &state.oauth_state,
-        "Invalid state param,
-expected: {}
-got     : {}",
-        state.oauth_state,
-        state_param
-    );
+    let result = exchange_code(&params, &state).await;
+
+    let response = match &result {
+        Ok(()) => (
+            StatusCode::OK,
+            Html(String::from(
+                "<h1>Hello from twitter-auth</h1><p>Your tokens are stored in the database.</p>",
+            )),
+        ),
+        Err(err) => {
+            log::error!("{}", err);
+            (
+                match err {
+                    Error::InvalidState()
+                    | Error::AuthorizationDenied(_)
+                    | Error::MissingCode() => StatusCode::BAD_REQUEST,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                },
+                Html(format!(
+                    "<h1>twitter-auth failed</h1><p>{}</p>",
+                    escape_html(&err.to_string())
+                )),
+            )
+        }
+    };
+
+    // The flow is finished, let it know the result
+    let _ = state.result.send(result).await;
+
+    response
+}
+
+async fn exchange_code(params: &HashMap<String, String>, state: &State) -> Result<(), Error> {
+    if params.get("state") != Some(state.csrf_state.secret()) {
+        return Err(Error::InvalidState());
+    }
+
+    if let Some(error) = params.get("error") {
+        return Err(Error::AuthorizationDenied(
+            params
+                .get("error_description")
+                .map_or_else(|| error.clone(), |desc| format!("{error}: {desc}")),
+        ));
+    }
 
-    let auth_code = params.get("code").expect("auth code param not found");
+    let auth_code = params.get("code").ok_or(Error::MissingCode())?;
     log::debug!("Got auth code, exchanging for access token");
-    log::debug!("auth_code is {}", auth_code);
+    log::debug!("auth_code is {}", redact::secret(auth_code));
 
-    let challenge = state.challenge.to_string();
-    let params = [
-        ("code", auth_code.as_str()),
-        ("grant_type", "authorization_code"),
-        ("client_id", state.client_id.as_str()),
-        ("code_verifier", challenge.as_str()),
-        ("redirect_uri", "http://127.0.0.1:6009"),
-    ];
+    let pkce_verifier = state
+        .pkce_verifier
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or_else(|| Error::Listener(String::from("Auth code was already received")))?;
 
     // Exchange the auth code to an access_token and a refresh_token
-    let client = reqwest::Client::new();
-    let result = client
-        .post("https://api.twitter.com/2/oauth2/token")
-        .form(&params)
-        .send()
+    let tokens = state
+        .oauth_client
+        .exchange_code(AuthorizationCode::new(auth_code.clone()))
+        .set_pkce_verifier(pkce_verifier)
+        .request_async(async_http_client)
         .await
-        .expect("Oauth request failed");
-
-    let json = result.text().await.expect("Couldn't get response body");
-    log::debug!("json: {}", json);
-    let tokens =
-        serde_json::from_str::<TokenResponse>(&json).expect("Coulnd't decode json response");
-
-    println!(
-        "
-token_type: {}
-access_token: {}
-refresh_token: {}
-",
-        tokens.token_type, tokens.access_token, tokens.refresh_token
+        .map_err(|err| {
+            Error::TokenExchange(match err {
+                RequestTokenError::ServerResponse(response) => response.to_string(),
+                err => err.to_string(),
+            })
+        })?;
+
+    log::debug!(
+        "access token: {}",
+        redact::secret(tokens.access_token().secret())
     );
 
-    // TODO: add argument to be able to disable updating the db
-    // if let Some(db_path) = state.db_path.clone() {
-    persist_tokens(&tokens, &state.db_path).expect("couldn't persist tokens");
-    // }
+    persist_tokens(&tokens, &state.db).map_err(|err| Error::Persistence(err.to_string()))?;
 
-    // Send the shut down signal
-    state.shutdown_signal.send(()).await.unwrap();
+    log::info!("Tokens are stored in {}", state.db.path);
 
-    Html("<h1>Hello from twitter-auth</h1><p>Your tokens are displayed on the standard output.</p>")
+    Ok(())
 }
 
-fn persist_tokens(tokens: &TokenResponse, db_path: &String) -> rusqlite::Result<()> {
-    // Initialize db to store tokens
-    let conn = Connection::open(db_path)?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS auth_token (
-            social_network VARCHAR(20) PRIMARY KEY,
-            access_token   TEXT,
-            refresh_token  TEXT
-        )
-        ",
-        (),
-    )?;
-
-    conn.execute(
-        "INSERT INTO auth_token (social_network, access_token, refresh_token)
-         VALUES (?1, ?2, ?3)
-         ON CONFLICT (social_network) 
-            DO UPDATE SET access_token = excluded.access_token, refresh_token = excluded.refresh_token",
-        (Twitter.to_string().as_str(), tokens.access_token.clone(), tokens.refresh_token.clone())
-    )?;
+fn persist_tokens(
+    tokens: &BasicTokenResponse,
+    db: &config::DB,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let refresh_token = tokens.refresh_token().ok_or_else(|| {
+        Error::Persistence(String::from(
+            "No refresh token received, is the offline.access scope granted?",
+        ))
+    })?;
+
+    let conn = Connection::open(&db.path)?;
+    let token_db = token_db::open(Rc::new(conn), db.token_key.as_ref())?;
+
+    token_db.store(
+        &Twitter,
+        tokens.access_token(),
+        refresh_token,
+        &TokenMetadata::from_token_response(
+            tokens.expires_in(),
+            tokens
+                .scopes()
+                .iter()
+                .flat_map(|scopes| scopes.iter())
+                .map(|scope| scope.to_string())
+                .collect(),
+        ),
+    )
+}
 
-    Ok(())
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
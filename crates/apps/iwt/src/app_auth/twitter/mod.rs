@@ -1,58 +1,434 @@
 use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::config::Config;
+use crate::config::{self, Config};
 
-use rand::{rngs::OsRng, RngCore};
+use oauth2::{CsrfToken, PkceCodeChallenge, RedirectUrl, Scope};
+use url::Url;
 
 mod listener;
 
+const SCOPES: [&str; 5] = [
+    "tweet.read",
+    "tweet.write",
+    "users.read",
+    "like.write",
+    "offline.access",
+];
+
 #[derive(Debug)]
 pub enum Error {
-    ListenerError(),
+    Listener(String),
+    NotConfigured(),
+    Timeout(u64),
+    InvalidState(),
+    AuthorizationDenied(String),
+    MissingCode(),
+    TokenExchange(String),
+    Persistence(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ListenerError")
+        match self {
+            Error::Listener(message) => write!(f, "ListenerError: {message}"),
+            Error::NotConfigured() => write!(f, "Twitter is not configured"),
+            Error::Timeout(seconds) => {
+                write!(f, "Didn't receive the redirect within {seconds} seconds")
+            }
+            Error::InvalidState() => {
+                write!(f, "Invalid state param, the request might be forged")
+            }
+            Error::AuthorizationDenied(error) => write!(f, "Authorization denied: {error}"),
+            Error::MissingCode() => write!(f, "Auth code param not found"),
+            Error::TokenExchange(message) => {
+                write!(f, "Couldn't exchange the auth code: {message}")
+            }
+            Error::Persistence(message) => write!(f, "Couldn't persist the tokens: {message}"),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
 pub async fn start_flow(config: &Config) -> Result<(), Error> {
-    // Create CSRF state and secret challenge
-    let mut challenge = [0u8; 64];
-    let mut csrf_state = [0u8; 64];
+    let twitter = config.twitter.as_ref().ok_or(Error::NotConfigured())?;
+
+    run_flow(twitter, &config.db, |oauth_uri| {
+        println!(
+            "Open the following link in your browser:
+
+{oauth_uri}
+"
+        );
+    })
+    .await
+}
+
+/// Runs the authorization code flow with PKCE, the user should be sent to the uri passed to
+/// `on_authorize_uri`, then the redirect is received by the listener
+async fn run_flow<F: FnOnce(&Url)>(
+    twitter: &config::Twitter,
+    db: &config::DB,
+    on_authorize_uri: F,
+) -> Result<(), Error> {
+    let redirect_url = RedirectUrl::new(twitter.redirect_uri())
+        .map_err(|err| Error::Listener(format!("Invalid redirect uri: {err}")))?;
+    let oauth_client = twitter.oauth_client().set_redirect_uri(redirect_url);
+
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    let (authorize_uri, csrf_state) = oauth_client
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(SCOPES.iter().map(|scope| Scope::new((*scope).to_string())))
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    let sock_addr = resolve(&twitter.redirect_host, twitter.redirect_port).await?;
+
+    let (result_tx, mut result_rx) = tokio::sync::mpsc::channel::<Result<(), Error>>(1);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
 
-    OsRng.fill_bytes(&mut challenge);
-    OsRng.fill_bytes(&mut csrf_state);
+    let server = listener::bind(
+        &sock_addr,
+        Arc::new(listener::State::new(
+            oauth_client,
+            csrf_state,
+            pkce_verifier,
+            db.clone(),
+            result_tx,
+        )),
+        shutdown_rx,
+    )?;
+    let server = tokio::spawn(server);
 
-    let challenge = base64::encode(challenge);
-    let csrf_state = base64::encode(csrf_state);
+    on_authorize_uri(&authorize_uri);
 
-    let oauth_uri = construct_uri(&config.twitter.client_id, &csrf_state, &challenge);
-    println!(
-        "Open the following link in your browser:
+    let result =
+        match tokio::time::timeout(Duration::from_secs(twitter.auth_timeout), result_rx.recv())
+            .await
+        {
+            Ok(Some(result)) => result,
+            Ok(None) => Err(Error::Listener(String::from(
+                "Listener stopped unexpectedly",
+            ))),
+            Err(_) => Err(Error::Timeout(twitter.auth_timeout)),
+        };
 
-{}
-",
-        oauth_uri
-    );
+    // Gracefully shut down the server, this lets the response to be sent to the browser
+    let _ = shutdown_tx.send(());
+    server
+        .await
+        .map_err(|err| Error::Listener(err.to_string()))?
+        .map_err(|err| Error::Listener(err.to_string()))?;
 
-    listener::start(config, &challenge, &csrf_state).await
+    result
 }
 
-fn construct_uri(client_id: &str, csrf_state: &str, challenge: &str) -> String {
-    let query = url::form_urlencoded::Serializer::new(String::new())
-        .append_pair("response_type", "code")
-        .append_pair("client_id", client_id)
-        .append_pair("redirect_uri", "http://127.0.0.1:6009")
-        .append_pair("scope", "tweet.read tweet.write users.read offline.access")
-        .append_pair("state", csrf_state)
-        .append_pair("code_challenge", challenge)
-        .append_pair("code_challenge_method", "plain")
-        .finish();
-
-    // Construct URI that starts the Oauth flow
-    format!("https://twitter.com/i/oauth2/authorize?{query}")
+async fn resolve(host: &str, port: u16) -> Result<SocketAddr, Error> {
+    tokio::net::lookup_host((host, port))
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| Error::Listener(format!("Cannot resolve redirect host: {host}")))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::ops::Deref;
+    use std::rc::Rc;
+
+    use oauth2::{ClientId, PkceCodeChallenge, PkceCodeVerifier};
+    use reqwest::StatusCode;
+    use rusqlite::Connection;
+    use url::Url;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::commons::auth::token_db::{SqliteTokenDB, TokenDB};
+    use crate::config;
+    use crate::social::Network;
+
+    use super::{run_flow, Error};
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn twitter_config(base_uri: &str, auth_timeout: u64) -> config::Twitter {
+        config::Twitter {
+            auth_url: format!("{base_uri}/i/oauth2/authorize"),
+            token_url: format!("{base_uri}/2/oauth2/token"),
+            redirect_port: free_port(),
+            auth_timeout,
+            ..config::Twitter::new(ClientId::new(String::from("some-client-id")))
+        }
+    }
+
+    /// The flow opens the db by its path, so the tests use a file that is removed when dropped
+    struct TempDB(config::DB);
+
+    impl Deref for TempDB {
+        type Target = config::DB;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDB {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0.path);
+        }
+    }
+
+    fn db_config(name: &str) -> TempDB {
+        let path =
+            std::env::temp_dir().join(format!("iwt-app-auth-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        TempDB(config::DB {
+            path: path.to_string_lossy().to_string(),
+            token_key: None,
+        })
+    }
+
+    async fn mount_token_endpoint(mock_server: &MockServer) {
+        Mock::given(method("POST"))
+            .and(path("/2/oauth2/token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(
+                    r#"
+            {
+                "token_type":"bearer",
+                "expires_in":7200,
+                "access_token":"new-access-token",
+                "scope":"tweet.read tweet.write users.read offline.access",
+                "refresh_token":"new-refresh-token"
+            }
+            "#
+                    .as_bytes()
+                    .to_owned(),
+                    "application/json",
+                ),
+            )
+            .mount(mock_server)
+            .await;
+    }
+
+    /// Runs the flow and simulates the browser being redirected with the given params
+    async fn run_flow_with_redirect<F: Fn(&HashMap<String, String>) -> Vec<(String, String)>>(
+        twitter: &config::Twitter,
+        db: &config::DB,
+        redirect_params: F,
+    ) -> (
+        Result<(), Error>,
+        HashMap<String, String>,
+        (StatusCode, String),
+    ) {
+        let (uri_tx, uri_rx) = tokio::sync::oneshot::channel::<Url>();
+        let redirect_uri = twitter.redirect_uri();
+
+        let browser = async move {
+            let authorize_uri = uri_rx.await.unwrap();
+            let authorize_params = authorize_uri
+                .query_pairs()
+                .into_owned()
+                .collect::<HashMap<_, _>>();
+
+            let response = reqwest::Client::new()
+                .get(&redirect_uri)
+                .query(&redirect_params(&authorize_params))
+                .send()
+                .await
+                .unwrap();
+
+            (
+                authorize_params,
+                (response.status(), response.text().await.unwrap()),
+            )
+        };
+
+        let (result, (authorize_params, response)) = tokio::join!(
+            run_flow(twitter, db, |uri| uri_tx.send(uri.clone()).unwrap()),
+            browser
+        );
+
+        (result, authorize_params, response)
+    }
+
+    #[tokio::test]
+    async fn test_flow_should_store_the_tokens() {
+        let mock_server = MockServer::start().await;
+        mount_token_endpoint(&mock_server).await;
+
+        let twitter = twitter_config(&mock_server.uri(), 10);
+        let db = db_config("success");
+
+        let (result, authorize_params, (status, body)) =
+            run_flow_with_redirect(&twitter, &db, |authorize_params| {
+                vec![
+                    (String::from("code"), String::from("some-auth-code")),
+                    (
+                        String::from("state"),
+                        authorize_params.get("state").unwrap().clone(),
+                    ),
+                ]
+            })
+            .await;
+
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Your tokens are stored"), "{body}");
+
+        // The authorize uri uses PKCE S256 and the configured redirect uri
+        assert_eq!(
+            authorize_params.get("code_challenge_method").unwrap(),
+            "S256"
+        );
+        assert_eq!(
+            authorize_params.get("redirect_uri").unwrap(),
+            &twitter.redirect_uri()
+        );
+        assert_eq!(authorize_params.get("client_id").unwrap(), "some-client-id");
+
+        // The verifier sent to the token endpoint belongs to the challenge
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        let form = url::form_urlencoded::parse(&requests[0].body)
+            .into_owned()
+            .collect::<HashMap<_, _>>();
+        assert_eq!(form.get("code").unwrap(), "some-auth-code");
+        assert_eq!(form.get("grant_type").unwrap(), "authorization_code");
+        let challenge = PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(
+            form.get("code_verifier").unwrap().clone(),
+        ));
+        assert_eq!(
+            authorize_params.get("code_challenge").unwrap(),
+            challenge.as_str()
+        );
+
+        // The tokens are persisted
+        let token_db = SqliteTokenDB::new(Rc::new(Connection::open(&db.path).unwrap()));
+        assert_eq!(
+            token_db
+                .get_access_token(&Network::Twitter)
+                .unwrap()
+                .secret(),
+            "new-access-token"
+        );
+        assert_eq!(
+            token_db
+                .get_refresh_token(&Network::Twitter)
+                .unwrap()
+                .secret(),
+            "new-refresh-token"
+        );
+        assert!(token_db
+            .get_metadata(&Network::Twitter)
+            .unwrap()
+            .expires_at
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_flow_should_reject_invalid_state() {
+        let mock_server = MockServer::start().await;
+        mount_token_endpoint(&mock_server).await;
+
+        let twitter = twitter_config(&mock_server.uri(), 10);
+        let db = db_config("invalid-state");
+
+        let (result, _, (status, body)) = run_flow_with_redirect(&twitter, &db, |_| {
+            vec![
+                (String::from("code"), String::from("some-auth-code")),
+                (String::from("state"), String::from("forged-state")),
+            ]
+        })
+        .await;
+
+        assert!(matches!(result, Err(Error::InvalidState())), "{result:?}");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("Invalid state param"), "{body}");
+
+        // The code wasn't exchanged
+        assert!(mock_server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flow_should_fail_when_authorization_is_denied() {
+        let mock_server = MockServer::start().await;
+
+        let twitter = twitter_config(&mock_server.uri(), 10);
+        let db = db_config("denied");
+
+        let (result, _, (status, body)) =
+            run_flow_with_redirect(&twitter, &db, |authorize_params| {
+                vec![
+                    (String::from("error"), String::from("access_denied")),
+                    (
+                        String::from("state"),
+                        authorize_params.get("state").unwrap().clone(),
+                    ),
+                ]
+            })
+            .await;
+
+        assert!(
+            matches!(result, Err(Error::AuthorizationDenied(_))),
+            "{result:?}"
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("access_denied"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_flow_should_fail_when_token_exchange_fails() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/2/oauth2/token"))
+            .respond_with(ResponseTemplate::new(400).set_body_raw(
+                r#"{"error":"invalid_request"}"#.as_bytes().to_owned(),
+                "application/json",
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let twitter = twitter_config(&mock_server.uri(), 10);
+        let db = db_config("exchange-failure");
+
+        let (result, _, (status, _)) = run_flow_with_redirect(&twitter, &db, |authorize_params| {
+            vec![
+                (String::from("code"), String::from("some-auth-code")),
+                (
+                    String::from("state"),
+                    authorize_params.get("state").unwrap().clone(),
+                ),
+            ]
+        })
+        .await;
+
+        assert!(matches!(result, Err(Error::TokenExchange(_))), "{result:?}");
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_flow_should_time_out_without_redirect() {
+        let mock_server = MockServer::start().await;
+
+        let twitter = twitter_config(&mock_server.uri(), 1);
+        let db = db_config("timeout");
+
+        let result = run_flow(&twitter, &db, |_| ()).await;
+
+        assert!(matches!(result, Err(Error::Timeout(1))), "{result:?}");
+    }
 }
@@ -0,0 +1,119 @@
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+
+use crate::commons::auth::encrypted_token_db::{EncryptedTokenDB, TokenCipher};
+use crate::commons::auth::token_db::{SqliteTokenDB, TokenDB};
+use crate::config::Config;
+use crate::social::Network;
+use crate::IwtError;
+
+#[derive(clap::Subcommand)]
+pub enum AuthSubcommand {
+    /// Show the expiry and scopes of the stored tokens
+    Status,
+    /// Encrypt the tokens that are stored in plaintext using the configured db.token_key
+    EncryptTokens,
+}
+
+pub fn execute(
+    command: &AuthSubcommand,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        AuthSubcommand::Status => status(config),
+        AuthSubcommand::EncryptTokens => encrypt_tokens(config),
+    }
+}
+
+fn status(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    // The metadata is not encrypted, the key is not needed here
+    let token_db = SqliteTokenDB::new(Rc::new(Connection::open(&config.db.path)?));
+    token_db.init_table()?;
+
+    let stored = token_db.social_networks()?;
+
+    for social_network in &stored {
+        let metadata = token_db.get_metadata(social_network)?;
+        println!("{social_network}:");
+        println!(
+            "  expires: {}",
+            describe_expiry(metadata.expires_at, Utc::now())
+        );
+        println!(
+            "  scopes:  {}",
+            if metadata.scopes.is_empty() {
+                String::from("unknown")
+            } else {
+                metadata.scopes.join(" ")
+            }
+        );
+    }
+
+    if config.twitter.is_some() && !stored.contains(&Network::Twitter) {
+        println!("twitter:\n  no tokens stored, run `iwt app-auth twitter`");
+    }
+
+    if config.mastodon.is_some() {
+        println!("mastodon:\n  access token is read from the config");
+    }
+
+    Ok(())
+}
+
+fn describe_expiry(expires_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> String {
+    match expires_at {
+        None => String::from("unknown"),
+        Some(expires_at) if expires_at <= now => {
+            format!("{} (expired)", expires_at.format("%Y-%m-%d %H:%M:%S UTC"))
+        }
+        Some(expires_at) => {
+            let remaining = expires_at - now;
+            format!(
+                "{} (in {}h {}m)",
+                expires_at.format("%Y-%m-%d %H:%M:%S UTC"),
+                remaining.num_hours(),
+                remaining.num_minutes() % 60
+            )
+        }
+    }
+}
+
+fn encrypt_tokens(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let token_key = config.db.token_key.as_ref().ok_or_else(|| {
+        IwtError::new("db.token_key is not configured, there is no key to encrypt the tokens with")
+    })?;
+
+    let token_db = SqliteTokenDB::new(Rc::new(Connection::open(&config.db.path)?));
+    token_db.init_table()?;
+
+    let updated = EncryptedTokenDB::new(token_db, TokenCipher::new(token_key.secret())?)
+        .encrypt_plaintext_tokens()?;
+
+    log::info!("Encrypted the tokens of {updated} social network(s)");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::describe_expiry;
+
+    #[test]
+    fn test_describe_expiry() {
+        let now = Utc.with_ymd_and_hms(2023, 1, 2, 10, 0, 0).unwrap();
+
+        assert_eq!(describe_expiry(None, now), "unknown");
+        assert_eq!(
+            describe_expiry(Some(now + Duration::minutes(90)), now),
+            "2023-01-02 11:30:00 UTC (in 1h 30m)"
+        );
+        assert_eq!(
+            describe_expiry(Some(now - Duration::minutes(1)), now),
+            "2023-01-02 09:59:00 UTC (expired)"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::Display;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use oauth2::{AccessToken, RefreshToken};
+
+use crate::social::Network;
+
+use super::token_db::{SqliteTokenDB, TokenDB, TokenMetadata};
+
+/// Marks the encrypted values, the version allows changing the format later
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+const NONCE_LENGTH: usize = 24;
+
+#[derive(Debug)]
+pub struct EncryptionError {
+    pub message: String,
+}
+
+impl EncryptionError {
+    fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for EncryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncryptionError: {}", self.message)
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+/// Encrypts tokens using XChaCha20-Poly1305 with a random nonce per value
+pub struct TokenCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl TokenCipher {
+    /// Creates a cipher from a base64 encoded 32 byte key, e.g. the output of
+    /// `openssl rand -base64 32`
+    pub fn new(base64_key: &str) -> Result<Self, EncryptionError> {
+        let key = base64::decode(base64_key.trim())
+            .map_err(|err| EncryptionError::new(&format!("Token key is not base64: {err}")))?;
+
+        XChaCha20Poly1305::new_from_slice(&key)
+            .map(|cipher| Self { cipher })
+            .map_err(|_| EncryptionError::new("Token key must be 32 bytes long"))
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, EncryptionError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| EncryptionError::new("Couldn't encrypt token"))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+
+        Ok(format!("{ENCRYPTED_PREFIX}{}", base64::encode(payload)))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, EncryptionError> {
+        let payload = value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or_else(|| EncryptionError::new("Token is not encrypted"))
+            .and_then(|encoded| {
+                base64::decode(encoded).map_err(|err| {
+                    EncryptionError::new(&format!("Encrypted token is not base64: {err}"))
+                })
+            })?;
+
+        if payload.len() < NONCE_LENGTH {
+            return Err(EncryptionError::new("Encrypted token is too short"));
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LENGTH);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| EncryptionError::new("Couldn't decrypt token, is the token key correct?"))
+            .and_then(|plaintext| {
+                String::from_utf8(plaintext)
+                    .map_err(|_| EncryptionError::new("Decrypted token is not valid UTF-8"))
+            })
+    }
+
+    #[must_use]
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+}
+
+/// Token DB that encrypts the tokens before passing them to the underlying DB
+pub struct EncryptedTokenDB<DB: TokenDB> {
+    db: DB,
+    cipher: TokenCipher,
+}
+
+impl<DB: TokenDB> EncryptedTokenDB<DB> {
+    pub fn new(db: DB, cipher: TokenCipher) -> Self {
+        Self { db, cipher }
+    }
+
+    fn decrypt(&self, social_network: &Network, value: &str) -> Result<String, EncryptionError> {
+        if !TokenCipher::is_encrypted(value) {
+            log::warn!(
+                "{social_network} token is stored in plaintext, run `iwt auth encrypt-tokens` to encrypt it"
+            );
+        }
+        self.plaintext(value)
+    }
+
+    fn plaintext(&self, value: &str) -> Result<String, EncryptionError> {
+        if TokenCipher::is_encrypted(value) {
+            self.cipher.decrypt(value)
+        } else {
+            Ok(value.to_string())
+        }
+    }
+}
+
+impl EncryptedTokenDB<SqliteTokenDB> {
+    /// Encrypts the tokens that are still stored in plaintext, returns the number of updated rows
+    pub fn encrypt_plaintext_tokens(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut updated = 0;
+
+        for social_network in self.db.social_networks()? {
+            let access_token = self.db.get_access_token(&social_network)?;
+            let refresh_token = self.db.get_refresh_token(&social_network)?;
+
+            if !TokenCipher::is_encrypted(access_token.secret())
+                || !TokenCipher::is_encrypted(refresh_token.secret())
+            {
+                log::info!("Encrypting {social_network} tokens");
+                self.store(
+                    &social_network,
+                    &AccessToken::new(self.plaintext(access_token.secret())?),
+                    &RefreshToken::new(self.plaintext(refresh_token.secret())?),
+                    &self.db.get_metadata(&social_network)?,
+                )?;
+                updated += 1;
+            }
+        }
+
+        Ok(updated)
+    }
+}
+
+impl<DB: TokenDB> TokenDB for EncryptedTokenDB<DB> {
+    fn get_access_token(
+        &self,
+        social_network: &Network,
+    ) -> Result<AccessToken, Box<dyn std::error::Error>> {
+        let access_token = self.db.get_access_token(social_network)?;
+        Ok(AccessToken::new(
+            self.decrypt(social_network, access_token.secret())?,
+        ))
+    }
+
+    fn get_refresh_token(
+        &self,
+        social_network: &Network,
+    ) -> Result<RefreshToken, Box<dyn std::error::Error>> {
+        let refresh_token = self.db.get_refresh_token(social_network)?;
+        Ok(RefreshToken::new(
+            self.decrypt(social_network, refresh_token.secret())?,
+        ))
+    }
+
+    fn get_metadata(
+        &self,
+        social_network: &Network,
+    ) -> Result<TokenMetadata, Box<dyn std::error::Error>> {
+        self.db.get_metadata(social_network)
+    }
+
+    fn store(
+        &self,
+        social_network: &Network,
+        access_token: &AccessToken,
+        refresh_token: &RefreshToken,
+        metadata: &TokenMetadata,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.db.store(
+            social_network,
+            &AccessToken::new(self.cipher.encrypt(access_token.secret())?),
+            &RefreshToken::new(self.cipher.encrypt(refresh_token.secret())?),
+            metadata,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use oauth2::{AccessToken, RefreshToken};
+    use rusqlite::Connection;
+
+    use crate::commons::auth::token_db::{SqliteTokenDB, TokenDB, TokenMetadata};
+    use crate::social::Network;
+
+    use super::{EncryptedTokenDB, TokenCipher};
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    fn sqlite_token_db() -> SqliteTokenDB {
+        let token_db = SqliteTokenDB::new(Rc::new(Connection::open_in_memory().unwrap()));
+        token_db.init_table().unwrap();
+        token_db
+    }
+
+    #[test]
+    fn test_cipher_should_decrypt_encrypted_values() {
+        let cipher = TokenCipher::new(KEY).unwrap();
+
+        let encrypted = cipher.encrypt("some-token").unwrap();
+
+        assert!(TokenCipher::is_encrypted(&encrypted));
+        assert!(!encrypted.contains("some-token"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "some-token");
+    }
+
+    #[test]
+    fn test_cipher_should_fail_with_wrong_key() {
+        let encrypted = TokenCipher::new(KEY)
+            .unwrap()
+            .encrypt("some-token")
+            .unwrap();
+
+        let other_cipher =
+            TokenCipher::new("ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=").unwrap();
+
+        assert!(other_cipher.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_cipher_should_reject_short_keys() {
+        assert!(TokenCipher::new("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn test_tokens_should_be_encrypted_at_rest() {
+        let conn = Rc::new(Connection::open_in_memory().unwrap());
+        let plain_db = SqliteTokenDB::new(Rc::clone(&conn));
+        plain_db.init_table().unwrap();
+        let encrypted_db =
+            EncryptedTokenDB::new(SqliteTokenDB::new(conn), TokenCipher::new(KEY).unwrap());
+
+        encrypted_db
+            .store(
+                &Network::Twitter,
+                &AccessToken::new(String::from("access-token")),
+                &RefreshToken::new(String::from("refresh-token")),
+                &TokenMetadata::default(),
+            )
+            .unwrap();
+
+        let stored = plain_db.get_access_token(&Network::Twitter).unwrap();
+        assert!(TokenCipher::is_encrypted(stored.secret()));
+
+        assert_eq!(
+            encrypted_db
+                .get_access_token(&Network::Twitter)
+                .unwrap()
+                .secret(),
+            "access-token"
+        );
+        assert_eq!(
+            encrypted_db
+                .get_refresh_token(&Network::Twitter)
+                .unwrap()
+                .secret(),
+            "refresh-token"
+        );
+    }
+
+    #[test]
+    fn test_plaintext_tokens_should_be_migrated() {
+        let plain_db = sqlite_token_db();
+        plain_db
+            .store(
+                &Network::Twitter,
+                &AccessToken::new(String::from("access-token")),
+                &RefreshToken::new(String::from("refresh-token")),
+                &TokenMetadata::default(),
+            )
+            .unwrap();
+        let encrypted_db = EncryptedTokenDB::new(plain_db, TokenCipher::new(KEY).unwrap());
+
+        assert_eq!(encrypted_db.encrypt_plaintext_tokens().unwrap(), 1);
+        assert_eq!(encrypted_db.encrypt_plaintext_tokens().unwrap(), 0);
+
+        assert!(TokenCipher::is_encrypted(
+            encrypted_db
+                .db
+                .get_refresh_token(&Network::Twitter)
+                .unwrap()
+                .secret()
+        ));
+        assert_eq!(
+            encrypted_db
+                .get_refresh_token(&Network::Twitter)
+                .unwrap()
+                .secret(),
+            "refresh-token"
+        );
+    }
+}
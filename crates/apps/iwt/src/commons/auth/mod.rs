@@ -1,2 +1,3 @@
+pub mod encrypted_token_db;
 pub mod oauth;
 pub mod token_db;
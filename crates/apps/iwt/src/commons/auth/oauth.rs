@@ -1,22 +1,37 @@
 use std::rc::Rc;
 
 use async_mutex::Mutex;
+use chrono::{Duration, Utc};
 
+use crate::commons::redact;
 use crate::social::Network;
+use crate::IwtError;
 
-use super::token_db::TokenDB;
+use super::token_db::{TokenDB, TokenMetadata};
 use oauth2::{
     basic::BasicClient, http::HeaderValue, reqwest::async_http_client, AccessToken, RefreshToken,
     TokenResponse,
 };
-use reqwest::{header::AUTHORIZATION, Client, Request, Response, StatusCode};
+use reqwest::{header::AUTHORIZATION, Client, Method, Request, Response, StatusCode};
+
+/// Access tokens are refreshed when they expire within this period
+const REFRESH_MARGIN_SECONDS: i64 = 60;
 
 struct TokenCredentials {
     access_token: AccessToken,
     refresh_token: RefreshToken,
+    metadata: TokenMetadata,
+}
+
+impl TokenCredentials {
+    fn expires_soon(&self) -> bool {
+        self.metadata.expires_at.is_some_and(|expires_at| {
+            expires_at - Duration::seconds(REFRESH_MARGIN_SECONDS) <= Utc::now()
+        })
+    }
 }
 
-pub struct AuthedClient<DB: TokenDB> {
+pub struct AuthedClient<DB: TokenDB + ?Sized> {
     oauth_client: BasicClient,
     db: Rc<DB>,
     social_network: Network,
@@ -25,15 +40,24 @@ pub struct AuthedClient<DB: TokenDB> {
     tokens: Mutex<TokenCredentials>,
 }
 
-impl<DB: TokenDB> AuthedClient<DB> {
-    pub fn new(social_network: Network, oauth_client: BasicClient, db: Rc<DB>) -> Self {
-        let access_token = db
-            .get_access_token(&social_network)
-            .expect("Couldn't load access token");
-        let refresh_token = db
-            .get_refresh_token(&social_network)
-            .expect("Couldn't load refresh token");
-        Self {
+impl<DB: TokenDB + ?Sized> AuthedClient<DB> {
+    pub fn new(
+        social_network: Network,
+        oauth_client: BasicClient,
+        db: Rc<DB>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let access_token = db.get_access_token(&social_network).map_err(|err| {
+            IwtError::new(&format!(
+                "Couldn't load {social_network} access token, run `iwt app-auth {social_network}` first: {err}"
+            ))
+        })?;
+        let refresh_token = db.get_refresh_token(&social_network).map_err(|err| {
+            IwtError::new(&format!(
+                "Couldn't load {social_network} refresh token, run `iwt app-auth {social_network}` first: {err}"
+            ))
+        })?;
+        let metadata = db.get_metadata(&social_network)?;
+        Ok(Self {
             oauth_client,
             db,
             social_network,
@@ -41,8 +65,9 @@ impl<DB: TokenDB> AuthedClient<DB> {
             tokens: Mutex::new(TokenCredentials {
                 access_token,
                 refresh_token,
+                metadata,
             }),
-        }
+        })
     }
 
     pub async fn authed_request(
@@ -50,38 +75,61 @@ impl<DB: TokenDB> AuthedClient<DB> {
         mut request: Request,
     ) -> Result<Response, Box<dyn std::error::Error>> {
         {
-            let tokens = self.tokens.lock().await;
+            let mut tokens = self.tokens.lock().await;
+            if tokens.expires_soon() {
+                log::debug!("access token expires soon, refreshing token");
+                *tokens = self.exchange_refresh_token(&tokens).await?;
+            }
             authorize_request(&mut request, &tokens);
         }
 
-        let mut cloned_request = request.try_clone().expect("Request cannot be cloned");
+        // Only the idempotent requests are replayed, a rejected POST is not repeated in case it was
+        // processed anyway
+        let replay = if is_idempotent(request.method()) {
+            Some(request.try_clone().ok_or_else(|| {
+                IwtError::new(&format!(
+                    "{} {} cannot be cloned to be replayed",
+                    request.method(),
+                    request.url()
+                ))
+            })?)
+        } else {
+            None
+        };
 
+        let method = request.method().clone();
+        let url = request.url().clone();
         log::debug!("headers: {:?}", request.headers());
         let response = self.http_client.execute(request).await?;
         log::debug!("response from execue: {:?}", response);
 
-        if response.status() == StatusCode::UNAUTHORIZED {
-            log::debug!("recieved unauthorized response, refresing token");
-            let mut tokens = self.tokens.lock().await;
-            log::debug!("token credentials lock acquired");
-            *tokens = self.exchange_refresh_token(&tokens).await?;
-
-            authorize_request(&mut cloned_request, &tokens);
-            log::debug!(
-                "headers after token refresh: {:?}",
-                cloned_request.headers()
-            );
-            self.http_client
-                .execute(cloned_request)
-                .await
-                .map(|res| {
-                    log::debug!("response: {:?}", res);
-                    res
-                })
-                .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
-        } else {
-            Ok(response)
+        // This is still needed when the expiry of the token is unknown or the token was revoked
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
         }
+
+        log::debug!("recieved unauthorized response, refresing token");
+        let mut tokens = self.tokens.lock().await;
+        log::debug!("token credentials lock acquired");
+        *tokens = self.exchange_refresh_token(&tokens).await?;
+
+        let Some(mut replay) = replay else {
+            return Err(Box::new(IwtError::new(&format!(
+                "{method} {url} was unauthorized, the token is refreshed but the request is not \
+                 replayed"
+            ))));
+        };
+        authorize_request(&mut replay, &tokens);
+        log::debug!("headers after token refresh: {:?}", replay.headers());
+
+        self.http_client
+            .execute(replay)
+            .await
+            .map(|res| {
+                log::debug!("response: {:?}", res);
+                res
+            })
+            .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
     }
 
     async fn exchange_refresh_token(
@@ -108,11 +156,18 @@ impl<DB: TokenDB> AuthedClient<DB> {
                         .refresh_token()
                         .unwrap_or(&tokens.refresh_token)
                         .clone(),
+                    metadata: TokenMetadata::from_token_response(
+                        token_response.expires_in(),
+                        token_response.scopes().map_or_else(
+                            || tokens.metadata.scopes.clone(),
+                            |scopes| scopes.iter().map(|scope| scope.to_string()).collect(),
+                        ),
+                    ),
                 };
 
                 log::debug!(
                     "access token refresh successful, new token: {}",
-                    tokens.access_token.secret()
+                    redact::secret(tokens.access_token.secret())
                 );
 
                 self.db
@@ -120,6 +175,7 @@ impl<DB: TokenDB> AuthedClient<DB> {
                         &self.social_network,
                         &tokens.access_token,
                         &tokens.refresh_token,
+                        &tokens.metadata,
                     )
                     .map(|_| tokens)
             }
@@ -127,20 +183,27 @@ impl<DB: TokenDB> AuthedClient<DB> {
     }
 }
 
+fn is_idempotent(method: &Method) -> bool {
+    [Method::GET, Method::HEAD, Method::PUT, Method::DELETE].contains(method)
+}
+
 fn authorize_request(request: &mut Request, tokens: &TokenCredentials) {
+    let mut authorization =
+        HeaderValue::from_str(&format!("Bearer {}", tokens.access_token.secret())).unwrap();
+    // Sensitive headers are redacted in the debug output of the requests
+    authorization.set_sensitive(!redact::logging_secrets());
+
     request.headers_mut().remove(AUTHORIZATION);
-    request.headers_mut().append(
-        AUTHORIZATION,
-        HeaderValue::from_str(&format!("Bearer {}", tokens.access_token.secret())).unwrap(),
-    );
+    request.headers_mut().append(AUTHORIZATION, authorization);
 }
 
 #[cfg(test)]
 mod test {
     use std::rc::Rc;
 
-    use crate::commons::auth::token_db::TokenDB;
+    use crate::commons::auth::token_db::{TokenDB, TokenMetadata};
     use crate::social::Network;
+    use chrono::{Duration, Utc};
     use oauth2::{basic::BasicClient, AuthUrl, ClientId, TokenUrl};
     use reqwest::{Method, Request, StatusCode, Url};
     use wiremock::{
@@ -163,11 +226,17 @@ mod test {
     }
 
     fn create_authed_client(base_url: &str) -> (Rc<impl TokenDB>, AuthedClient<impl TokenDB>) {
-        let db = StubTokenDB::new();
+        create_authed_client_with_db(base_url, StubTokenDB::new())
+    }
+
+    fn create_authed_client_with_db(
+        base_url: &str,
+        db: StubTokenDB,
+    ) -> (Rc<impl TokenDB>, AuthedClient<impl TokenDB>) {
         let shared_db = Rc::new(db);
         (
             Rc::clone(&shared_db),
-            AuthedClient::new(Network::Twitter, basic_client(base_url), shared_db),
+            AuthedClient::new(Network::Twitter, basic_client(base_url), shared_db).unwrap(),
         )
     }
 
@@ -327,4 +396,129 @@ mod test {
             db.get_refresh_token(&Network::Twitter).unwrap().secret(),
         );
     }
+
+    #[tokio::test]
+    async fn test_post_is_not_replayed_if_response_is_401() {
+        let mock_server = MockServer::start().await;
+
+        let (db, authed_client) = create_authed_client(&mock_server.uri());
+
+        Mock::given(method("POST"))
+            .and(path("/restricted"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(
+                    r#"
+            {
+                "token_type":"bearer",
+                "expires_in":7200,
+                "access_token":"new-access-token",
+                "scope":"some-scope",
+                "refresh_token":"new-refresh-token"
+            }
+            "#
+                    .as_bytes()
+                    .to_owned(),
+                    "application/json",
+                ),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let request = Request::new(
+            Method::POST,
+            Url::parse(format!("{}/restricted", mock_server.uri()).as_str()).unwrap(),
+        );
+
+        let result = authed_client.authed_request(request).await;
+        assert!(result.is_err());
+
+        let requests = mock_server
+            .received_requests()
+            .await
+            .expect("Requests expected");
+
+        // The token was refreshed, but the request wasn't sent again
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].url.path(), "/restricted");
+        assert_eq!(requests[1].url.path(), "/oauth/token");
+        assert_eq!(
+            "new-access-token",
+            db.get_access_token(&Network::Twitter).unwrap().secret(),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_is_refreshed_before_the_request_if_it_expires_soon() {
+        let mock_server = MockServer::start().await;
+
+        let (db, authed_client) = create_authed_client_with_db(
+            &mock_server.uri(),
+            StubTokenDB::with_metadata(TokenMetadata {
+                expires_at: Some(Utc::now() + Duration::seconds(10)),
+                scopes: vec![String::from("some-scope")],
+            }),
+        );
+
+        Mock::given(method("GET"))
+            .and(path("/restricted"))
+            .and(headers("Authorization", vec!["Bearer new-access-token"]))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        Mock::given(method("POST"))
+            .and(path("/oauth/token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(
+                    r#"
+            {
+                "token_type":"bearer",
+                "expires_in":7200,
+                "access_token":"new-access-token",
+                "scope":"tweet.read tweet.write",
+                "refresh_token":"new-refresh-token"
+            }
+            "#
+                    .as_bytes()
+                    .to_owned(),
+                    "application/json",
+                ),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let request = Request::new(
+            Method::GET,
+            Url::parse(format!("{}/restricted", mock_server.uri()).as_str()).unwrap(),
+        );
+
+        let result = authed_client.authed_request(request).await;
+        assert_eq!(result.unwrap().status(), StatusCode::OK);
+
+        let requests = mock_server
+            .received_requests()
+            .await
+            .expect("Requests expected");
+
+        // The token was refreshed first, so there wasn't any unauthorized request
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].url.path(), "/oauth/token");
+        assert_eq!(requests[1].url.path(), "/restricted");
+
+        // The new expiry and scopes are stored
+        let metadata = db.get_metadata(&Network::Twitter).unwrap();
+        let expires_at = metadata.expires_at.unwrap();
+        assert!(expires_at > Utc::now() + Duration::seconds(7100));
+        assert!(expires_at <= Utc::now() + Duration::seconds(7200));
+        assert_eq!(
+            metadata.scopes,
+            vec![String::from("tweet.read"), String::from("tweet.write")]
+        );
+    }
 }
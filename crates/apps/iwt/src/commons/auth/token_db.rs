@@ -1,10 +1,39 @@
 use std::rc::Rc;
 
+use chrono::{DateTime, TimeZone, Utc};
 use oauth2::{AccessToken, RefreshToken};
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 
+use crate::config::Secret;
 use crate::social::Network;
 
+use super::encrypted_token_db::{EncryptedTokenDB, TokenCipher};
+
+/// Non-secret information about the stored tokens
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TokenMetadata {
+    /// When the access token expires, `None` if it is unknown
+    pub expires_at: Option<DateTime<Utc>>,
+    /// The scopes granted to the access token
+    pub scopes: Vec<String>,
+}
+
+impl TokenMetadata {
+    /// Creates the metadata from the `expires_in` and `scope` fields of a token response
+    #[must_use]
+    pub fn from_token_response(
+        expires_in: Option<std::time::Duration>,
+        scopes: Vec<String>,
+    ) -> Self {
+        Self {
+            expires_at: expires_in
+                .and_then(|expires_in| chrono::Duration::from_std(expires_in).ok())
+                .map(|expires_in| Utc::now() + expires_in),
+            scopes,
+        }
+    }
+}
+
 pub trait TokenDB {
     fn get_access_token(
         &self,
@@ -14,11 +43,16 @@ pub trait TokenDB {
         &self,
         social_network: &Network,
     ) -> Result<RefreshToken, Box<dyn std::error::Error>>;
+    fn get_metadata(
+        &self,
+        social_network: &Network,
+    ) -> Result<TokenMetadata, Box<dyn std::error::Error>>;
     fn store(
         &self,
         social_network: &Network,
         access_token: &AccessToken,
         refresh_token: &RefreshToken,
+        metadata: &TokenMetadata,
     ) -> Result<(), Box<dyn std::error::Error>>;
 }
 
@@ -30,6 +64,70 @@ impl SqliteTokenDB {
     pub fn new(conn: Rc<Connection>) -> Self {
         Self { conn }
     }
+
+    pub fn init_table(&self) -> rusqlite::Result<()> {
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS auth_token (
+                    social_network VARCHAR(20) PRIMARY KEY,
+                    access_token   TEXT,
+                    refresh_token  TEXT
+                )",
+                (),
+            )
+            .map(|_| ())?;
+
+        // Columns added after the table was introduced
+        for (column, definition) in [("expires_at", "INTEGER"), ("scopes", "TEXT")] {
+            let exists = self
+                .conn
+                .query_row(
+                    "SELECT 1 FROM pragma_table_info('auth_token') WHERE name = ?1",
+                    [column],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+
+            if !exists {
+                self.conn.execute(
+                    &format!("ALTER TABLE auth_token ADD COLUMN {column} {definition}"),
+                    (),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The social networks that have tokens stored
+    pub fn social_networks(&self) -> rusqlite::Result<Vec<Network>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT social_network FROM auth_token ORDER BY social_network")?;
+
+        let social_networks = statement
+            .query_map((), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>();
+        social_networks
+    }
+}
+
+/// Opens the token DB, the tokens are encrypted if a token key is configured
+pub fn open(
+    conn: Rc<Connection>,
+    token_key: Option<&Secret>,
+) -> Result<Rc<dyn TokenDB>, Box<dyn std::error::Error>> {
+    let token_db = SqliteTokenDB::new(conn);
+    token_db.init_table()?;
+
+    match token_key {
+        Some(token_key) => Ok(Rc::new(EncryptedTokenDB::new(
+            token_db,
+            TokenCipher::new(token_key.secret())?,
+        ))),
+        None => Ok(Rc::new(token_db)),
+    }
 }
 
 impl TokenDB for SqliteTokenDB {
@@ -59,20 +157,124 @@ impl TokenDB for SqliteTokenDB {
             .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
     }
 
+    fn get_metadata(
+        &self,
+        social_network: &Network,
+    ) -> Result<TokenMetadata, Box<dyn std::error::Error>> {
+        self.conn
+            .query_row(
+                "SELECT expires_at, scopes FROM auth_token WHERE social_network = :social_network",
+                &[(":social_network", social_network.to_string().as_str())],
+                |row| {
+                    let expires_at: Option<i64> = row.get("expires_at")?;
+                    let scopes: Option<String> = row.get("scopes")?;
+                    Ok(TokenMetadata {
+                        expires_at: expires_at
+                            .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single()),
+                        scopes: scopes
+                            .map(|scopes| scopes.split(' ').map(String::from).collect())
+                            .unwrap_or_default(),
+                    })
+                },
+            )
+            .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
+    }
+
     fn store(
         &self,
         social_network: &Network,
         access_token: &AccessToken,
         refresh_token: &RefreshToken,
+        metadata: &TokenMetadata,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        let scopes = Some(metadata.scopes.join(" ")).filter(|scopes| !scopes.is_empty());
+
         self.conn.execute(
-            "INSERT INTO auth_token (social_network, access_token, refresh_token)
-             VALUES (?1, ?2, ?3)
+            "INSERT INTO auth_token (social_network, access_token, refresh_token, expires_at, scopes)
+             VALUES (?1, ?2, ?3, ?4, ?5)
              ON CONFLICT (social_network) 
-                DO UPDATE SET access_token = excluded.access_token, refresh_token = excluded.refresh_token",
-            (social_network.to_string().as_str(), access_token.secret(), refresh_token.secret())
+                DO UPDATE SET access_token = excluded.access_token, refresh_token = excluded.refresh_token,
+                              expires_at = excluded.expires_at, scopes = excluded.scopes",
+            (
+                social_network.to_string().as_str(),
+                access_token.secret(),
+                refresh_token.secret(),
+                metadata.expires_at.map(|expires_at| expires_at.timestamp()),
+                scopes,
+            )
         )
             .map(|_| ())
             .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use chrono::{TimeZone, Utc};
+    use oauth2::{AccessToken, RefreshToken};
+    use rusqlite::Connection;
+
+    use crate::social::Network;
+
+    use super::{SqliteTokenDB, TokenDB, TokenMetadata};
+
+    #[test]
+    fn test_metadata_should_be_stored() {
+        let token_db = SqliteTokenDB::new(Rc::new(Connection::open_in_memory().unwrap()));
+        token_db.init_table().unwrap();
+
+        let metadata = TokenMetadata {
+            expires_at: Utc.timestamp_opt(1_700_000_000, 0).single(),
+            scopes: vec![String::from("tweet.read"), String::from("offline.access")],
+        };
+
+        token_db
+            .store(
+                &Network::Twitter,
+                &AccessToken::new(String::from("access-token")),
+                &RefreshToken::new(String::from("refresh-token")),
+                &metadata,
+            )
+            .unwrap();
+
+        assert_eq!(token_db.get_metadata(&Network::Twitter).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_init_table_should_add_metadata_columns_to_existing_table() {
+        let conn = Rc::new(Connection::open_in_memory().unwrap());
+        conn.execute(
+            "CREATE TABLE auth_token (
+                social_network VARCHAR(20) PRIMARY KEY,
+                access_token   TEXT,
+                refresh_token  TEXT
+            )",
+            (),
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO auth_token VALUES ('twitter', 'access-token', 'refresh-token')",
+            (),
+        )
+        .unwrap();
+
+        let token_db = SqliteTokenDB::new(conn);
+        token_db.init_table().unwrap();
+        // It's idempotent
+        token_db.init_table().unwrap();
+
+        assert_eq!(
+            token_db.get_metadata(&Network::Twitter).unwrap(),
+            TokenMetadata::default()
+        );
+        assert_eq!(
+            token_db
+                .get_access_token(&Network::Twitter)
+                .unwrap()
+                .secret(),
+            "access-token"
+        );
+    }
+}
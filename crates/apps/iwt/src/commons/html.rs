@@ -0,0 +1,465 @@
+use scraper::{ElementRef, Html, Node, Selector};
+use serde_derive::{Deserialize, Serialize};
+use url::Url;
+
+/// Decides what happens with the links of the post body
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkPolicy {
+    /// `text (url)`, or only the url when it is the text of the link
+    #[default]
+    Inline,
+    /// `text[1]`, the urls are listed after the text
+    Footnotes,
+    /// Only the text of the link is kept
+    Drop,
+}
+
+/// Plain text rendering of an HTML post body
+#[derive(Debug, Default, PartialEq)]
+pub struct PlainText {
+    pub text: String,
+    /// Whether the text was cut at the first heading, i.e. the post has more sections
+    pub excerpt: bool,
+    /// Link targets in the order of the `[n]` markers, only used with `LinkPolicy::Footnotes`
+    pub footnotes: Vec<String>,
+}
+
+/// Converts the HTML into text suitable for microblogs. Only the part before the first heading
+/// is kept, relative links are resolved against `base`, when it is given.
+#[must_use]
+pub fn to_plain_text(html: &str, links: LinkPolicy, base: Option<&Url>) -> PlainText {
+    let fragment = Html::parse_fragment(html);
+    let mut renderer = Renderer {
+        links,
+        base,
+        blocks: Vec::new(),
+        inline: String::new(),
+        footnotes: Vec::new(),
+        excerpt: false,
+    };
+
+    renderer.children(fragment.root_element());
+    renderer.flush();
+
+    PlainText {
+        text: renderer.blocks.join("\n\n"),
+        excerpt: renderer.excerpt,
+        footnotes: renderer.footnotes,
+    }
+}
+
+struct Renderer<'a> {
+    links: LinkPolicy,
+    base: Option<&'a Url>,
+    /// Finished paragraphs, lists, quotes, etc.
+    blocks: Vec<String>,
+    /// Text of the current paragraph
+    inline: String,
+    footnotes: Vec<String>,
+    /// Set when the first heading is reached, nothing is rendered after that
+    excerpt: bool,
+}
+
+impl<'a> Renderer<'a> {
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            if self.excerpt {
+                return;
+            }
+
+            match child.value() {
+                Node::Text(text) => push_collapsed(&mut self.inline, text),
+                Node::Element(_) => self.element(ElementRef::wrap(child).unwrap()),
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        match element.value().name() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => self.excerpt = true,
+            "script" | "style" | "img" | "video" | "audio" | "iframe" => {}
+            "br" => self.inline.push('\n'),
+            "hr" => self.flush(),
+            "ul" | "ol" => {
+                self.flush();
+                let list = self.list(element);
+                self.push_block(list);
+            }
+            "blockquote" => {
+                self.flush();
+                let quote = self
+                    .nested_blocks(element)
+                    .join("\n\n")
+                    .lines()
+                    .map(|line| {
+                        if line.is_empty() {
+                            String::from(">")
+                        } else {
+                            format!("> {line}")
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.push_block(quote);
+            }
+            "pre" => {
+                self.flush();
+                let code = element.text().collect::<String>();
+                self.push_block(code.trim_matches('\n').to_string());
+            }
+            "code" => {
+                self.inline.push('`');
+                self.inline.push_str(&element.text().collect::<String>());
+                self.inline.push('`');
+            }
+            "em" | "i" => self.wrapped(element, "_"),
+            "strong" | "b" => self.wrapped(element, "*"),
+            "a" => self.link(element),
+            "p" | "div" | "section" | "article" | "header" | "footer" | "figure" | "figcaption"
+            | "dl" | "dt" | "dd" | "table" | "tr" => {
+                self.flush();
+                self.children(element);
+                self.flush();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    fn wrapped(&mut self, element: ElementRef, marker: &str) {
+        self.inline.push_str(marker);
+        self.children(element);
+        self.inline.push_str(marker);
+    }
+
+    fn link(&mut self, element: ElementRef) {
+        let start = self.inline.len();
+        self.children(element);
+
+        let href = match element.value().attr("href") {
+            Some(href) if !href.starts_with('#') => self.resolve(href),
+            _ => return,
+        };
+        let text = self.inline[start..].trim();
+
+        if text == href || text == href.trim_end_matches('/') {
+            // The text is the url itself
+            return;
+        }
+
+        match self.links {
+            LinkPolicy::Inline => self.inline.push_str(&format!(" ({href})")),
+            LinkPolicy::Footnotes => {
+                self.footnotes.push(href);
+                self.inline.push_str(&format!("[{}]", self.footnotes.len()));
+            }
+            LinkPolicy::Drop => {}
+        }
+    }
+
+    fn resolve(&self, href: &str) -> String {
+        self.base
+            .and_then(|base| base.join(href).ok())
+            .map_or_else(|| href.to_string(), |url| url.to_string())
+    }
+
+    fn list(&mut self, element: ElementRef) -> String {
+        let ordered = element.value().name() == "ol";
+        let mut lines = Vec::new();
+
+        for (i, item) in element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|child| child.value().name() == "li")
+            .enumerate()
+        {
+            let prefix = if ordered {
+                format!("{}. ", i + 1)
+            } else {
+                String::from("- ")
+            };
+            let indent = " ".repeat(prefix.len());
+
+            for (j, line) in self.nested_blocks(item).join("\n").lines().enumerate() {
+                lines.push(if j == 0 {
+                    format!("{prefix}{line}")
+                } else {
+                    format!("{indent}{line}")
+                });
+            }
+
+            if self.excerpt {
+                break;
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// Renders the children of the element as separate blocks, e.g. the paragraphs of a quote
+    fn nested_blocks(&mut self, element: ElementRef) -> Vec<String> {
+        let blocks = std::mem::take(&mut self.blocks);
+        let inline = std::mem::take(&mut self.inline);
+
+        self.children(element);
+        self.flush();
+
+        let nested = std::mem::replace(&mut self.blocks, blocks);
+        self.inline = inline;
+
+        nested
+    }
+
+    /// Closes the current paragraph
+    fn flush(&mut self) {
+        let paragraph = std::mem::take(&mut self.inline)
+            .lines()
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_matches('\n')
+            .to_string();
+
+        self.push_block(paragraph);
+    }
+
+    fn push_block(&mut self, block: String) {
+        if !block.trim().is_empty() {
+            self.blocks.push(block);
+        }
+    }
+}
+
+/// Escapes the text, so it is kept as it is when it is parsed as HTML
+#[must_use]
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The targets of the links of the HTML, e.g. the ones cited in a status
+#[must_use]
+pub fn links(html: &str) -> Vec<String> {
+    hrefs(&Html::parse_fragment(html), "a[href]", None)
+}
+
+/// The `u-syndication` (or `rel="syndication"`) links of the HTML, e.g. of a post or of the page
+/// of its h-entry. Relative links are resolved against `base`, when it is given.
+#[must_use]
+pub fn syndication_links(html: &str, base: Option<&Url>) -> Vec<String> {
+    hrefs(
+        &Html::parse_document(html),
+        ".u-syndication[href], [rel~=\"syndication\"][href]",
+        base,
+    )
+}
+
+fn hrefs(html: &Html, selector: &str, base: Option<&Url>) -> Vec<String> {
+    let selector = Selector::parse(selector).expect("The selector should be valid");
+    let mut hrefs = Vec::new();
+
+    for href in html
+        .select(&selector)
+        .filter_map(|element| element.value().attr("href"))
+        .filter(|href| !href.starts_with('#'))
+    {
+        let href = base
+            .and_then(|base| base.join(href).ok())
+            .map_or_else(|| href.to_string(), |url| url.to_string());
+
+        if !hrefs.contains(&href) {
+            hrefs.push(href);
+        }
+    }
+
+    hrefs
+}
+
+/// Appends the text with its whitespace collapsed to single spaces, like browsers render it
+fn push_collapsed(output: &mut String, text: &str) {
+    for (i, word) in text.split_whitespace().enumerate() {
+        let needs_space = (i > 0 || text.starts_with(char::is_whitespace))
+            && !output.is_empty()
+            && !output.ends_with([' ', '\n']);
+
+        if needs_space {
+            output.push(' ');
+        }
+        output.push_str(word);
+    }
+
+    // The whitespace between inline elements separates their words too
+    if text.ends_with(char::is_whitespace) && !output.is_empty() && !output.ends_with([' ', '\n']) {
+        output.push(' ');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+
+    use super::{escape, links, syndication_links, to_plain_text, LinkPolicy, PlainText};
+
+    fn text(html: &str) -> String {
+        to_plain_text(html, LinkPolicy::Inline, None).text
+    }
+
+    #[test]
+    fn test_escape_should_keep_the_text() {
+        let title = "Fish & chips <3 &amp;";
+
+        assert_eq!(escape(title), "Fish &amp; chips &lt;3 &amp;amp;");
+        assert_eq!(text(&escape(title)), title);
+    }
+
+    #[test]
+    fn test_paragraphs_and_line_breaks() {
+        assert_eq!(
+            text("<p>First\n  paragraph,<br>new line.</p>\n<p>Second &amp; last.</p>"),
+            "First paragraph,\nnew line.\n\nSecond & last."
+        );
+    }
+
+    #[test]
+    fn test_inline_formatting() {
+        assert_eq!(
+            text(
+                "<p>Some <em>emphasis</em>, <strong>strong</strong> and <code>a &lt; b</code>.</p>"
+            ),
+            "Some _emphasis_, *strong* and `a < b`."
+        );
+    }
+
+    #[test]
+    fn test_whitespace_between_inline_elements() {
+        assert_eq!(
+            text(
+                "<p><strong>Note:</strong> <a href=\"https://x.org/\">link</a> <em>a</em> <em>b</em></p>"
+            ),
+            "*Note:* link (https://x.org/) _a_ _b_"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(
+            text("<ul><li>One</li><li>Two<ol><li>Nested</li><li>Other</li></ol></li></ul><p>After</p>"),
+            "- One\n- Two\n  1. Nested\n  2. Other\n\nAfter"
+        );
+    }
+
+    #[test]
+    fn test_blockquotes() {
+        assert_eq!(
+            text("<p>Quote:</p><blockquote><p>First</p><p>Second</p></blockquote>"),
+            "Quote:\n\n> First\n>\n> Second"
+        );
+    }
+
+    #[test]
+    fn test_preformatted_code_is_kept() {
+        assert_eq!(
+            text("<p>Code:</p><pre><code>fn main() {\n    println!();\n}\n</code></pre>"),
+            "Code:\n\nfn main() {\n    println!();\n}"
+        );
+    }
+
+    #[test]
+    fn test_text_is_cut_at_the_first_heading() {
+        assert_eq!(
+            to_plain_text(
+                "<p>Summary</p><h2 id=\"more\">More</h2><p>Details</p>",
+                LinkPolicy::Inline,
+                None
+            ),
+            PlainText {
+                text: String::from("Summary"),
+                excerpt: true,
+                footnotes: Vec::new(),
+            }
+        );
+        assert!(!to_plain_text("<p>Summary</p>", LinkPolicy::Inline, None).excerpt);
+    }
+
+    #[test]
+    fn test_inline_links() {
+        assert_eq!(
+            text("<p>See <a href=\"https://example.com/a\">this</a> and <a href=\"https://example.com/b\">https://example.com/b</a>.</p>"),
+            "See this (https://example.com/a) and https://example.com/b."
+        );
+    }
+
+    #[test]
+    fn test_footnote_links() {
+        assert_eq!(
+            to_plain_text(
+                "<p>See <a href=\"https://example.com/a\">this</a> and <a href=\"/b\">that</a>.</p>",
+                LinkPolicy::Footnotes,
+                Some(&Url::parse("https://example.com/posts/1").unwrap())
+            ),
+            PlainText {
+                text: String::from("See this[1] and that[2]."),
+                excerpt: false,
+                footnotes: vec![
+                    String::from("https://example.com/a"),
+                    String::from("https://example.com/b")
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_dropped_links() {
+        assert_eq!(
+            to_plain_text(
+                "<p>See <a href=\"https://example.com/a\">this</a>.</p>",
+                LinkPolicy::Drop,
+                None
+            )
+            .text,
+            "See this."
+        );
+    }
+
+    #[test]
+    fn test_plain_text_is_kept() {
+        assert_eq!(text("Just some text"), "Just some text");
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(
+            links(
+                r##"<p>New post: <a href="https://example.com/posts/1">https://example.com/posts/1</a>
+                <a href="https://mastodon.social/tags/rust" class="hashtag">#rust</a>
+                <a href="https://example.com/posts/1">again</a> <a href="#top">top</a></p>"##
+            ),
+            vec![
+                "https://example.com/posts/1",
+                "https://mastodon.social/tags/rust"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_syndication_links() {
+        let base = Url::parse("https://example.com/posts/1").unwrap();
+
+        assert_eq!(
+            syndication_links(
+                r#"<html><body><article class="h-entry">
+                <p class="e-content">Hello <a href="https://example.com/other">other</a></p>
+                <a class="u-syndication" href="https://mastodon.social/@someone/1234">Mastodon</a>
+                <a rel="syndication" href="/tweet">Twitter</a>
+                </article></body></html>"#,
+                Some(&base)
+            ),
+            vec![
+                "https://mastodon.social/@someone/1234",
+                "https://example.com/tweet"
+            ]
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use std::{error::Error, fmt::Display};
 
 pub mod auth;
+pub mod html;
 pub mod permashort_link;
+pub mod rate_limit;
+pub mod redact;
+pub mod run_lock;
+pub mod template;
 pub mod text;
 pub mod url_shortener;
 
@@ -0,0 +1,185 @@
+use std::cell::Cell;
+use std::fmt::Display;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{Response, StatusCode};
+
+/// Assumed when a network rejects a request as too many, but doesn't tell when to retry
+const DEFAULT_RETRY_AFTER_SECONDS: i64 = 60;
+
+/// The network doesn't accept more requests until the given time
+#[derive(Debug, PartialEq, Eq)]
+pub struct RateLimited {
+    pub until: DateTime<Utc>,
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "RateLimited: no requests are accepted until {}",
+            self.until
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Tracks the rate limit reported by the responses of a network, so no request is sent while it
+/// is exhausted
+#[derive(Default)]
+pub struct RateLimiter {
+    until: Cell<Option<DateTime<Utc>>>,
+}
+
+impl RateLimiter {
+    /// Fails while the rate limit is exhausted
+    pub fn check(&self) -> Result<(), RateLimited> {
+        match self.until.get() {
+            Some(until) if until > Utc::now() => Err(RateLimited { until }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the rate limit reported by the response, fails when the request was rejected
+    /// because of it
+    pub fn record(&self, response: &Response) -> Result<(), RateLimited> {
+        let until = reset_at(response.status(), response.headers(), Utc::now());
+        self.until.set(until);
+
+        match until {
+            Some(until) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                Err(RateLimited { until })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The time the rate limit resets at, when it is exhausted. `Retry-After` is sent with the
+/// rejected requests, Twitter sends `x-rate-limit-*`, Mastodon sends `x-ratelimit-*` headers
+fn reset_at(status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let retry_after =
+        header("retry-after").and_then(|retry_after| match retry_after.trim().parse::<i64>() {
+            Ok(seconds) => Some(now + Duration::seconds(seconds)),
+            Err(_) => DateTime::parse_from_rfc2822(retry_after)
+                .ok()
+                .map(|at| at.with_timezone(&Utc)),
+        });
+
+    let exhausted = |remaining: &str| remaining.trim().parse::<u64>() == Ok(0);
+
+    let twitter_reset = header("x-rate-limit-remaining")
+        .filter(|remaining| exhausted(remaining))
+        .and(header("x-rate-limit-reset"))
+        .and_then(|reset| reset.trim().parse::<i64>().ok())
+        .and_then(|reset| Utc.timestamp_opt(reset, 0).single());
+
+    let mastodon_reset = header("x-ratelimit-remaining")
+        .filter(|remaining| exhausted(remaining))
+        .and(header("x-ratelimit-reset"))
+        .and_then(|reset| DateTime::parse_from_rfc3339(reset).ok())
+        .map(|reset| reset.with_timezone(&Utc));
+
+    retry_after
+        .or(twitter_reset)
+        .or(mastodon_reset)
+        .or_else(|| {
+            (status == StatusCode::TOO_MANY_REQUESTS)
+                .then(|| now + Duration::seconds(DEFAULT_RETRY_AFTER_SECONDS))
+        })
+        .filter(|until| *until > now)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, Duration, Utc};
+    use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+    use reqwest::StatusCode;
+
+    use super::reset_at;
+
+    fn headers(headers: &[(&'static str, &str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_reset_at_should_honour_retry_after() {
+        assert_eq!(
+            reset_at(
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[("retry-after", "120")]),
+                now()
+            ),
+            Some(now() + Duration::minutes(2))
+        );
+        assert_eq!(
+            reset_at(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &headers(&[("retry-after", "Mon, 01 Jan 2024 11:00:00 GMT")]),
+                now()
+            ),
+            Some(now() + Duration::hours(1))
+        );
+        assert_eq!(
+            reset_at(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), now()),
+            Some(now() + Duration::minutes(1))
+        );
+    }
+
+    #[test]
+    fn test_reset_at_should_honour_exhausted_rate_limits() {
+        let reset = (now() + Duration::minutes(15)).timestamp().to_string();
+
+        assert_eq!(
+            reset_at(
+                StatusCode::CREATED,
+                &headers(&[
+                    ("x-rate-limit-remaining", "0"),
+                    ("x-rate-limit-reset", &reset)
+                ]),
+                now()
+            ),
+            Some(now() + Duration::minutes(15))
+        );
+        assert_eq!(
+            reset_at(
+                StatusCode::OK,
+                &headers(&[
+                    ("x-ratelimit-remaining", "0"),
+                    ("x-ratelimit-reset", "2024-01-01T10:05:00.000Z")
+                ]),
+                now()
+            ),
+            Some(now() + Duration::minutes(5))
+        );
+        assert_eq!(
+            reset_at(
+                StatusCode::OK,
+                &headers(&[
+                    ("x-ratelimit-remaining", "299"),
+                    ("x-ratelimit-reset", "2024-01-01T10:05:00.000Z")
+                ]),
+                now()
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Secrets are only logged when this is explicitly enabled by `--log-secrets`
+static LOG_SECRETS: AtomicBool = AtomicBool::new(false);
+
+pub fn enable_logging_secrets() {
+    LOG_SECRETS.store(true, Ordering::Relaxed);
+}
+
+#[must_use]
+pub fn logging_secrets() -> bool {
+    LOG_SECRETS.load(Ordering::Relaxed)
+}
+
+/// Returns the secret only if logging secrets is enabled, otherwise a placeholder
+#[must_use]
+pub fn secret(secret: &str) -> &str {
+    if logging_secrets() {
+        secret
+    } else {
+        "[redacted]"
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::path::Path;
+use std::rc::Rc;
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+/// Exit code of a run that couldn't take the lock, `EX_TEMPFAIL` of sysexits.h
+pub const LOCKED_EXIT_CODE: i32 = 75;
+
+/// How often a waiting run checks the lock
+const WAIT_INTERVAL_SECONDS: u64 = 5;
+
+/// The run holding a lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub owner: String,
+    pub pid: u32,
+    pub host: String,
+    pub acquired_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Lease {
+    /// The lease is left by a run that is gone: it is expired, or its process doesn't run on this
+    /// host anymore
+    fn is_stale(&self, now: DateTime<Utc>, host: &str) -> bool {
+        self.expires_at <= now || (self.host == host && is_running(self.pid) == Some(false))
+    }
+}
+
+/// Another run holds the lock
+#[derive(Debug)]
+pub struct Locked {
+    pub name: String,
+    pub lease: Lease,
+}
+
+impl Display for Locked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Locked: {} is running as pid {} on {} since {}, the lock expires at {}",
+            self.name,
+            self.lease.pid,
+            self.lease.host,
+            self.lease.acquired_at,
+            self.lease.expires_at
+        )
+    }
+}
+
+impl std::error::Error for Locked {}
+
+/// Lock held in the DB, so only one run of a command works with the DB at a time, even across
+/// hosts sharing the DB. The lease of the lock expires unless it is renewed, so the lock of a
+/// crashed run is taken over eventually
+pub struct RunLock {
+    conn: Rc<Connection>,
+    name: String,
+    owner: String,
+    ttl: Duration,
+}
+
+impl RunLock {
+    pub fn new(conn: Rc<Connection>, name: &str, ttl: Duration) -> Self {
+        Self {
+            conn,
+            name: name.to_string(),
+            owner: format!(
+                "{}@{}/{:016x}",
+                std::process::id(),
+                host(),
+                rand::random::<u64>()
+            ),
+            ttl,
+        }
+    }
+
+    pub fn init_table(&self) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS run_lock (
+              name VARCHAR(64) NOT NULL,
+              owner TEXT NOT NULL,
+              pid INTEGER NOT NULL,
+              host TEXT NOT NULL,
+              acquired_at TEXT NOT NULL,
+              expires_at TEXT NOT NULL,
+
+              PRIMARY KEY (name)
+            )",
+                (),
+            )
+            .map(|_| ())
+    }
+
+    /// Takes the lock, unless another run holds it. Stale leases are taken over
+    pub fn try_acquire(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        let transaction = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate)?;
+
+        if let Some(lease) = find(&transaction, &self.name)? {
+            if !lease.is_stale(now, &host()) {
+                return Err(Box::new(Locked {
+                    name: self.name.clone(),
+                    lease,
+                }));
+            }
+
+            log::warn!(
+                "Taking over the stale lock of {}, held by pid {} on {} since {}",
+                self.name,
+                lease.pid,
+                lease.host,
+                lease.acquired_at
+            );
+        }
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO run_lock (name, owner, pid, host, acquired_at, expires_at)
+             VALUES (:name, :owner, :pid, :host, :acquired_at, :expires_at)",
+            rusqlite::named_params! {
+                ":name": self.name,
+                ":owner": self.owner,
+                ":pid": std::process::id(),
+                ":host": host(),
+                ":acquired_at": now.to_rfc3339(),
+                ":expires_at": (now + self.ttl).to_rfc3339(),
+            },
+        )?;
+
+        Ok(transaction.commit()?)
+    }
+
+    /// Takes the lock, waits for the other run to release it when `wait` is set
+    pub async fn acquire(&self, wait: bool) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            match self.try_acquire() {
+                Err(err) if wait && err.is::<Locked>() => {
+                    log::info!("{err}, waiting");
+                    tokio::time::sleep(std::time::Duration::from_secs(WAIT_INTERVAL_SECONDS)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Extends the lease, fails when the lock was taken over in the meantime
+    pub fn renew(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let renewed = self.conn.execute(
+            "UPDATE run_lock SET expires_at = :expires_at WHERE name = :name AND owner = :owner",
+            rusqlite::named_params! {
+                ":name": self.name,
+                ":owner": self.owner,
+                ":expires_at": (Utc::now() + self.ttl).to_rfc3339(),
+            },
+        )?;
+
+        match find(&self.conn, &self.name)? {
+            Some(_) if renewed == 1 => Ok(()),
+            Some(lease) => Err(Box::new(Locked {
+                name: self.name.clone(),
+                lease,
+            })),
+            None => Err(Box::new(crate::IwtError::new(&format!(
+                "The lock of {} was released by someone else",
+                self.name
+            )))),
+        }
+    }
+
+    /// Runs the future while holding the lock, the lease is renewed until the future completes
+    pub async fn hold_while<T, F>(&self, future: F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    {
+        let renew_interval = (self.ttl / 3)
+            .to_std()
+            .unwrap_or(std::time::Duration::from_secs(60));
+        tokio::pin!(future);
+
+        loop {
+            tokio::select! {
+                result = &mut future => return result,
+                () = tokio::time::sleep(renew_interval) => self.renew()?,
+            }
+        }
+    }
+
+    pub fn release(&self) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute(
+                "DELETE FROM run_lock WHERE name = :name AND owner = :owner",
+                rusqlite::named_params! { ":name": self.name, ":owner": self.owner },
+            )
+            .map(|_| ())
+    }
+
+    /// Releases the lock whoever holds it, e.g. when a crashed run left it behind. Returns the
+    /// released lease
+    pub fn force_release(&self) -> Result<Option<Lease>, rusqlite::Error> {
+        let lease = find(&self.conn, &self.name)?;
+        self.conn.execute(
+            "DELETE FROM run_lock WHERE name = :name",
+            rusqlite::named_params! { ":name": self.name },
+        )?;
+
+        Ok(lease)
+    }
+}
+
+fn find(conn: &Connection, name: &str) -> Result<Option<Lease>, rusqlite::Error> {
+    let parse_date = |index: usize, date: String| {
+        DateTime::parse_from_rfc3339(&date)
+            .map(|date| date.with_timezone(&Utc))
+            .map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    index,
+                    rusqlite::types::Type::Text,
+                    Box::new(err),
+                )
+            })
+    };
+
+    conn.query_row(
+        "SELECT owner, pid, host, acquired_at, expires_at FROM run_lock WHERE name = :name",
+        rusqlite::named_params! { ":name": name },
+        |row| {
+            Ok(Lease {
+                owner: row.get(0)?,
+                pid: row.get(1)?,
+                host: row.get(2)?,
+                acquired_at: parse_date(3, row.get(3)?)?,
+                expires_at: parse_date(4, row.get(4)?)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Name of this host, to tell the processes of this host from the ones of the others
+fn host() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| String::from("localhost"))
+}
+
+/// Whether the process runs, `None` when it can't be told without procfs
+fn is_running(pid: u32) -> Option<bool> {
+    Path::new("/proc/self")
+        .exists()
+        .then(|| Path::new(&format!("/proc/{pid}")).exists())
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use chrono::{Duration, Utc};
+    use rusqlite::Connection;
+
+    use super::{find, host, Lease, Locked, RunLock};
+
+    fn run_lock(conn: &Rc<Connection>, ttl: Duration) -> RunLock {
+        let run_lock = RunLock::new(Rc::clone(conn), "cross-publish", ttl);
+        run_lock.init_table().unwrap();
+        run_lock
+    }
+
+    #[test]
+    fn test_run_lock_should_be_held_by_one_run_at_a_time() {
+        let conn = Rc::new(Connection::open_in_memory().unwrap());
+        let first = run_lock(&conn, Duration::minutes(10));
+        let second = run_lock(&conn, Duration::minutes(10));
+
+        first.try_acquire().unwrap();
+        let err = second.try_acquire().unwrap_err();
+        let locked = err.downcast_ref::<Locked>().unwrap();
+        assert_eq!(locked.lease.pid, std::process::id());
+        assert!(second.renew().is_err());
+
+        first.release().unwrap();
+        second.try_acquire().unwrap();
+        second.renew().unwrap();
+    }
+
+    #[test]
+    fn test_run_lock_should_take_over_the_expired_leases() {
+        let conn = Rc::new(Connection::open_in_memory().unwrap());
+        let crashed = run_lock(&conn, Duration::seconds(-1));
+        let next = run_lock(&conn, Duration::minutes(10));
+
+        crashed.try_acquire().unwrap();
+        next.try_acquire().unwrap();
+
+        assert!(crashed.renew().is_err());
+        assert_eq!(
+            find(&conn, "cross-publish").unwrap().unwrap().owner,
+            next.owner
+        );
+    }
+
+    #[test]
+    fn test_lease_should_be_stale_when_its_process_is_gone() {
+        let now = Utc::now();
+        let lease = |pid: u32, host: &str| Lease {
+            owner: String::from("owner"),
+            pid,
+            host: host.to_string(),
+            acquired_at: now,
+            expires_at: now + Duration::minutes(10),
+        };
+
+        assert!(!lease(std::process::id(), &host()).is_stale(now, &host()));
+        assert!(!lease(u32::MAX, "elsewhere").is_stale(now, &host()));
+        if std::path::Path::new("/proc/self").exists() {
+            assert!(lease(u32::MAX, &host()).is_stale(now, &host()));
+        }
+        assert!(lease(std::process::id(), &host()).is_stale(now + Duration::hours(1), &host()));
+    }
+
+    #[test]
+    fn test_force_release_should_release_the_lock_of_any_run() {
+        let conn = Rc::new(Connection::open_in_memory().unwrap());
+        let holder = run_lock(&conn, Duration::minutes(10));
+        holder.try_acquire().unwrap();
+
+        let released = run_lock(&conn, Duration::minutes(10))
+            .force_release()
+            .unwrap();
+
+        assert_eq!(released.map(|lease| lease.owner), Some(holder.owner));
+        assert_eq!(find(&conn, "cross-publish").unwrap(), None);
+        run_lock(&conn, Duration::minutes(10))
+            .try_acquire()
+            .unwrap();
+    }
+}
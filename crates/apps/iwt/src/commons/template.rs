@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use convert_case::{Case, Casing};
+use minijinja::{Environment, UndefinedBehavior};
+use serde_derive::Serialize;
+
+use super::text::{self, LengthModel};
+
+/// The built-in templates are named after the post kinds, they are used when a network doesn't
+/// set a template
+const BUILTIN_TEMPLATES: [(&str, &str); 3] = [
+    // The text (shortened if needed) with its footnotes, then the hashtags and the citation.
+    // Truncated texts are quoted and link to the post, so do excerpts (posts with more sections)
+    (
+        "note",
+        r#"{% if truncated %}"{{ text }}…"{% else %}{{ text }}{% endif %}{% for url in footnotes %}
+[{{ loop.index }}] {{ url }}{% endfor %}
+{% if hashtags %}{{ hashtags }} {% endif %}{% if truncated or excerpt %}{{ permashort_uri }}{% else %}({{ permashort_citation }}){% endif %}"#,
+    ),
+    // The title and the summary, then the hashtags and the link to the article
+    (
+        "article",
+        r#"{{ title }}{% if text %}: {{ text }}{% if truncated %}…{% endif %}{% endif %}{% for url in footnotes %}
+[{{ loop.index }}] {{ url }}{% endfor %}
+{% if hashtags %}{{ hashtags }} {% endif %}{{ permashort_uri }}"#,
+    ),
+    // The media that is not attached to the status, then the same as a note
+    (
+        "photo",
+        r#"{% for url in media %}{{ url }}
+{% endfor %}{% include "note" %}"#,
+    ),
+];
+
+#[derive(Debug)]
+pub struct TemplateError {
+    pub message: String,
+}
+
+impl TemplateError {
+    fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TemplateError: {}", self.message)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<minijinja::Error> for TemplateError {
+    fn from(err: minijinja::Error) -> Self {
+        Self::new(&err.to_string())
+    }
+}
+
+/// Values of a post that are available in the templates
+#[derive(Debug, Default)]
+pub struct StatusContext {
+    pub title: String,
+    /// The cleaned text of the post
+    pub summary: String,
+    /// Whether the post has more sections than the summary
+    pub excerpt: bool,
+    /// Link targets of the `[n]` markers in the summary
+    pub footnotes: Vec<String>,
+    /// Note, article or photo
+    pub kind: String,
+    /// Urls of the media that should be linked, because it is not attached to the status
+    pub media: Vec<String>,
+    pub tags: Vec<String>,
+    pub content_warning: Option<String>,
+    /// Urls of the responded posts, used when the network can't respond natively
+    pub in_reply_to: Option<String>,
+    pub repost_of: Option<String>,
+    pub like_of: Option<String>,
+    pub permashort_uri: String,
+    pub permashort_citation: String,
+    pub link: String,
+}
+
+#[derive(Serialize)]
+struct TemplateValues<'a> {
+    title: &'a str,
+    summary: &'a str,
+    /// The summary, shortened when the status would be too long
+    text: &'a str,
+    truncated: bool,
+    excerpt: bool,
+    /// Only the footnotes that are referenced in the text
+    footnotes: &'a [String],
+    kind: &'a str,
+    media: &'a [String],
+    tags: &'a [String],
+    hashtags: String,
+    content_warning: Option<&'a str>,
+    in_reply_to: Option<&'a str>,
+    repost_of: Option<&'a str>,
+    like_of: Option<&'a str>,
+    permashort_uri: &'a str,
+    permashort_citation: &'a str,
+    link: &'a str,
+}
+
+/// The built-in and the configured status templates
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl Templates {
+    /// Compiles the templates, the configured ones can override the built-in ones
+    pub fn new(templates: &BTreeMap<String, String>) -> Result<Self, TemplateError> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.add_filter("hashtag", hashtag);
+        for (name, source) in BUILTIN_TEMPLATES {
+            env.add_template(name, source)?;
+        }
+
+        for (name, source) in templates {
+            env.add_template_owned(name.clone(), source.clone())
+                .map_err(|err| TemplateError::new(&format!("templates.{name}: {err}")))?;
+        }
+
+        Ok(Self { env })
+    }
+
+    #[must_use]
+    pub fn contains(&self, name: &str) -> bool {
+        self.env.get_template(name).is_ok()
+    }
+
+    /// Renders the status, the summary is shortened on word boundaries until the status fits
+    /// in the limit
+    pub fn render(
+        &self,
+        name: &str,
+        context: &StatusContext,
+        limit: usize,
+        model: &LengthModel,
+    ) -> Result<String, TemplateError> {
+        let full = self.render_with(name, context, &context.summary, false)?;
+
+        if model.length(&full) <= limit {
+            return Ok(full);
+        }
+
+        let render_truncated = |budget: usize| -> Result<String, TemplateError> {
+            self.render_with(
+                name,
+                context,
+                text::shorten(&context.summary, budget, model),
+                true,
+            )
+        };
+
+        let shortest = render_truncated(0)?;
+        if model.length(&shortest) > limit {
+            return Err(TemplateError::new(&format!(
+                "{name} doesn't fit in {limit} characters even without text"
+            )));
+        }
+
+        // The longest text that fits, the text can be included more than once and it can come
+        // with footnotes, so the budget is searched for
+        let (mut fits, mut too_long) = (0, limit.saturating_sub(model.length(&shortest)) + 1);
+        while too_long - fits > 1 {
+            let budget = (fits + too_long) / 2;
+            if model.length(&render_truncated(budget)?) <= limit {
+                fits = budget;
+            } else {
+                too_long = budget;
+            }
+        }
+
+        if fits == 0 {
+            Ok(shortest)
+        } else {
+            render_truncated(fits)
+        }
+    }
+
+    fn render_with(
+        &self,
+        name: &str,
+        context: &StatusContext,
+        text: &str,
+        truncated: bool,
+    ) -> Result<String, TemplateError> {
+        Ok(self.env.get_template(name)?.render(TemplateValues {
+            title: &context.title,
+            summary: &context.summary,
+            text,
+            truncated,
+            excerpt: context.excerpt,
+            footnotes: &context.footnotes[0..context
+                .footnotes
+                .iter()
+                .enumerate()
+                .take_while(|(i, _)| text.contains(&format!("[{}]", i + 1)))
+                .count()],
+            kind: &context.kind,
+            media: &context.media,
+            tags: &context.tags,
+            hashtags: context
+                .tags
+                .iter()
+                .map(|tag| hashtag(tag.clone()))
+                .collect::<Vec<_>>()
+                .join(" "),
+            content_warning: context.content_warning.as_deref(),
+            in_reply_to: context.in_reply_to.as_deref(),
+            repost_of: context.repost_of.as_deref(),
+            like_of: context.like_of.as_deref(),
+            permashort_uri: &context.permashort_uri,
+            permashort_citation: &context.permashort_citation,
+            link: &context.link,
+        })?)
+    }
+}
+
+/// The tag as it is published, e.g. `#IndieWeb`
+#[allow(clippy::needless_pass_by_value)] // minijinja filters take owned values
+pub fn hashtag(tag: String) -> String {
+    String::from("#") + &tag.to_case(Case::Pascal)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::commons::text::LengthModel;
+
+    use super::{StatusContext, Templates};
+
+    const MASTODON: LengthModel = LengthModel::Mastodon {
+        characters_reserved_per_url: 23,
+    };
+
+    const LOREM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat.";
+
+    fn context(summary: &str, tags: &[&str]) -> StatusContext {
+        StatusContext {
+            title: String::from("Some title"),
+            summary: summary.to_string(),
+            excerpt: false,
+            footnotes: Vec::new(),
+            kind: String::from("note"),
+            media: Vec::new(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            content_warning: None,
+            in_reply_to: None,
+            repost_of: None,
+            like_of: None,
+            permashort_uri: String::from("http://localhost/asdf"),
+            permashort_citation: String::from("localhost asdf"),
+            link: String::from("https://example.com/some-post"),
+        }
+    }
+
+    fn templates(templates: &[(&str, &str)]) -> Templates {
+        Templates::new(
+            &templates
+                .iter()
+                .map(|(name, source)| (name.to_string(), source.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_note_template_should_add_hashtags() {
+        assert_eq!(
+            templates(&[])
+                .render(
+                    "note",
+                    &context("This is some text.", &["some-tag"]),
+                    100,
+                    &MASTODON
+                )
+                .unwrap(),
+            "This is some text.\n#SomeTag (localhost asdf)"
+        );
+    }
+
+    #[test]
+    fn test_note_template_should_link_excerpts() {
+        let context = StatusContext {
+            excerpt: true,
+            ..context("This is some text.", &["some-tag"])
+        };
+
+        assert_eq!(
+            templates(&[])
+                .render("note", &context, 100, &MASTODON)
+                .unwrap(),
+            "This is some text.\n#SomeTag http://localhost/asdf"
+        );
+    }
+
+    #[test]
+    fn test_note_template_should_add_hashtags_when_text_too_long() {
+        assert_eq!(
+            templates(&[])
+                .render("note", &context(LOREM, &["tag"]), 60, &MASTODON)
+                .unwrap(),
+            "\"Lorem ipsum dolor sit amet,…\"\n#Tag http://localhost/asdf"
+        );
+    }
+
+    #[test]
+    fn test_note_template_should_fit_in_the_limit() {
+        let texts = [
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(10),
+            "日本語のテキストです。".repeat(30),
+            "Emoji 👩‍👩‍👧‍👦 and links https://example.com/long/path ".repeat(20),
+        ];
+
+        for text in texts {
+            for model in [LengthModel::Twitter, MASTODON] {
+                let status = templates(&[])
+                    .render("note", &context(&text, &["some-tag", "other"]), 280, &model)
+                    .unwrap();
+
+                assert!(model.length(&status) <= 280, "{model:?}: {status}");
+                assert!(model.length(&status) > 250, "{model:?}: {status}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_configured_templates_should_be_rendered() {
+        let templates = templates(&[(
+            "article",
+            "{{ title }}: {{ text }}{% if truncated %}…{% endif %} {{ link }} {{ tags | map('hashtag') | join(' ') }}",
+        )]);
+
+        assert_eq!(
+            templates
+                .render("article", &context("Short.", &["a-tag"]), 100, &MASTODON)
+                .unwrap(),
+            "Some title: Short. https://example.com/some-post #ATag"
+        );
+        assert_eq!(
+            templates
+                .render("article", &context(LOREM, &["a-tag"]), 60, &MASTODON)
+                .unwrap(),
+            "Some title: Lorem ipsum dolor… https://example.com/some-post #ATag"
+        );
+    }
+
+    #[test]
+    fn test_templates_using_the_text_twice_should_fit() {
+        let templates = templates(&[("twice", "{{ text }} / {{ text }}")]);
+
+        let status = templates
+            .render("twice", &context(LOREM, &[]), 50, &MASTODON)
+            .unwrap();
+
+        assert!(MASTODON.length(&status) <= 50, "{status}");
+        assert!(status.starts_with("Lorem ipsum"), "{status}");
+    }
+
+    #[test]
+    fn test_render_should_fail_when_the_template_does_not_fit() {
+        let templates = templates(&[("long", "{{ link }} {{ link }} {{ text }}")]);
+
+        assert!(templates
+            .render("long", &context(LOREM, &[]), 40, &MASTODON)
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_templates_should_be_rejected() {
+        assert!(Templates::new(&BTreeMap::from([(
+            String::from("broken"),
+            String::from("{% if %}")
+        )]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_unknown_variables_should_fail_the_rendering() {
+        assert!(templates(&[("typo", "{{ txet }}")])
+            .render("typo", &context("Some text", &[]), 100, &MASTODON)
+            .is_err());
+    }
+
+    #[test]
+    fn test_note_template_should_list_the_referenced_footnotes() {
+        let context = StatusContext {
+            footnotes: vec![
+                String::from("https://example.com/a"),
+                String::from("https://example.com/b"),
+            ],
+            ..context("See this[1]. Lorem ipsum dolor sit amet and that[2].", &[])
+        };
+
+        assert_eq!(
+            templates(&[])
+                .render("note", &context, 200, &MASTODON)
+                .unwrap(),
+            "See this[1]. Lorem ipsum dolor sit amet and that[2].\n[1] https://example.com/a\n[2] https://example.com/b\n(localhost asdf)"
+        );
+        assert_eq!(
+            templates(&[])
+                .render("note", &context, 80, &MASTODON)
+                .unwrap(),
+            "\"See this[1]. Lorem ipsum…\"\n[1] https://example.com/a\nhttp://localhost/asdf"
+        );
+    }
+
+    #[test]
+    fn test_article_template() {
+        assert_eq!(
+            templates(&[])
+                .render("article", &context(LOREM, &["a-tag"]), 80, &MASTODON)
+                .unwrap(),
+            "Some title: Lorem ipsum dolor sit amet,…\n#ATag http://localhost/asdf"
+        );
+        assert_eq!(
+            templates(&[])
+                .render("article", &context("", &[]), 80, &MASTODON)
+                .unwrap(),
+            "Some title\nhttp://localhost/asdf"
+        );
+    }
+
+    #[test]
+    fn test_photo_template_should_lead_with_the_media() {
+        let context = StatusContext {
+            media: vec![String::from("https://example.com/photo.jpg")],
+            ..context("Caption", &[])
+        };
+
+        assert_eq!(
+            templates(&[])
+                .render("photo", &context, 100, &MASTODON)
+                .unwrap(),
+            "https://example.com/photo.jpg\nCaption\n(localhost asdf)"
+        );
+    }
+}
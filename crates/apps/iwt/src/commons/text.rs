@@ -1,169 +1,215 @@
-use super::permashort_link::PermashortCitation;
-use convert_case::{Case, Casing};
+use std::sync::OnceLock;
+
 use regex::Regex;
-use scraper::{Html, Selector};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Length of the t.co links in tweets, every URL counts as this long
+pub const TWITTER_URL_LENGTH: usize = 23;
+
+/// Counts the length of a status text the same way as the social network validates it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthModel {
+    /// twitter-text weighted counting: code points in the listed ranges weigh 1, others
+    /// (e.g. CJK characters) and emoji sequences weigh 2, URLs are shortened by t.co
+    Twitter,
+    /// Grapheme clusters, URLs count as `characters_reserved_per_url`
+    Mastodon { characters_reserved_per_url: usize },
+}
 
-#[must_use]
-pub fn shorten(text: &str, limit: usize) -> &str {
-    let words = words(text);
-    let mut len = 0;
-    let mut i = 0;
+/// Code point ranges counted with the light weight by twitter-text
+const TWITTER_LIGHT_RANGES: [(u32, u32); 4] = [(0, 4351), (8192, 8205), (8208, 8223), (8242, 8247)];
+
+const TWITTER_LIGHT_WEIGHT: usize = 1;
+const TWITTER_DEFAULT_WEIGHT: usize = 2;
+
+impl LengthModel {
+    #[must_use]
+    pub fn length(&self, text: &str) -> usize {
+        let mut length = 0;
+        let mut last = 0;
 
-    while i < words.len() && (len + words[i].len() + 1) < limit {
-        len += words[i].len() + usize::from(i != 0);
-        i += 1;
+        for url in url_regex().find_iter(text) {
+            length += self.text_length(&text[last..url.start()]) + self.url_length();
+            last = url.end();
+        }
+
+        length + self.text_length(&text[last..])
     }
-    &text[0..len]
-}
 
-#[must_use]
-pub fn shorten_with_permashort_citation(
-    text: &str,
-    limit: usize,
-    permashort_citation: &PermashortCitation,
-    tags: &[String],
-) -> String {
-    let hash_tags = tags
-        .iter()
-        .map(|tag| String::from("#") + &tag.to_case(Case::Pascal))
-        .collect::<Vec<_>>()
-        .join(" ");
-
-    let (cleaned, short) = clean_description(text);
-
-    let suffix = if short {
-        format!("\n{hash_tags} {}", permashort_citation.to_uri())
-    } else {
-        format!("\n{hash_tags} ({})", permashort_citation.to_string())
-    };
+    fn url_length(&self) -> usize {
+        match self {
+            LengthModel::Twitter => TWITTER_URL_LENGTH,
+            LengthModel::Mastodon {
+                characters_reserved_per_url,
+            } => *characters_reserved_per_url,
+        }
+    }
 
-    let shortened = shorten(&cleaned, limit - suffix.len());
+    fn text_length(&self, text: &str) -> usize {
+        match self {
+            LengthModel::Twitter => text.graphemes(true).map(twitter_weight).sum(),
+            LengthModel::Mastodon { .. } => text.graphemes(true).count(),
+        }
+    }
+}
 
-    if shortened == cleaned {
-        let mut appended = cleaned;
-        appended.push_str(&suffix);
-        appended
-    } else {
-        let shortened = shorten(
-            &cleaned,
-            limit - 23 - 4 - hash_tags.len() - 2, /* Link + space + ellipsis + quuotes + hastags + space around hash_tags*/
-        );
+fn twitter_weight(grapheme: &str) -> usize {
+    let is_emoji = grapheme
+        .chars()
+        .any(|c| c == '\u{200d}' || c == '\u{fe0f}' || u32::from(c) >= 0x1f000);
 
-        format!(
-            "\"{}…\"\n{} {}",
-            shortened,
-            hash_tags,
-            permashort_citation.to_uri()
-        )
+    if is_emoji {
+        // Emoji sequences (flags, skin tones, ZWJ sequences) count as a single emoji
+        TWITTER_DEFAULT_WEIGHT
+    } else {
+        grapheme
+            .chars()
+            .map(|c| {
+                let code_point = u32::from(c);
+                if TWITTER_LIGHT_RANGES
+                    .iter()
+                    .any(|(from, to)| (*from..=*to).contains(&code_point))
+                {
+                    TWITTER_LIGHT_WEIGHT
+                } else {
+                    TWITTER_DEFAULT_WEIGHT
+                }
+            })
+            .sum()
     }
 }
 
-fn words(input: &str) -> Vec<&str> {
-    input.split(' ').collect()
+fn url_regex() -> &'static Regex {
+    static URL_REGEX: OnceLock<Regex> = OnceLock::new();
+    URL_REGEX.get_or_init(|| Regex::new(r#"https?://[^\s]*[^\s.,;:!?)\]'"…]"#).unwrap())
 }
 
+/// Returns the longest prefix of the text that ends on a word boundary and fits in the limit,
+/// falls back to grapheme boundaries if not even the first word fits
 #[must_use]
-pub fn clean_description(description: &str) -> (String, bool) {
-    let re = Regex::new(r"<h[1-6] ").unwrap();
-
-    let summary: String = re.split(description).next().unwrap().to_string();
+pub fn shorten<'a>(text: &'a str, limit: usize, model: &LengthModel) -> &'a str {
+    if model.length(text) <= limit {
+        return text;
+    }
 
-    let shortened = summary != description;
+    let word_ends = text
+        .char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .map(|(i, _)| i)
+        .filter(|i| *i > 0)
+        .collect::<Vec<_>>();
 
-    let str = summary
-        .replace("<li>", "<li>- ")
-        .replace("<code>", "`")
-        .replace("</code>", "`")
-        .replace('\n', " ")
-        .replace("</p> ", "\n\n");
+    let fitting = word_ends.partition_point(|end| model.length(&text[0..*end]) <= limit);
 
-    log::debug!("original desc:\n{}\n", str);
+    if fitting > 0 {
+        return text[0..word_ends[fitting - 1]].trim_end();
+    }
 
-    let fragment = Html::parse_document(&format!("<html>{}</html>", &str));
-    let cleaned = fragment
-        .select(&Selector::parse("html").unwrap())
-        .next()
-        .unwrap()
-        .text()
-        .collect::<Vec<_>>()
-        .join("");
+    let grapheme_ends = text
+        .grapheme_indices(true)
+        .map(|(i, grapheme)| i + grapheme.len())
+        .collect::<Vec<_>>();
 
-    log::debug!("cleaned desc:\n{}\n", cleaned);
+    let fitting = grapheme_ends.partition_point(|end| model.length(&text[0..*end]) <= limit);
 
-    (cleaned, shortened)
+    if fitting > 0 {
+        &text[0..grapheme_ends[fitting - 1]]
+    } else {
+        ""
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::commons::permashort_link::PermashortCitation;
+    use super::{shorten, LengthModel};
 
-    use super::{shorten, shorten_with_permashort_citation};
+    const MASTODON: LengthModel = LengthModel::Mastodon {
+        characters_reserved_per_url: 23,
+    };
 
     #[test]
     fn test_short_returns_same_if_short() {
         let short_text = "This is some text.";
-        assert_eq!(shorten(short_text, 100), short_text);
+        assert_eq!(shorten(short_text, 100, &MASTODON), short_text);
     }
 
     #[test]
     fn test_shorten_returns_shortened_sentence_limit_on_dot() {
         let text = "This is some text. Looooong word.";
-        assert_eq!(shorten(text, 18), "This is some");
+        assert_eq!(shorten(text, 18, &MASTODON), "This is some text.");
     }
 
     #[test]
     fn test_shorten_returns_shortened_sentence_limit_after_dot() {
         let text = "This is some text. Looooong word.";
-        assert_eq!(shorten(text, 19), "This is some text.");
+        assert_eq!(shorten(text, 19, &MASTODON), "This is some text.");
+    }
+
+    #[test]
+    fn test_shorten_limit_is_inclusive() {
+        let text = "This is some text. Looooong word.";
+        assert_eq!(MASTODON.length("This is some text."), 18);
+        assert_eq!(shorten(text, 18, &MASTODON), "This is some text.");
+        assert_eq!(shorten(text, 17, &MASTODON), "This is some");
     }
 
     #[test]
     fn test_shorten_returns_shortened_sentence_limit_with_ellipsis() {
         let text = "This is some text. Looooong word.";
-        assert_eq!(shorten(text, 21), "This is some text.");
+        assert_eq!(shorten(text, 21, &MASTODON), "This is some text.");
     }
 
     #[test]
     fn test_shorten_returns_shortened_sentence_limit_with_ellipsis_longer() {
         let text = "This is some text. Looooong word.";
-        assert_eq!(shorten(text, 23), "This is some text.");
+        assert_eq!(shorten(text, 23, &MASTODON), "This is some text.");
     }
 
     #[test]
-    fn test_shorten_with_permashort_citation_should_add_hashtags() {
-        let short_text = "This is some text.";
-        let permashort_citation = PermashortCitation::new(
-            "http".to_string(),
-            "localhost".to_string(),
-            "asdf".to_string(),
-        );
+    fn test_shorten_counts_characters_instead_of_bytes() {
+        let text = "Árvíztűrő tükörfúrógép és még több szöveg";
+        assert_eq!(shorten(text, 25, &MASTODON), "Árvíztűrő tükörfúrógép és");
+    }
+
+    #[test]
+    fn test_shorten_falls_back_to_graphemes_without_spaces() {
+        let text = "日本語のテキストです";
+        assert_eq!(shorten(text, 6, &LengthModel::Twitter), "日本語");
+        assert_eq!(shorten(text, 7, &LengthModel::Twitter), "日本語");
+        assert_eq!(shorten(text, 3, &MASTODON), "日本語");
+    }
+
+    #[test]
+    fn test_twitter_length_is_weighted() {
+        assert_eq!(LengthModel::Twitter.length("Hello world"), 11);
+        assert_eq!(LengthModel::Twitter.length("Árvíztűrő"), 9);
+        assert_eq!(LengthModel::Twitter.length("日本語"), 6);
+        assert_eq!(LengthModel::Twitter.length("…"), 2);
+        assert_eq!(LengthModel::Twitter.length("👍"), 2);
+        assert_eq!(LengthModel::Twitter.length("👩‍👩‍👧‍👦"), 2);
+        assert_eq!(LengthModel::Twitter.length("🇭🇺"), 2);
+    }
+
+    #[test]
+    fn test_twitter_length_counts_urls_as_tco_links() {
         assert_eq!(
-            shorten_with_permashort_citation(
-                short_text,
-                100,
-                &permashort_citation,
-                &["some-tag".to_string()]
-            ),
-            short_text.to_string() + "\n#SomeTag (localhost asdf)"
+            LengthModel::Twitter.length("see https://example.com/a/very/long/path?with=query."),
+            4 + 23 + 1
         );
+        assert_eq!(LengthModel::Twitter.length("http://a.b"), 23);
     }
 
     #[test]
-    fn test_shorten_with_permashort_citation_should_add_hashtags_when_text_too_long() {
-        let short_text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat.";
-        let permashort_citation = PermashortCitation::new(
-            "http".to_string(),
-            "localhost".to_string(),
-            "asdf".to_string(),
-        );
+    fn test_mastodon_length_counts_graphemes_and_urls() {
+        assert_eq!(MASTODON.length("e\u{301}"), 1);
+        assert_eq!(MASTODON.length("👩‍👩‍👧‍👦 日本語"), 5);
+        assert_eq!(MASTODON.length("https://example.com/a/very/long/path"), 23);
         assert_eq!(
-            shorten_with_permashort_citation(
-                short_text,
-                60,
-                &permashort_citation,
-                &["tag".to_string()]
-            ),
-            "\"Lorem ipsum dolor sit…\"\n#Tag http://localhost/asdf"
+            LengthModel::Mastodon {
+                characters_reserved_per_url: 30
+            }
+            .length("link: https://x.y"),
+            6 + 30
         );
     }
 }
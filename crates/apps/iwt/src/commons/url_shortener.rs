@@ -29,7 +29,11 @@ impl std::error::Error for ClientError {}
 
 #[async_trait(?Send)]
 pub trait Client {
+    /// Shortens the uri, a new short link is created unless the uri has one already
     async fn put_uri(&self, uri: &str) -> Result<PermashortCitation, ClientError>;
+    /// Looks up the short link of the uri without creating it, e.g. for dry runs and for finding
+    /// the statuses that cite the uri
+    async fn get_uri(&self, uri: &str) -> Result<Option<PermashortCitation>, ClientError>;
 }
 
 pub struct ReqwestClient {
@@ -51,6 +55,14 @@ impl ReqwestClient {
             client: reqwest::Client::new(),
         }
     }
+
+    fn citation(&self, short: &str) -> PermashortCitation {
+        PermashortCitation::new(
+            self.protocol.clone(),
+            self.domain.clone(),
+            format!("s/{short}"),
+        )
+    }
 }
 
 #[async_trait(?Send)]
@@ -63,16 +75,120 @@ impl Client for ReqwestClient {
             .await?;
 
         if response.status().is_success() {
-            let short = response.text().await?;
-            Ok(PermashortCitation::new(
-                self.protocol.clone(),
-                self.domain.clone(),
-                format!("s/{short}"),
-            ))
+            Ok(self.citation(&response.text().await?))
         } else {
             Err(ClientError {
                 message: format!("Unexpected status: {}", response.status()),
             })
         }
     }
+
+    async fn get_uri(&self, uri: &str) -> Result<Option<PermashortCitation>, ClientError> {
+        let response = self
+            .client
+            .get(format!("{}/u/{}", self.base_uri, urlencoding::encode(uri)))
+            .send()
+            .await?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            Ok(None)
+        } else if response.status().is_success() {
+            Ok(Some(self.citation(&response.text().await?)))
+        } else {
+            Err(ClientError {
+                message: format!("Unexpected status: {}", response.status()),
+            })
+        }
+    }
+}
+
+/// Used when no url shortener is configured, the citation points to the original uri
+pub struct NoopClient;
+
+#[async_trait(?Send)]
+impl Client for NoopClient {
+    async fn put_uri(&self, uri: &str) -> Result<PermashortCitation, ClientError> {
+        let url = url::Url::parse(uri).map_err(|err| ClientError {
+            message: format!("Invalid uri {uri}: {err}"),
+        })?;
+
+        let short_url = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+
+        Ok(PermashortCitation::new(
+            url.scheme().to_string(),
+            url[url::Position::BeforeHost..url::Position::BeforePath].to_string(),
+            short_url.trim_start_matches('/').to_string(),
+        ))
+    }
+
+    async fn get_uri(&self, uri: &str) -> Result<Option<PermashortCitation>, ClientError> {
+        self.put_uri(uri).await.map(Some)
+    }
+}
+
+/// Cites a placeholder short link on the domain of the url shortener without contacting it, e.g.
+/// for previews
+pub struct PlaceholderClient {
+    protocol: String,
+    domain: String,
+}
+
+impl PlaceholderClient {
+    #[must_use]
+    pub fn new(protocol: &str, domain: &str) -> Self {
+        Self {
+            protocol: protocol.to_owned(),
+            domain: domain.to_owned(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Client for PlaceholderClient {
+    async fn put_uri(&self, _uri: &str) -> Result<PermashortCitation, ClientError> {
+        Ok(PermashortCitation::new(
+            self.protocol.clone(),
+            self.domain.clone(),
+            String::from("s/xxxx"),
+        ))
+    }
+
+    async fn get_uri(&self, uri: &str) -> Result<Option<PermashortCitation>, ClientError> {
+        self.put_uri(uri).await.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Client, NoopClient, PlaceholderClient};
+
+    #[tokio::test]
+    async fn test_noop_client_should_cite_the_original_uri() {
+        let citation = NoopClient
+            .put_uri("https://example.com:8080/posts/some-post?foo=bar")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            citation.to_uri().as_str(),
+            "https://example.com:8080/posts/some-post?foo=bar"
+        );
+        assert_eq!(
+            citation.to_string().as_str(),
+            "example.com:8080 posts/some-post?foo=bar"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_placeholder_client_should_cite_the_domain_of_the_shortener() {
+        let citation = PlaceholderClient::new("https", "vdx.hu")
+            .put_uri("https://example.com/posts/some-post")
+            .await
+            .unwrap();
+
+        assert_eq!(citation.to_uri().as_str(), "https://vdx.hu/s/xxxx");
+    }
 }
@@ -0,0 +1,266 @@
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::value::{Dict, Value};
+use figment::Figment;
+
+use super::Config;
+
+/// Prefix of the environment variables overriding the config, nested keys are separated by `__`,
+/// e.g. `IWT_MASTODON__BASE_URI`
+pub const ENV_PREFIX: &str = "IWT_";
+
+/// Used when the config file is not given explicitly, it is skipped if it doesn't exist
+const DEFAULT_FILE: &str = "indieweb.toml";
+
+/// Keys with this suffix point to a file containing the value of the key without the suffix,
+/// e.g. `access_token_file = "/run/credentials/iwt/mastodon"`
+const SECRET_FILE_SUFFIX: &str = "_file";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    FileNotFound(String),
+    SecretFile {
+        key: String,
+        path: String,
+        error: std::io::Error,
+    },
+    Deserialization(Box<figment::Error>),
+    Invalid(Vec<String>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::FileNotFound(file_name) => {
+                write!(f, "ConfigError: cannot find file: {file_name}")
+            }
+            ConfigError::SecretFile { key, path, error } => {
+                write!(f, "ConfigError: cannot read {key} from {path}: {error}")
+            }
+            ConfigError::Deserialization(error) => write!(f, "ConfigError: {error}"),
+            ConfigError::Invalid(problems) => {
+                write!(f, "ConfigError: invalid config:\n{}", problems.join("\n"))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<figment::Error> for ConfigError {
+    fn from(error: figment::Error) -> Self {
+        ConfigError::Deserialization(Box::new(error))
+    }
+}
+
+/// Merges the layers of the config, the later ones override the earlier ones: defaults, TOML file
+/// and environment variables. The secret files only fill the keys that are not given directly, so
+/// `access_token` wins over `access_token_file`, wherever each of them is set
+pub fn load(file_name: Option<&str>) -> Result<Config, ConfigError> {
+    let figment = match file_name {
+        Some(file_name) if !Path::new(file_name).exists() => {
+            return Err(ConfigError::FileNotFound(file_name.to_string()))
+        }
+        Some(file_name) => Figment::new().merge(Toml::file(file_name)),
+        None => Figment::new().merge(Toml::file(DEFAULT_FILE)),
+    }
+    .merge(Env::prefixed(ENV_PREFIX).split("__"));
+
+    let secrets = read_secret_files(&figment)?;
+
+    secrets
+        .into_iter()
+        .fold(figment, |figment, (key, secret)| {
+            figment.merge(Serialized::default(&key, secret))
+        })
+        .extract()
+        .map_err(ConfigError::from)
+}
+
+/// Reads the files referenced by the `*_file` keys, unless the value is given directly
+fn read_secret_files(figment: &Figment) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut file_keys = Vec::new();
+    if let Ok(Value::Dict(_, dict)) = figment.extract::<Value>() {
+        collect_file_keys("", &dict, &mut file_keys);
+    }
+
+    file_keys
+        .into_iter()
+        .filter(|(key, _)| !figment.contains(key))
+        .map(|(key, path)| {
+            fs::read_to_string(&path)
+                .map(|secret| {
+                    (
+                        key.clone(),
+                        secret.trim_end_matches(['\r', '\n']).to_string(),
+                    )
+                })
+                .map_err(|error| ConfigError::SecretFile { key, path, error })
+        })
+        .collect()
+}
+
+fn collect_file_keys(prefix: &str, dict: &Dict, file_keys: &mut Vec<(String, String)>) {
+    for (key, value) in dict {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+
+        match value {
+            Value::Dict(_, child) => collect_file_keys(&path, child, file_keys),
+            Value::String(_, file) if key.ends_with(SECRET_FILE_SUFFIX) => file_keys.push((
+                path.trim_end_matches(SECRET_FILE_SUFFIX).to_string(),
+                file.clone(),
+            )),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::result_large_err)] // figment::Jail closures return figment::Error
+mod test {
+    use figment::Jail;
+
+    use super::{load, ConfigError};
+
+    #[test]
+    fn load_should_fail_when_the_given_file_is_missing() {
+        Jail::expect_with(|_| {
+            match load(Some("missing.toml")) {
+                Err(ConfigError::FileNotFound(file_name)) => assert_eq!(file_name, "missing.toml"),
+                result => panic!("Unexpected result: {result:?}"),
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_should_use_defaults_without_config_file() {
+        Jail::expect_with(|_| {
+            let config = load(None).unwrap();
+
+            assert_eq!(config.db.path, "indieweb.db");
+            assert!(config.mastodon.is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_should_override_file_with_environment_variables() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "indieweb.toml",
+                r#"
+                [db]
+                path = "some/path"
+                [mastodon]
+                base_uri = "https://mastodon.social"
+                access_token = "some-access-token"
+                "#,
+            )?;
+            jail.set_env("IWT_DB__PATH", "other/path");
+            jail.set_env("IWT_MASTODON__ACCESS_TOKEN", "other-access-token");
+
+            let config = load(Some("indieweb.toml")).unwrap();
+
+            assert_eq!(config.db.path, "other/path");
+            assert_eq!(
+                config.mastodon.unwrap().access_token.secret(),
+                "other-access-token"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_should_read_secrets_from_files() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "indieweb.toml",
+                r#"
+                [mastodon]
+                base_uri = "https://mastodon.social"
+                access_token_file = "mastodon_token"
+                "#,
+            )?;
+            jail.create_file("mastodon_token", "secret-access-token\n")?;
+
+            let config = load(None).unwrap();
+
+            assert_eq!(
+                config.mastodon.unwrap().access_token.secret(),
+                "secret-access-token"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_should_prefer_the_value_given_directly_over_the_secret_file() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "indieweb.toml",
+                r#"
+                [mastodon]
+                base_uri = "https://mastodon.social"
+                access_token = "inline-access-token"
+                access_token_file = "mastodon_token"
+                [db]
+                path = "indieweb.db"
+                token_key_file = "token_key"
+                "#,
+            )?;
+            jail.create_file("mastodon_token", "file-access-token")?;
+            jail.create_file("token_key", "file-token-key")?;
+            jail.set_env("IWT_DB__TOKEN_KEY", "env-token-key");
+
+            let config = load(None).unwrap();
+
+            assert_eq!(
+                config.mastodon.unwrap().access_token.secret(),
+                "inline-access-token"
+            );
+            assert_eq!(config.db.token_key.unwrap().secret(), "env-token-key");
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_should_read_secret_file_given_in_environment_variable() {
+        Jail::expect_with(|jail| {
+            jail.create_file("mastodon_token", "secret-access-token")?;
+            jail.set_env("IWT_MASTODON__BASE_URI", "https://mastodon.social");
+            jail.set_env("IWT_MASTODON__ACCESS_TOKEN_FILE", "mastodon_token");
+
+            let config = load(None).unwrap();
+
+            assert_eq!(
+                config.mastodon.unwrap().access_token.secret(),
+                "secret-access-token"
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_should_fail_when_secret_file_is_missing() {
+        Jail::expect_with(|jail| {
+            jail.set_env("IWT_MASTODON__BASE_URI", "https://mastodon.social");
+            jail.set_env("IWT_MASTODON__ACCESS_TOKEN_FILE", "missing");
+
+            match load(None) {
+                Err(ConfigError::SecretFile { key, .. }) => {
+                    assert_eq!(key, "mastodon.access_token");
+                }
+                result => panic!("Unexpected result: {result:?}"),
+            }
+            Ok(())
+        });
+    }
+}
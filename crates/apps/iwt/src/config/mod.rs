@@ -1,67 +1,717 @@
-use std::fs;
+use std::collections::BTreeMap;
 
-use oauth2::{AccessToken, ClientId};
-use serde_derive::Deserialize;
+use oauth2::basic::BasicClient;
+use oauth2::{AccessToken, AuthUrl, ClientId, TokenUrl};
+use serde::Serializer;
+use serde_derive::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, PartialEq)]
+pub use layers::ConfigError;
+
+use crate::commons::html::LinkPolicy;
+use crate::commons::template::Templates;
+use crate::social::{Account, Network, Visibility};
+
+mod layers;
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Config {
+    #[serde(default)]
     pub rss: Rss,
+    #[serde(default)]
     pub db: DB,
-    pub twitter: Twitter,
-    pub mastodon: Mastodon,
-    pub url_shortener: UrlShortener,
+    pub twitter: Option<Twitter>,
+    pub mastodon: Option<Mastodon>,
+    pub url_shortener: Option<UrlShortener>,
+    /// Named status templates, the networks and the feeds can refer to them by name
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+    /// Change the networks, tags, content warning and template of the matching items, applied in
+    /// order after the feed settings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
 pub struct Rss {
     pub urls: Vec<String>,
+    /// Seconds to wait after the publication date of the items before they are syndicated, so
+    /// the last fixes can land first
+    #[serde(default)]
+    pub grace_period: u64,
+    /// Queue the rendered statuses for approval instead of publishing them, see `iwt queue`
+    #[serde(default)]
+    pub require_approval: bool,
+    /// Seconds, the items published earlier are ignored, e.g. the history of the feed on the
+    /// first run
+    pub max_age: Option<u64>,
+    /// What identifies the items of the feeds, when the item misses it the next one is used:
+    /// guid, link, then a hash of the title, the publication date and the content
+    #[serde(default)]
+    pub identity: Identity,
+    /// Settings of the feeds, keyed by the feed url
+    #[serde(default)]
+    pub feeds: BTreeMap<String, Feed>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct Feed {
+    /// Visibility of the items that don't set `iwt:visibility`, only used by Mastodon
+    pub visibility: Option<Visibility>,
+    /// Language of the items that don't set `iwt:language`, the language of the channel is used
+    /// without it
+    pub language: Option<String>,
+    /// Whether the media of the items that don't set `iwt:sensitive` is sensitive
+    pub sensitive: Option<bool>,
+    /// Overrides the grace period of `rss`
+    pub grace_period: Option<u64>,
+    /// Overrides `require_approval` of `rss`
+    pub require_approval: Option<bool>,
+    /// Overrides `max_age` of `rss`
+    pub max_age: Option<u64>,
+    /// Overrides `identity` of `rss`, e.g. `link` for a feed with unstable guids
+    pub identity: Option<Identity>,
+    /// Fields of the items the text of the statuses is taken from, the first one an item has is
+    /// used. `iwt:summary`, `content:encoded`, `description`, then the title by default
+    pub content: Option<Vec<ContentSource>>,
+    /// Extension of the items that don't have an `iwt:extension`, e.g. of a feed generated by a
+    /// third party. The items without an extension are skipped when it is not set
+    pub defaults: Option<ItemDefaults>,
+    /// Template names by network, they override the template of the network
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+}
+
+/// Stands in for the `iwt:extension` of the items that don't have one
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ItemDefaults {
+    /// Networks the items are syndicated to
+    #[serde(default)]
+    pub target_networks: Vec<Network>,
+    /// Take the tags of the items from their `category` elements
+    #[serde(default)]
+    pub tags_from_categories: bool,
+    pub content_warning: Option<String>,
+}
+
+/// Changes the syndication of the items that match all of its conditions
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Rule {
+    /// Names the rule in the logs, the index of the rule is used without it
+    pub name: Option<String>,
+    #[serde(default)]
+    pub when: Conditions,
+    #[serde(default)]
+    pub then: Actions,
+}
+
+/// Conditions of a rule, the ones that are not set match every item
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Conditions {
+    /// Url of the feed
+    pub feed: Option<String>,
+    /// A tag or a category of the item, case insensitive
+    pub tag: Option<String>,
+    /// Language of the item, `de` matches `de-AT` as well
+    pub language: Option<String>,
+    /// Kind of the post: `note`, `article` or `photo`
+    pub kind: Option<String>,
+    /// Regex matched against the title and the text of the item
+    pub text: Option<String>,
+}
+
+/// What a rule changes, in the order of the fields
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Actions {
+    /// Replaces the networks of the item, the named accounts are dropped
+    pub only_networks: Option<Vec<Network>>,
+    /// Networks the item is syndicated to as well
+    #[serde(default)]
+    pub add_networks: Vec<Network>,
+    /// Networks the item is not syndicated to, with their named accounts
+    #[serde(default)]
+    pub remove_networks: Vec<Network>,
+    /// Replaces the networks and the accounts of the item, e.g. `mastodon:german`. The name of a
+    /// network stands for its default account
+    pub only_accounts: Option<Vec<Account>>,
+    /// Accounts the item is syndicated to as well
+    #[serde(default)]
+    pub add_accounts: Vec<Account>,
+    /// Accounts the item is not syndicated to
+    #[serde(default)]
+    pub remove_accounts: Vec<Account>,
+    /// Appended to the tags of the item
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub content_warning: Option<String>,
+    /// Name of the status template
+    pub template: Option<String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct DB {
     pub path: String,
+    /// Base64 encoded 32 byte key, when it is set the auth tokens are encrypted in the DB
+    pub token_key: Option<Secret>,
+}
+
+impl Default for DB {
+    fn default() -> Self {
+        Self {
+            path: String::from("indieweb.db"),
+            token_key: None,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Twitter {
     pub client_id: ClientId,
+    #[serde(default = "Twitter::default_auth_url")]
+    pub auth_url: String,
+    #[serde(default = "Twitter::default_token_url")]
+    pub token_url: String,
+    /// The app-auth flow listens on this host and port for the redirect, the resulting uri has to
+    /// be registered as a callback uri of the Twitter app
+    #[serde(default = "Twitter::default_redirect_host")]
+    pub redirect_host: String,
+    #[serde(default = "Twitter::default_redirect_port")]
+    pub redirect_port: u16,
+    /// Seconds to wait for the redirect in the app-auth flow
+    #[serde(default = "Twitter::default_auth_timeout")]
+    pub auth_timeout: u64,
+    /// Name of the status template, the built-in template of the post kind is used by default
+    pub template: Option<String>,
+    /// What happens with the links of the post body: inline, footnotes or drop
+    #[serde(default)]
+    pub links: LinkPolicy,
+    /// At most this many posts are published in a run, the rest is left for the next runs
+    pub max_posts_per_run: Option<usize>,
+    /// Seconds to wait between two posts
+    #[serde(default)]
+    pub post_interval: u64,
+}
+
+impl Twitter {
+    #[must_use]
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            auth_url: Self::default_auth_url(),
+            token_url: Self::default_token_url(),
+            redirect_host: Self::default_redirect_host(),
+            redirect_port: Self::default_redirect_port(),
+            auth_timeout: Self::default_auth_timeout(),
+            template: None,
+            links: LinkPolicy::default(),
+            max_posts_per_run: None,
+            post_interval: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn redirect_uri(&self) -> String {
+        format!("http://{}:{}", self.redirect_host, self.redirect_port)
+    }
+
+    /// The OAuth2 client of the Twitter app, the urls are validated when the config is loaded
+    #[must_use]
+    pub fn oauth_client(&self) -> BasicClient {
+        BasicClient::new(
+            self.client_id.clone(),
+            None,
+            AuthUrl::new(self.auth_url.clone()).expect("Twitter auth url is invalid."),
+            Some(TokenUrl::new(self.token_url.clone()).expect("Twitter token url is invalid")),
+        )
+    }
+
+    fn default_auth_url() -> String {
+        String::from("https://twitter.com/i/oauth2/authorize")
+    }
+
+    fn default_token_url() -> String {
+        String::from("https://api.twitter.com/2/oauth2/token")
+    }
+
+    fn default_redirect_host() -> String {
+        String::from("127.0.0.1")
+    }
+
+    fn default_redirect_port() -> u16 {
+        6009
+    }
+
+    fn default_auth_timeout() -> u64 {
+        300
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Mastodon {
     pub base_uri: String,
+    #[serde(serialize_with = "redacted")]
+    pub access_token: AccessToken,
+    /// Overrides the character limit reported by the instance
+    pub max_characters: Option<usize>,
+    /// Overrides the length a link is counted as, reported by the instance
+    pub characters_reserved_per_url: Option<usize>,
+    /// Name of the status template, the built-in template of the post kind is used by default
+    pub template: Option<String>,
+    /// What happens with the links of the post body: inline, footnotes or drop
+    #[serde(default)]
+    pub links: LinkPolicy,
+    /// Schedule the posts that are not due yet on the instance, instead of publishing them from a
+    /// later run
+    #[serde(default)]
+    pub schedule_on_server: bool,
+    /// At most this many posts are published in a run, the rest is left for the next runs
+    pub max_posts_per_run: Option<usize>,
+    /// Seconds to wait between two posts
+    #[serde(default)]
+    pub post_interval: u64,
+    /// Named accounts besides the default one, the rules route the items to them, e.g.
+    /// `mastodon:german`. They share the settings of the section, unless they override them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub accounts: BTreeMap<String, MastodonAccount>,
+}
+
+/// A named Mastodon account
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MastodonAccount {
+    /// The instance of the account, the one of the default account by default
+    pub base_uri: Option<String>,
+    #[serde(serialize_with = "redacted")]
     pub access_token: AccessToken,
+    /// Name of the status template, the template of the default account by default
+    pub template: Option<String>,
+}
+
+impl Mastodon {
+    #[must_use]
+    pub fn new(base_uri: String, access_token: AccessToken) -> Self {
+        Self {
+            base_uri,
+            access_token,
+            max_characters: None,
+            characters_reserved_per_url: None,
+            template: None,
+            links: LinkPolicy::default(),
+            schedule_on_server: false,
+            max_posts_per_run: None,
+            post_interval: 0,
+            accounts: BTreeMap::new(),
+        }
+    }
+}
+
+/// What identifies an item of a feed, the syndicated posts are recorded by it. Every item needs a
+/// link (or a permalink guid) whatever identifies it, the statuses cite it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Identity {
+    /// The guid, falling back to the link, then to the hash
+    #[default]
+    Guid,
+    /// The link, falling back to the hash
+    Link,
+    /// Hash of the title, the publication date and the content
+    Hash,
+}
+
+/// Field of an item the text of its statuses can be taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentSource {
+    /// `iwt:summary`
+    Summary,
+    /// `content:encoded`
+    Content,
+    Description,
+    Title,
+}
+
+impl ContentSource {
+    /// The fields in the order they are used by default
+    pub const PRECEDENCE: [ContentSource; 4] = [
+        ContentSource::Summary,
+        ContentSource::Content,
+        ContentSource::Description,
+        ContentSource::Title,
+    ];
+}
+
+impl std::fmt::Display for ContentSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentSource::Summary => write!(f, "iwt:summary"),
+            ContentSource::Content => write!(f, "content:encoded"),
+            ContentSource::Description => write!(f, "description"),
+            ContentSource::Title => write!(f, "title"),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+/// How fast the posts are published to a network
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pacing {
+    pub max_posts_per_run: Option<usize>,
+    /// Seconds to wait between two posts
+    pub post_interval: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct UrlShortener {
     pub protocol: String,
     pub domain: String,
     pub put_base_uri: Option<String>,
 }
 
+/// Config value that is redacted when it is printed
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    #[must_use]
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    #[must_use]
+    pub fn secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret([redacted])")
+    }
+}
+
+impl serde::Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        redacted(self, serializer)
+    }
+}
+
 impl PartialEq for Mastodon {
     fn eq(&self, other: &Self) -> bool {
-        self.base_uri == other.base_uri && self.access_token.secret() == other.access_token.secret()
+        self.base_uri == other.base_uri
+            && self.access_token.secret() == other.access_token.secret()
+            && self.max_characters == other.max_characters
+            && self.characters_reserved_per_url == other.characters_reserved_per_url
+            && self.template == other.template
+            && self.links == other.links
+            && self.schedule_on_server == other.schedule_on_server
+            && self.max_posts_per_run == other.max_posts_per_run
+            && self.post_interval == other.post_interval
+            && self.accounts == other.accounts
+    }
+}
+
+impl PartialEq for MastodonAccount {
+    fn eq(&self, other: &Self) -> bool {
+        self.base_uri == other.base_uri
+            && self.access_token.secret() == other.access_token.secret()
+            && self.template == other.template
     }
 }
 
 impl Config {
-    pub fn from_file(file_name: &str) -> Result<Config, toml::de::Error> {
-        let config_str = fs::read_to_string(file_name)
-            .unwrap_or_else(|_| panic!("Cannot found file: {file_name}"));
+    /// The networks that have a section in the config
+    #[must_use]
+    pub fn networks(&self) -> Vec<Network> {
+        let mut networks = Vec::new();
+        if self.twitter.is_some() {
+            networks.push(Network::Twitter);
+        }
+        if self.mastodon.is_some() {
+            networks.push(Network::Mastodon);
+        }
+        networks
+    }
+
+    /// The accounts of the configured networks, the default ones first
+    #[must_use]
+    pub fn accounts(&self) -> Vec<Account> {
+        let mut accounts = self
+            .networks()
+            .into_iter()
+            .map(Account::from)
+            .collect::<Vec<_>>();
+        if let Some(mastodon) = &self.mastodon {
+            accounts.extend(
+                mastodon
+                    .accounts
+                    .keys()
+                    .map(|name| Account::named(Network::Mastodon, name)),
+            );
+        }
+        accounts
+    }
 
-        toml::from_str(&config_str)
+    /// Name of the template the network uses by default, `None` when it is not configured
+    #[must_use]
+    pub fn template(&self, network: &Network) -> Option<&String> {
+        match network {
+            Network::Twitter => self.twitter.as_ref().and_then(|t| t.template.as_ref()),
+            Network::Mastodon => self.mastodon.as_ref().and_then(|m| m.template.as_ref()),
+        }
+    }
+
+    /// What identifies the items of the feed
+    #[must_use]
+    pub fn identity(&self, url: &str) -> Identity {
+        self.rss
+            .feeds
+            .get(url)
+            .and_then(|feed| feed.identity)
+            .unwrap_or(self.rss.identity)
+    }
+
+    /// The pacing of the network, unconfigured networks are not paced
+    #[must_use]
+    pub fn pacing(&self, network: &Network) -> Pacing {
+        match network {
+            Network::Twitter => self.twitter.as_ref().map(|twitter| Pacing {
+                max_posts_per_run: twitter.max_posts_per_run,
+                post_interval: twitter.post_interval,
+            }),
+            Network::Mastodon => self.mastodon.as_ref().map(|mastodon| Pacing {
+                max_posts_per_run: mastodon.max_posts_per_run,
+                post_interval: mastodon.post_interval,
+            }),
+        }
+        .unwrap_or_default()
+    }
+
+    /// Loads the config from the defaults, the given (or the default) TOML file and the `IWT_*`
+    /// environment variables, then validates it
+    pub fn load(file_name: Option<&str>) -> Result<Config, ConfigError> {
+        let config = layers::load(file_name)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values that are well typed, but still don't make sense
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        for url in &self.rss.urls {
+            if let Err(err) = url::Url::parse(url) {
+                problems.push(format!("rss.urls: invalid url {url}: {err}"));
+            }
+        }
+
+        if let Some(twitter) = &self.twitter {
+            for (key, url) in [
+                ("twitter.auth_url", &twitter.auth_url),
+                ("twitter.token_url", &twitter.token_url),
+            ] {
+                if let Err(err) = url::Url::parse(url) {
+                    problems.push(format!("{key}: invalid url {url}: {err}"));
+                }
+            }
+        }
+
+        if let Some(mastodon) = &self.mastodon {
+            let base_uris = std::iter::once(("mastodon.base_uri".to_string(), &mastodon.base_uri))
+                .chain(mastodon.accounts.iter().filter_map(|(name, account)| {
+                    account
+                        .base_uri
+                        .as_ref()
+                        .map(|base_uri| (format!("mastodon.accounts.{name}.base_uri"), base_uri))
+                }));
+            for (key, base_uri) in base_uris {
+                if let Err(err) = url::Url::parse(base_uri) {
+                    problems.push(format!("{key}: invalid url {base_uri}: {err}"));
+                }
+            }
+
+            for name in mastodon.accounts.keys() {
+                if name.is_empty() || name.contains(':') {
+                    problems.push(format!(
+                        "mastodon.accounts: invalid name \"{name}\", it can't be empty or contain :"
+                    ));
+                }
+            }
+        }
+
+        self.validate_templates(&mut problems);
+        self.validate_rules(&mut problems);
+
+        if let Some(url_shortener) = &self.url_shortener {
+            if !["http", "https"].contains(&url_shortener.protocol.as_str()) {
+                problems.push(format!(
+                    "url_shortener.protocol: expected http or https, got {}",
+                    url_shortener.protocol
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    fn validate_templates(&self, problems: &mut Vec<String>) {
+        let templates = match Templates::new(&self.templates) {
+            Ok(templates) => templates,
+            Err(err) => {
+                problems.push(err.message);
+                return;
+            }
+        };
+
+        let mut references = self
+            .networks()
+            .into_iter()
+            .map(|network| (format!("{network}.template"), self.template(&network)))
+            .collect::<Vec<_>>();
+        if let Some(mastodon) = &self.mastodon {
+            references.extend(mastodon.accounts.iter().map(|(name, account)| {
+                (
+                    format!("mastodon.accounts.{name}.template"),
+                    account.template.as_ref(),
+                )
+            }));
+        }
+
+        for (url, feed) in &self.rss.feeds {
+            if !self.rss.urls.contains(url) {
+                problems.push(format!(
+                    "rss.feeds.\"{url}\": the feed is not listed in rss.urls"
+                ));
+            }
+
+            for (network, name) in &feed.templates {
+                if Network::parse(network).is_none() {
+                    problems.push(format!(
+                        "rss.feeds.\"{url}\".templates: unknown network {network}"
+                    ));
+                }
+                references.push((
+                    format!("rss.feeds.\"{url}\".templates.{network}"),
+                    Some(name),
+                ));
+            }
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            references.push((
+                format!("rules[{i}].then.template"),
+                rule.then.template.as_ref(),
+            ));
+        }
+
+        for (key, name) in references {
+            if let Some(name) = name {
+                if !templates.contains(name) {
+                    problems.push(format!("{key}: unknown template {name}"));
+                }
+            }
+        }
+    }
+
+    fn validate_rules(&self, problems: &mut Vec<String>) {
+        // The networks that are not configured are warned about when the items are syndicated
+        let named_accounts = self
+            .accounts()
+            .into_iter()
+            .filter(|account| account.name.is_some())
+            .collect::<Vec<_>>();
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            let when = &rule.when;
+
+            if let Some(feed) = &when.feed {
+                if !self.rss.urls.contains(feed) {
+                    problems.push(format!(
+                        "rules[{i}].when.feed: the feed {feed} is not listed in rss.urls"
+                    ));
+                }
+            }
+
+            if let Some(kind) = &when.kind {
+                if !["note", "article", "photo"].contains(&kind.as_str()) {
+                    problems.push(format!(
+                        "rules[{i}].when.kind: expected note, article or photo, got {kind}"
+                    ));
+                }
+            }
+
+            if let Some(text) = &when.text {
+                if let Err(err) = regex::Regex::new(text) {
+                    problems.push(format!("rules[{i}].when.text: invalid regex {text}: {err}"));
+                }
+            }
+
+            let then = &rule.then;
+            for (key, listed) in [
+                (
+                    "only_accounts",
+                    then.only_accounts.as_deref().unwrap_or_default(),
+                ),
+                ("add_accounts", &then.add_accounts),
+                ("remove_accounts", &then.remove_accounts),
+            ] {
+                let unknown = listed
+                    .iter()
+                    .filter(|account| account.name.is_some() && !named_accounts.contains(account));
+                for account in unknown {
+                    problems.push(format!(
+                        "rules[{i}].then.{key}: the account {account} is not configured"
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn redacted<T, S: Serializer>(_secret: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[redacted]")
+}
+
+#[derive(clap::Subcommand)]
+pub enum ConfigSubcommand {
+    /// Validate the config and print the effective values, secrets are redacted
+    Check,
+}
+
+pub fn execute(
+    command: &ConfigSubcommand,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        ConfigSubcommand::Check => {
+            println!("{}", toml::to_string_pretty(config)?);
+
+            if config.twitter.is_none() && config.mastodon.is_none() {
+                log::warn!("None of the social networks are configured");
+            }
+
+            log::info!("Config is valid");
+            Ok(())
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
+
     use oauth2::AccessToken;
     use oauth2::ClientId;
 
+    use super::Account;
     use super::Config;
+    use super::ConfigError;
+    use super::Identity;
+    use super::ItemDefaults;
     use super::Mastodon;
+    use super::Network;
     use super::Rss;
     use super::Twitter;
     use super::UrlShortener;
@@ -94,24 +744,311 @@ mod test {
                     urls: vec![
                         "http://exmample.com/rss.xml".to_string(),
                         "http://exmample.com/some-site/rss.xml".to_string()
-                    ]
+                    ],
+                    feeds: BTreeMap::new(),
+                    grace_period: 0,
+                    require_approval: false,
+                    max_age: None,
+                    identity: Identity::Guid,
                 },
                 db: DB {
-                    path: String::from("some/path")
-                },
-                twitter: Twitter {
-                    client_id: ClientId::new(String::from("some_client_id"))
-                },
-                mastodon: Mastodon {
-                    base_uri: String::from("https://mastodon.social"),
-                    access_token: AccessToken::new(String::from("some-access-token"))
+                    path: String::from("some/path"),
+                    token_key: None,
                 },
-                url_shortener: UrlShortener {
+                twitter: Some(Twitter::new(ClientId::new(String::from("some_client_id")))),
+                mastodon: Some(Mastodon::new(
+                    String::from("https://mastodon.social"),
+                    AccessToken::new(String::from("some-access-token"))
+                )),
+                url_shortener: Some(UrlShortener {
                     protocol: String::from("http"),
                     domain: String::from("localhost:9000"),
                     put_base_uri: None,
-                }
+                }),
+                templates: BTreeMap::new(),
+                rules: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn config_model_should_be_deserializable_without_network_sections() {
+        let config = r#"
+        [rss]
+        urls = [ "http://exmample.com/rss.xml" ]
+        [db]
+        path = "some/path"
+        "#;
+
+        assert_eq!(
+            toml::from_str::<Config>(config),
+            Ok(Config {
+                rss: Rss {
+                    urls: vec!["http://exmample.com/rss.xml".to_string()],
+                    feeds: BTreeMap::new(),
+                    grace_period: 0,
+                    require_approval: false,
+                    max_age: None,
+                    identity: Identity::Guid,
+                },
+                db: DB {
+                    path: String::from("some/path"),
+                    token_key: None,
+                },
+                twitter: None,
+                mastodon: None,
+                url_shortener: None,
+                templates: BTreeMap::new(),
+                rules: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn config_model_should_use_defaults_for_missing_sections() {
+        assert_eq!(
+            toml::from_str::<Config>(""),
+            Ok(Config {
+                rss: Rss::default(),
+                db: DB {
+                    path: String::from("indieweb.db"),
+                    token_key: None,
+                },
+                twitter: None,
+                mastodon: None,
+                url_shortener: None,
+                templates: BTreeMap::new(),
+                rules: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn config_should_be_serialized_with_redacted_secrets() {
+        let config = Config {
+            mastodon: Some(Mastodon::new(
+                String::from("https://mastodon.social"),
+                AccessToken::new(String::from("some-access-token")),
+            )),
+            ..toml::from_str(
+                r#"
+                [rss]
+                urls = [ "http://example.com/rss.xml" ]
+                [rss.feeds."http://example.com/rss.xml"]
+                visibility = "unlisted"
+                [rss.feeds."http://example.com/rss.xml".defaults]
+                target_networks = [ "mastodon" ]
+                [rss.feeds."http://example.com/rss.xml".templates]
+                mastodon = "article"
+                [[rules]]
+                when = { tag = "politics" }
+                then = { content_warning = "Politics", remove_networks = [ "twitter" ] }
+                "#,
+            )
+            .unwrap()
+        };
+
+        let serialized = toml::to_string(&config).unwrap();
+
+        assert_eq!(
+            toml::from_str::<Config>(&serialized).unwrap().rules,
+            config.rules
+        );
+
+        assert_eq!(
+            toml::from_str::<Config>(&serialized).unwrap().rss.feeds["http://example.com/rss.xml"]
+                .defaults,
+            Some(ItemDefaults {
+                target_networks: vec![Network::Mastodon],
+                ..Default::default()
             })
         );
+
+        assert!(serialized.contains("[redacted]"), "{serialized}");
+        assert!(!serialized.contains("some-access-token"), "{serialized}");
+    }
+
+    #[test]
+    fn config_should_configure_the_named_accounts() {
+        let config: Config = toml::from_str(
+            r#"
+            [mastodon]
+            base_uri = "https://mastodon.social"
+            access_token = "some-access-token"
+            [mastodon.accounts.german]
+            base_uri = "https://chaos.social"
+            access_token = "german-access-token"
+            [mastodon.accounts.project]
+            access_token = "project-access-token"
+            template = "project"
+            [templates]
+            project = "{{ text }} {{ permashort_uri }}"
+            [[rules]]
+            when = { language = "de" }
+            then = { only_accounts = [ "mastodon:german" ] }
+            [[rules]]
+            when = { tag = "rust" }
+            then = { add_accounts = [ "mastodon:project" ], remove_accounts = [ "twitter" ] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.accounts(),
+            [
+                Account::from(Network::Mastodon),
+                Account::named(Network::Mastodon, "german"),
+                Account::named(Network::Mastodon, "project"),
+            ]
+        );
+        assert_eq!(
+            config.rules[0].then.only_accounts,
+            Some(vec![Account::named(Network::Mastodon, "german")])
+        );
+        assert_eq!(
+            config.rules[1].then.remove_accounts,
+            [Account::from(Network::Twitter)]
+        );
+        assert!(config.validate().is_ok(), "{:?}", config.validate());
+
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(
+            toml::from_str::<Config>(&serialized).unwrap().rules,
+            config.rules
+        );
+        assert!(!serialized.contains("german-access-token"), "{serialized}");
+
+        assert!(toml::from_str::<Config>(
+            r#"
+            [[rules]]
+            then = { add_accounts = [ "bluesky:german" ] }
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn config_validation_should_report_the_unknown_accounts() {
+        let config: Config = toml::from_str(
+            r#"
+            [mastodon]
+            base_uri = "https://mastodon.social"
+            access_token = "some-access-token"
+            [mastodon.accounts."a:b"]
+            base_uri = "not a url"
+            access_token = "other-access-token"
+            template = "longform"
+            [[rules]]
+            then = { add_accounts = [ "mastodon:project", "twitter" ] }
+            "#,
+        )
+        .unwrap();
+
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems,
+                vec![
+                    "mastodon.accounts.a:b.base_uri: invalid url not a url: relative URL without \
+                     a base",
+                    "mastodon.accounts: invalid name \"a:b\", it can't be empty or contain :",
+                    "mastodon.accounts.a:b.template: unknown template longform",
+                    "rules[0].then.add_accounts: the account mastodon:project is not configured",
+                ]
+            ),
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn config_validation_should_report_invalid_values() {
+        let config: Config = toml::from_str(
+            r#"
+            [rss]
+            urls = [ "not a url" ]
+            [url_shortener]
+            protocol = "ftp"
+            domain = "localhost"
+            "#,
+        )
+        .unwrap();
+
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(problems.len(), 2, "{problems:?}"),
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn config_validation_should_report_unknown_templates() {
+        let config: Config = toml::from_str(
+            r#"
+            [rss]
+            urls = [ "http://example.com/rss.xml" ]
+            [rss.feeds."http://example.com/rss.xml".templates]
+            mastodon = "longform"
+            bluesky = "article"
+            [rss.feeds."http://example.com/other.xml"]
+            [mastodon]
+            base_uri = "https://mastodon.social"
+            access_token = "some-access-token"
+            template = "note"
+            [templates]
+            note = "{{ text }} {{ permashort_uri }}"
+            "#,
+        )
+        .unwrap();
+
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems,
+                vec![
+                    "rss.feeds.\"http://example.com/other.xml\": the feed is not listed in rss.urls",
+                    "rss.feeds.\"http://example.com/rss.xml\".templates: unknown network bluesky",
+                    "rss.feeds.\"http://example.com/rss.xml\".templates.mastodon: unknown template longform",
+                ]
+            ),
+            result => panic!("Unexpected result: {result:?}"),
+        }
+    }
+
+    #[test]
+    fn config_validation_should_report_invalid_rules() {
+        let config: Config = toml::from_str(
+            r#"
+            [rss]
+            urls = [ "http://example.com/rss.xml" ]
+            [[rules]]
+            when = { feed = "http://example.com/other.xml", kind = "video", text = "(rust" }
+            then = { template = "longform" }
+            [[rules]]
+            when = { tag = "rust", feed = "http://example.com/rss.xml" }
+            then = { add_networks = [ "mastodon" ] }
+            "#,
+        )
+        .unwrap();
+
+        match config.validate() {
+            Err(ConfigError::Invalid(problems)) => {
+                assert_eq!(problems.len(), 4, "{problems:?}");
+                assert_eq!(
+                    problems[0],
+                    "rules[0].then.template: unknown template longform"
+                );
+                assert_eq!(
+                    problems[1],
+                    "rules[0].when.feed: the feed http://example.com/other.xml is not listed in \
+                     rss.urls"
+                );
+                assert_eq!(
+                    problems[2],
+                    "rules[0].when.kind: expected note, article or photo, got video"
+                );
+                assert!(
+                    problems[3].starts_with("rules[0].when.text: invalid regex (rust"),
+                    "{problems:?}"
+                );
+            }
+            result => panic!("Unexpected result: {result:?}"),
+        }
     }
 }
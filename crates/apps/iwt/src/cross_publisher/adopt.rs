@@ -0,0 +1,412 @@
+use std::rc::Rc;
+
+use ::rss::Item;
+use rusqlite::Connection;
+use url::Url;
+
+use super::identity::identified;
+use super::preview::parse_network;
+use super::rss;
+use super::syndicated_post::{SqliteSyndycatedPostStorage, Storage, SyndicatedPost};
+use super::target::{PublishedStatus, Target};
+use crate::commons::html;
+use crate::commons::url_shortener;
+use crate::config::{Config, Identity};
+use crate::social::Network;
+
+#[derive(clap::Args)]
+pub struct AdoptArgs {
+    /// Network to look for the copies on, can be repeated. All the configured networks by default
+    #[clap(long = "network", value_parser = parse_network)]
+    networks: Vec<Network>,
+    /// Url of the feed, all the configured feeds by default
+    #[clap(long, value_parser)]
+    feed: Option<String>,
+    /// Fetch the pages of the items for the `u-syndication` links of their h-entries
+    #[clap(long, action)]
+    fetch_entries: bool,
+    /// Only log the copies that would be adopted
+    #[clap(long, action)]
+    dry_run: bool,
+}
+
+pub async fn execute(args: AdoptArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = Rc::new(Connection::open(&config.db.path)?);
+    let storage = SqliteSyndycatedPostStorage::new(Rc::clone(&conn));
+    storage.init_table()?;
+
+    let url_shortener_client = super::url_shortener_client(config);
+    let targets = super::targets(config, &conn, Rc::clone(&url_shortener_client))
+        .await?
+        .into_iter()
+        .filter(|target| args.networks.is_empty() || args.networks.contains(&target.network()))
+        .collect::<Vec<_>>();
+
+    let feeds = match args.feed {
+        Some(feed) => vec![feed],
+        None => config.rss.urls.clone(),
+    }
+    .into_iter()
+    .map(|url| {
+        let identity = config.identity(&url);
+        (url, identity)
+    })
+    .collect::<Vec<_>>();
+    let entry_client = args.fetch_entries.then(reqwest::Client::new);
+
+    let adopted = adopt(
+        &rss::ReqwestClient,
+        &feeds,
+        &targets,
+        url_shortener_client.as_ref(),
+        entry_client.as_ref(),
+        &storage,
+        args.dry_run,
+    )
+    .await?;
+    log::info!("{adopted} copies are adopted");
+
+    Ok(())
+}
+
+/// Records the statuses that were published without iwt as the syndicated posts of the items,
+/// so they are not published again. A status is a copy of an item when a `u-syndication` link of
+/// the item (or of its h-entry, when `entry_client` is given) points to it, or when a recent
+/// status of the account cites the link or the permashort link of the item. Returns the number
+/// of the adopted statuses
+async fn adopt<R: rss::Client, S: Storage>(
+    rss_client: &R,
+    feeds: &[(String, Identity)],
+    targets: &[Box<dyn Target>],
+    url_shortener_client: &dyn url_shortener::Client,
+    entry_client: Option<&reqwest::Client>,
+    storage: &S,
+    dry_run: bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut recent_statuses = Vec::new();
+    for target in targets {
+        recent_statuses.push(target.recent_statuses().await.unwrap_or_else(|err| {
+            log::warn!(
+                "Couldn't list the recent statuses of {}, only the syndication links are \
+                 checked: {err}",
+                target.account()
+            );
+            Vec::new()
+        }));
+    }
+
+    let mut adopted = 0;
+
+    for (url, identity) in feeds {
+        let (channel, invalid_items) =
+            identified(rss_client.get_channel(url).await?, url, *identity);
+        for invalid_item in invalid_items {
+            log::warn!("{invalid_item}, skipping");
+        }
+
+        for identified in &channel.items {
+            let (link, item) = (&identified.link, &identified.item);
+
+            let mut candidates = Vec::new();
+            for (target, statuses) in targets.iter().zip(&recent_statuses) {
+                if storage
+                    .find(&identified.identity, &target.account())?
+                    .is_none()
+                {
+                    candidates.push((target, statuses));
+                }
+            }
+            if candidates.is_empty() {
+                continue;
+            }
+
+            let syndication_links = syndication_links(item, entry_client).await;
+            let citations = citations(link, url_shortener_client).await;
+
+            for (target, statuses) in candidates {
+                let account = target.account();
+                let Some(id) = syndication_links
+                    .iter()
+                    .find_map(|link| target.status_id(link))
+                    .or_else(|| citing_status(statuses, &citations))
+                else {
+                    continue;
+                };
+
+                if dry_run {
+                    log::info!("{link} |> The status {id} of {account} would be adopted");
+                } else {
+                    storage.store(SyndicatedPost::new(account.clone(), &id, identified))?;
+                    log::info!("{link} |> Adopted the status {id} of {account}");
+                }
+                adopted += 1;
+            }
+        }
+    }
+
+    Ok(adopted)
+}
+
+/// The `u-syndication` links of the content of the item, and of its h-entry when the entry
+/// client is given
+async fn syndication_links(item: &Item, entry_client: Option<&reqwest::Client>) -> Vec<String> {
+    let link = item.link().unwrap_or_default();
+    let base = Url::parse(link).ok();
+
+    let mut links = [item.description(), item.content()]
+        .into_iter()
+        .flatten()
+        .flat_map(|content| html::syndication_links(content, base.as_ref()))
+        .collect::<Vec<_>>();
+
+    if let Some(entry_client) = entry_client {
+        match fetch_entry(entry_client, link).await {
+            Ok(entry) => links.extend(html::syndication_links(&entry, base.as_ref())),
+            Err(err) => log::warn!("{link} |> Couldn't fetch the h-entry: {err}"),
+        }
+    }
+
+    links
+}
+
+async fn fetch_entry(
+    entry_client: &reqwest::Client,
+    link: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(entry_client
+        .get(link)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
+
+/// The links a copy of the item may cite: the link of the item and its permashort link, the
+/// permashort link is only looked up, an item that has none can't be cited by it
+pub async fn citations<C: url_shortener::Client + ?Sized>(
+    link: &str,
+    url_shortener_client: &C,
+) -> Vec<String> {
+    let mut citations = vec![link.to_string()];
+
+    match url_shortener_client.get_uri(link).await {
+        Ok(Some(citation)) if citation.to_uri() != link => citations.push(citation.to_uri()),
+        Ok(_) => {}
+        Err(err) => log::warn!("{link} |> Couldn't get the permashort link: {err}"),
+    }
+
+    citations
+}
+
+/// Id of the first status that cites any of the links
+pub fn citing_status(statuses: &[PublishedStatus], citations: &[String]) -> Option<String> {
+    let same = |a: &str, b: &str| a.trim_end_matches('/') == b.trim_end_matches('/');
+
+    statuses
+        .iter()
+        .find(|status| {
+            status
+                .links
+                .iter()
+                .any(|link| citations.iter().any(|citation| same(link, citation)))
+        })
+        .map(|status| status.id.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use rss::Item;
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::commons::url_shortener::{NoopClient, PlaceholderClient, ReqwestClient};
+    use crate::config::Identity;
+    use crate::cross_publisher::stubs::identity::identified_item;
+    use crate::cross_publisher::stubs::rss::{gen_items, StubRssClient};
+    use crate::cross_publisher::stubs::syndycated_post::SyndicatedPostStorageStub;
+    use crate::cross_publisher::stubs::target::StubTarget;
+    use crate::cross_publisher::syndicated_post::{Storage, SyndicatedPost};
+    use crate::cross_publisher::target::{PublishedStatus, Target};
+    use crate::social::Network;
+
+    use super::adopt;
+
+    fn status(id: &str, links: &[&str]) -> PublishedStatus {
+        PublishedStatus {
+            id: id.to_string(),
+            links: links.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adopt_should_record_the_copies_of_the_items() {
+        let feed = "http://example.com/rss.xml";
+        let mut items = gen_items(&[feed]);
+        let posts = items.get_mut(feed).unwrap();
+        posts[2].description = Some(format!(
+            r#"<p>Hello</p><a class="u-syndication" href="{}">Tweet</a>"#,
+            StubTarget::status_url(&Network::Twitter, "t2")
+        ));
+        let posts = posts.clone();
+
+        let mut mastodon = StubTarget::new(Network::Mastodon);
+        mastodon.recent_statuses = vec![
+            status("m9", &["https://example.com/unrelated"]),
+            status("m1", &[&format!("{}/", posts[1].link().unwrap())]),
+        ];
+        let targets: Vec<Box<dyn Target>> =
+            vec![mastodon.into(), StubTarget::new(Network::Twitter).into()];
+        let storage = SyndicatedPostStorageStub::default();
+
+        let adopted = adopt(
+            &StubRssClient::new(&items),
+            &[(feed.to_string(), Identity::Guid)],
+            &targets,
+            &NoopClient,
+            None,
+            &storage,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(adopted, 2);
+        assert_eq!(
+            *storage.posts.lock().unwrap(),
+            [
+                SyndicatedPost::new(Network::Mastodon.into(), "m1", &identified_item(&posts[1])),
+                SyndicatedPost::new(Network::Twitter.into(), "t2", &identified_item(&posts[2])),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_adopt_should_match_the_permashort_links() {
+        let feed = "http://example.com/rss.xml";
+        let items = gen_items(&[feed]);
+        let posts = items.get(feed).unwrap();
+
+        let mut mastodon = StubTarget::new(Network::Mastodon);
+        mastodon.recent_statuses = vec![status("m1", &["https://vdx.hu/s/xxxx"])];
+        let targets: Vec<Box<dyn Target>> = vec![mastodon.into()];
+        let storage = SyndicatedPostStorageStub::default();
+        storage
+            .store(SyndicatedPost::new(
+                Network::Mastodon.into(),
+                "1",
+                &identified_item(&posts[0]),
+            ))
+            .unwrap();
+
+        let adopted = adopt(
+            &StubRssClient::new(&items),
+            &[(feed.to_string(), Identity::Guid)],
+            &targets,
+            &PlaceholderClient::new("https", "vdx.hu"),
+            None,
+            &storage,
+            true,
+        )
+        .await
+        .unwrap();
+
+        // The placeholder cites the same link for all the items, the syndicated one is skipped
+        assert_eq!(adopted, posts.len() - 1);
+        assert_eq!(
+            *storage.posts.lock().unwrap(),
+            [SyndicatedPost::new(
+                Network::Mastodon.into(),
+                "1",
+                &identified_item(&posts[0])
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_adopt_should_only_look_up_the_permashort_links() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/u/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Df3l"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("PUT"))
+            .respond_with(ResponseTemplate::new(201).set_body_string("Xy12"))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let feed = "http://example.com/rss.xml";
+        let items = gen_items(&[feed]);
+        let posts = items.get(feed).unwrap();
+        let mut mastodon = StubTarget::new(Network::Mastodon);
+        mastodon.recent_statuses = vec![status("m1", &["https://vdx.hu/s/Df3l"])];
+        let targets: Vec<Box<dyn Target>> = vec![mastodon.into()];
+        let storage = SyndicatedPostStorageStub::default();
+
+        let adopted = adopt(
+            &StubRssClient::new(&items),
+            &[(feed.to_string(), Identity::Guid)],
+            &targets,
+            &ReqwestClient::new("https", "vdx.hu", Some(&mock_server.uri())),
+            None,
+            &storage,
+            true,
+        )
+        .await
+        .unwrap();
+
+        // The lookup returns the same short link for all the items
+        assert_eq!(adopted, posts.len());
+        assert!(storage.posts.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_adopt_should_read_the_syndication_links_of_the_h_entries() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/posts/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                r#"<html><body><article class="h-entry">
+                <a class="u-syndication" href="{}">Mastodon</a>
+                </article></body></html>"#,
+                StubTarget::status_url(&Network::Mastodon, "m1")
+            )))
+            .mount(&mock_server)
+            .await;
+
+        let feed = "http://example.com/rss.xml";
+        let link = format!("{}/posts/1", mock_server.uri());
+        let post = Item {
+            link: Some(link.clone()),
+            guid: Some(rss::GuidBuilder::default().value(link).build()),
+            ..Default::default()
+        };
+        let items = [(feed.to_string(), vec![post.clone()])].into();
+        let targets: Vec<Box<dyn Target>> = vec![StubTarget::new(Network::Mastodon).into()];
+        let storage = SyndicatedPostStorageStub::default();
+
+        let adopted = adopt(
+            &StubRssClient::new(&items),
+            &[(feed.to_string(), Identity::Guid)],
+            &targets,
+            &NoopClient,
+            Some(&reqwest::Client::new()),
+            &storage,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(adopted, 1);
+        assert_eq!(
+            *storage.posts.lock().unwrap(),
+            [SyndicatedPost::new(
+                Network::Mastodon.into(),
+                "m1",
+                &identified_item(&post)
+            )]
+        );
+    }
+}
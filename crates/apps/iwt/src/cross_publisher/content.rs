@@ -0,0 +1,78 @@
+use rss::Item;
+
+use crate::commons::html;
+use crate::config::ContentSource;
+
+/// The body of an item, the statuses are rendered from it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Body {
+    /// The field the body is taken from
+    pub source: ContentSource,
+    /// HTML, the title is escaped so every body is HTML
+    pub html: String,
+}
+
+/// The first field of the item that is set and not blank, in the order of the sources.
+/// `summary` is the value of `iwt:summary`
+#[must_use]
+pub fn body(item: &Item, summary: Option<&str>, sources: &[ContentSource]) -> Option<Body> {
+    sources.iter().find_map(|source| {
+        let field = match source {
+            ContentSource::Summary => summary,
+            ContentSource::Content => item.content(),
+            ContentSource::Description => item.description(),
+            ContentSource::Title => item.title(),
+        }
+        .filter(|field| !field.trim().is_empty())?;
+
+        Some(Body {
+            source: *source,
+            html: match source {
+                ContentSource::Title => html::escape(field),
+                _ => field.to_string(),
+            },
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rss::Item;
+
+    use crate::config::ContentSource;
+
+    use super::{body, Body};
+
+    #[test]
+    fn test_body_should_use_the_first_field_the_item_has() {
+        let item = Item {
+            title: Some(String::from("Fish & chips")),
+            description: Some(String::from("<p>Description</p>")),
+            content: Some(String::from(" ")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            body(&item, Some("Summary"), &ContentSource::PRECEDENCE),
+            Some(Body {
+                source: ContentSource::Summary,
+                html: String::from("Summary")
+            })
+        );
+        assert_eq!(
+            body(&item, None, &ContentSource::PRECEDENCE),
+            Some(Body {
+                source: ContentSource::Description,
+                html: String::from("<p>Description</p>")
+            })
+        );
+        assert_eq!(
+            body(&item, None, &[ContentSource::Title]),
+            Some(Body {
+                source: ContentSource::Title,
+                html: String::from("Fish &amp; chips")
+            })
+        );
+        assert_eq!(body(&item, None, &[ContentSource::Content]), None);
+    }
+}
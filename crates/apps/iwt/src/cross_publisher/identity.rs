@@ -0,0 +1,343 @@
+use std::fmt::Display;
+
+use ::rss::{Channel, Guid, Item};
+use sha2::{Digest, Sha256};
+
+use crate::config::Identity;
+
+/// Prefix of the identities that are hashed from the item
+pub const HASH_PREFIX: &str = "hash:";
+
+/// The item can't be syndicated, the rest of the feed is
+#[derive(Debug)]
+pub struct InvalidItem {
+    pub feed: String,
+    /// Title or link of the item
+    pub item: String,
+    pub reason: String,
+}
+
+impl Display for InvalidItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "InvalidItem: {} in {}, {}",
+            self.item, self.feed, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidItem {}
+
+/// An item of a feed, with the identity it is recorded by and the link its statuses cite
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentifiedItem {
+    pub identity: String,
+    pub link: String,
+    pub item: Item,
+}
+
+/// A channel with its identified items
+pub struct IdentifiedChannel {
+    /// The channel without its items
+    pub channel: Channel,
+    pub items: Vec<IdentifiedItem>,
+}
+
+/// Identifies the items of the channel. A permalink guid is the link of the item when it has no
+/// link. The items that can't be identified, or have no link to cite, are dropped and returned as
+/// errors
+pub fn identified(
+    mut channel: Channel,
+    feed: &str,
+    identity: Identity,
+) -> (IdentifiedChannel, Vec<InvalidItem>) {
+    let mut items = Vec::new();
+    let mut invalid_items = Vec::new();
+
+    for mut item in std::mem::take(&mut channel.items) {
+        let invalid = |item: &Item, reason: &str| InvalidItem {
+            feed: feed.to_string(),
+            item: label(item),
+            reason: reason.to_string(),
+        };
+
+        let link = link(&item);
+        let Some(identity) = identify(&item, link.as_deref(), identity) else {
+            invalid_items.push(invalid(
+                &item,
+                "it has no guid, link, title, publication date or content to identify it by",
+            ));
+            continue;
+        };
+        let Some(link) = link else {
+            invalid_items.push(invalid(&item, "it has no link"));
+            continue;
+        };
+
+        // The statuses cite the link of the item
+        item.set_link(link.clone());
+        items.push(IdentifiedItem {
+            identity,
+            link,
+            item,
+        });
+    }
+
+    (IdentifiedChannel { channel, items }, invalid_items)
+}
+
+/// The link of the item, or its guid when the guid is a permalink
+fn link(item: &Item) -> Option<String> {
+    non_empty(item.link())
+        .or_else(|| {
+            item.guid()
+                .filter(|guid| guid.is_permalink())
+                .and_then(|guid| non_empty(Some(guid.value())))
+                .filter(|guid| url::Url::parse(guid).is_ok())
+        })
+        .map(String::from)
+}
+
+/// The identity of the item: the guid falls back to the link, and the link to the hash. `None`
+/// when the item has nothing to be hashed
+#[must_use]
+pub fn identify(item: &Item, link: Option<&str>, identity: Identity) -> Option<String> {
+    let guid = || non_empty(item.guid().map(Guid::value));
+
+    match identity {
+        Identity::Guid => guid().or(link).map(String::from).or_else(|| hash(item)),
+        Identity::Link => link.map(String::from).or_else(|| hash(item)),
+        Identity::Hash => hash(item),
+    }
+}
+
+/// Hash of the title, the publication date and the content (or description) of the item
+fn hash(item: &Item) -> Option<String> {
+    let parts = [
+        item.title(),
+        item.pub_date(),
+        item.content().or_else(|| item.description()),
+    ];
+
+    parts
+        .iter()
+        .any(|part| non_empty(*part).is_some())
+        .then(|| {
+            format!(
+                "{HASH_PREFIX}{}",
+                sha256_hex(&parts.map(Option::unwrap_or_default))
+            )
+        })
+}
+
+/// Hex encoded SHA-256 of the parts, they are separated so moving text from one part to the
+/// other changes the hash
+#[must_use]
+pub fn sha256_hex(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            hasher.update([0]);
+        }
+        hasher.update(part);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|value| !value.is_empty())
+}
+
+/// Names the item in the logs
+fn label(item: &Item) -> String {
+    non_empty(item.title())
+        .or_else(|| non_empty(item.link()))
+        .unwrap_or("Untitled item")
+        .to_string()
+}
+
+#[cfg(test)]
+pub mod stubs {
+    use rss::Item;
+
+    use super::IdentifiedItem;
+
+    /// Identifies the item by its guid, the item has to have a link
+    pub fn identified_item(item: &Item) -> IdentifiedItem {
+        let link = item.link().expect("the item has a link").to_string();
+
+        IdentifiedItem {
+            identity: item
+                .guid()
+                .map_or_else(|| link.clone(), |guid| guid.value().to_string()),
+            link,
+            item: item.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rss::{Channel, GuidBuilder, Item};
+
+    use crate::config::Identity;
+
+    use super::{identified, identify, HASH_PREFIX};
+
+    const LINK: &str = "https://example.com/1";
+
+    fn item(guid: Option<&str>, link: Option<&str>, title: Option<&str>) -> Item {
+        Item {
+            guid: guid.map(|guid| {
+                GuidBuilder::default()
+                    .value(guid)
+                    .permalink(guid.starts_with("https://"))
+                    .build()
+            }),
+            link: link.map(String::from),
+            title: title.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_identify_should_fall_back_to_the_link() {
+        let full = item(Some("guid"), Some(LINK), Some("Title"));
+        let without_guid = item(Some(" "), Some(LINK), Some("Title"));
+
+        assert_eq!(
+            identify(&full, Some(LINK), Identity::Guid),
+            Some(String::from("guid"))
+        );
+        assert_eq!(
+            identify(&without_guid, Some(LINK), Identity::Guid),
+            Some(String::from(LINK))
+        );
+        assert_eq!(
+            identify(&full, Some(LINK), Identity::Link),
+            Some(String::from(LINK))
+        );
+    }
+
+    #[test]
+    fn test_identify_should_fall_back_to_the_hash() {
+        let only_title = item(None, None, Some("Title"));
+        let hash = identify(&only_title, None, Identity::Hash);
+
+        assert!(hash.as_ref().unwrap().starts_with(HASH_PREFIX));
+        assert_eq!(identify(&only_title, None, Identity::Guid), hash);
+        assert_eq!(identify(&only_title, None, Identity::Link), hash);
+        assert_eq!(
+            identify(&item(None, None, None), None, Identity::Guid),
+            None
+        );
+    }
+
+    #[test]
+    fn test_identify_should_hash_the_title_date_and_content() {
+        let hash = identify(
+            &item(None, Some(LINK), Some("Title")),
+            Some(LINK),
+            Identity::Hash,
+        )
+        .unwrap();
+
+        assert!(hash.starts_with(HASH_PREFIX));
+        assert_eq!(
+            identify(
+                &item(Some("guid"), Some(LINK), Some("Title")),
+                Some(LINK),
+                Identity::Hash
+            ),
+            Some(hash.clone())
+        );
+        assert_ne!(
+            identify(
+                &item(None, Some(LINK), Some("Other")),
+                Some(LINK),
+                Identity::Hash
+            ),
+            Some(hash)
+        );
+        assert_eq!(
+            identify(&item(None, Some(LINK), None), Some(LINK), Identity::Hash),
+            None
+        );
+    }
+
+    #[test]
+    fn test_identified_should_drop_the_invalid_items() {
+        let channel = Channel {
+            items: vec![
+                item(Some("guid"), Some(LINK), Some("First")),
+                item(Some("guid"), None, Some("Without a link")),
+                item(None, Some("https://example.com/3"), None),
+            ],
+            ..Default::default()
+        };
+
+        let (channel, invalid_items) =
+            identified(channel, "https://example.com/rss.xml", Identity::Guid);
+
+        assert_eq!(
+            channel
+                .items
+                .iter()
+                .map(|item| (item.identity.as_str(), item.link.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("guid", LINK),
+                ("https://example.com/3", "https://example.com/3")
+            ]
+        );
+        assert_eq!(
+            invalid_items
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec!["InvalidItem: Without a link in https://example.com/rss.xml, it has no link"]
+        );
+    }
+
+    #[test]
+    fn test_identified_should_cite_the_permalink_guids_by_any_identity() {
+        let permalink = "https://example.com/2";
+
+        for identity in [Identity::Guid, Identity::Link, Identity::Hash] {
+            let only_a_guid = item(Some(permalink), None, Some("Only a guid"));
+            let expected = identify(&only_a_guid, Some(permalink), identity).unwrap();
+            let channel = Channel {
+                items: vec![
+                    only_a_guid,
+                    item(Some("guid"), None, Some("Not a permalink")),
+                ],
+                ..Default::default()
+            };
+
+            let (channel, invalid_items) =
+                identified(channel, "https://example.com/rss.xml", identity);
+
+            assert_eq!(
+                channel
+                    .items
+                    .iter()
+                    .map(|item| (item.identity.as_str(), item.link.as_str(), item.item.link()))
+                    .collect::<Vec<_>>(),
+                vec![(expected.as_str(), permalink, Some(permalink))]
+            );
+            assert_eq!(
+                invalid_items
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                vec!["InvalidItem: Not a permalink in https://example.com/rss.xml, it has no link"]
+            );
+        }
+    }
+}
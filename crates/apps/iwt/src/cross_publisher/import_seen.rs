@@ -0,0 +1,195 @@
+use std::rc::Rc;
+
+use rusqlite::Connection;
+
+use super::identity::identified;
+use super::preview::parse_network;
+use super::rss;
+use super::syndicated_post::{SqliteSyndycatedPostStorage, Storage, SyndicatedPost};
+use crate::config::{Config, Identity};
+use crate::social::{Account, Network};
+
+#[derive(clap::Args)]
+pub struct ImportSeenArgs {
+    /// Network to mark the items as syndicated to, with its named accounts, can be repeated. All
+    /// the configured accounts by default
+    #[clap(long = "network", value_parser = parse_network)]
+    networks: Vec<Network>,
+    /// Url of the feed, all the configured feeds by default
+    #[clap(long, value_parser)]
+    feed: Option<String>,
+    /// Only log the items that would be marked
+    #[clap(long, action)]
+    dry_run: bool,
+}
+
+pub async fn execute(
+    args: ImportSeenArgs,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let storage = SqliteSyndycatedPostStorage::new(Rc::new(Connection::open(&config.db.path)?));
+    storage.init_table()?;
+
+    let feeds = match args.feed {
+        Some(feed) => vec![feed],
+        None => config.rss.urls.clone(),
+    }
+    .into_iter()
+    .map(|url| {
+        let identity = config.identity(&url);
+        (url, identity)
+    })
+    .collect::<Vec<_>>();
+    let accounts = if args.networks.is_empty() {
+        config.accounts()
+    } else {
+        let named = config
+            .accounts()
+            .into_iter()
+            .filter(|account| account.name.is_some() && args.networks.contains(&account.network))
+            .collect::<Vec<_>>();
+        args.networks
+            .into_iter()
+            .map(Account::from)
+            .chain(named)
+            .collect()
+    };
+
+    let marked = import_seen(
+        &rss::ReqwestClient,
+        &feeds,
+        &accounts,
+        &storage,
+        args.dry_run,
+    )
+    .await?;
+    log::info!("{marked} items are marked as syndicated");
+
+    Ok(())
+}
+
+/// Records the current items of the feeds as syndicated to the accounts without publishing them,
+/// returns the number of the new records
+async fn import_seen<R: rss::Client, S: Storage>(
+    rss_client: &R,
+    feeds: &[(String, Identity)],
+    accounts: &[Account],
+    storage: &S,
+    dry_run: bool,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut marked = 0;
+
+    for (url, identity) in feeds {
+        let (channel, invalid_items) =
+            identified(rss_client.get_channel(url).await?, url, *identity);
+        for invalid_item in invalid_items {
+            log::warn!("{invalid_item}, skipping");
+        }
+
+        for item in &channel.items {
+            let link = &item.link;
+
+            for account in accounts {
+                if storage.find(&item.identity, account)?.is_some() {
+                    log::debug!("{link} |> Has been already syndicated to {account}");
+                    continue;
+                }
+
+                if dry_run {
+                    log::info!("{link} |> Would be marked as syndicated to {account}");
+                } else {
+                    storage.store(SyndicatedPost::seen(account.clone(), item))?;
+                    log::info!("{link} |> Marked as syndicated to {account}");
+                }
+                marked += 1;
+            }
+        }
+    }
+
+    Ok(marked)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Identity;
+    use crate::cross_publisher::stubs::identity::identified_item;
+    use crate::cross_publisher::stubs::rss::{gen_items, StubRssClient};
+    use crate::cross_publisher::stubs::syndycated_post::SyndicatedPostStorageStub;
+    use crate::cross_publisher::syndicated_post::{Storage, SyndicatedPost};
+    use crate::social::{Account, Network};
+
+    use super::import_seen;
+
+    #[tokio::test]
+    async fn test_import_seen_should_mark_the_new_items_for_the_networks() {
+        let feed = "http://example.com/rss.xml";
+        let items = gen_items(&[feed]);
+        let client = StubRssClient::new(&items);
+        let storage = SyndicatedPostStorageStub::default();
+        let items = items.get(feed).unwrap();
+        let german = Account::named(Network::Mastodon, "german");
+        storage
+            .store(SyndicatedPost::new(
+                Network::Mastodon.into(),
+                "1",
+                &identified_item(&items[0]),
+            ))
+            .unwrap();
+
+        let marked = import_seen(
+            &client,
+            &[(feed.to_string(), Identity::Guid)],
+            &[Network::Mastodon.into(), german.clone()],
+            &storage,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(marked, 2 * items.len() - 1);
+        assert_eq!(
+            storage
+                .find(items[1].guid().unwrap().value(), &Network::Mastodon.into())
+                .unwrap(),
+            Some(SyndicatedPost::seen(
+                Network::Mastodon.into(),
+                &identified_item(&items[1])
+            ))
+        );
+        assert_eq!(
+            storage
+                .find(items[0].guid().unwrap().value(), &german)
+                .unwrap(),
+            Some(SyndicatedPost::seen(
+                german.clone(),
+                &identified_item(&items[0])
+            ))
+        );
+        assert_eq!(
+            storage
+                .find(items[1].guid().unwrap().value(), &Network::Twitter.into())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_seen_should_not_store_anything_in_dry_run() {
+        let feed = "http://example.com/rss.xml";
+        let items = gen_items(&[feed]);
+        let storage = SyndicatedPostStorageStub::default();
+
+        let marked = import_seen(
+            &StubRssClient::new(&items),
+            &[(feed.to_string(), Identity::Guid)],
+            &[Network::Mastodon.into(), Network::Twitter.into()],
+            &storage,
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(marked, 2 * items.get(feed).unwrap().len());
+        assert_eq!(*storage.posts.lock().unwrap(), []);
+    }
+}
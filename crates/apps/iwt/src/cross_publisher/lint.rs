@@ -0,0 +1,145 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+
+use ::rss::Channel;
+
+use super::identity::identified;
+use super::rss::{self, Client};
+use super::rss_item_ext::RssItemExt;
+use crate::config::{Config, Identity};
+use crate::IwtError;
+
+#[derive(clap::Args)]
+pub struct LintArgs {
+    /// Url or path of the feed
+    #[clap(value_parser)]
+    feed: String,
+}
+
+/// A problem of an item of the feed
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Guid, link or title of the item
+    pub item: String,
+    /// Path of the invalid element, `None` when the item itself is invalid
+    pub path: Option<String>,
+    pub problem: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{} |> {path}: {}", self.item, self.problem),
+            None => write!(f, "{} |> {}", self.item, self.problem),
+        }
+    }
+}
+
+pub async fn execute(args: LintArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let channel = if args.feed.starts_with("http://") || args.feed.starts_with("https://") {
+        rss::ReqwestClient.get_channel(&args.feed).await?
+    } else {
+        Channel::read_from(BufReader::new(File::open(&args.feed)?))?
+    };
+    let items = channel.items().len();
+
+    let diagnostics = lint(channel, &args.feed, config.identity(&args.feed));
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}");
+    }
+
+    if diagnostics.is_empty() {
+        println!("{items} items of {} are valid", args.feed);
+        Ok(())
+    } else {
+        Err(Box::new(IwtError::new(&format!(
+            "{} problems in the {items} items of {}",
+            diagnostics.len(),
+            args.feed
+        ))))
+    }
+}
+
+/// Validates the items of the channel, the ones that can't be identified and the ones with an IWT
+/// extension that doesn't follow the schema are reported. The items without an extension are
+/// valid
+fn lint(channel: Channel, feed: &str, identity: Identity) -> Vec<Diagnostic> {
+    let (channel, invalid_items) = identified(channel, feed, identity);
+
+    let mut diagnostics = invalid_items
+        .into_iter()
+        .map(|invalid_item| Diagnostic {
+            item: invalid_item.item,
+            path: None,
+            problem: invalid_item.reason,
+        })
+        .collect::<Vec<_>>();
+
+    for identified in &channel.items {
+        if let Err(err) = identified.item.get_iwt_extension() {
+            diagnostics.extend(err.problems.into_iter().map(|problem| Diagnostic {
+                item: err.item.clone(),
+                path: Some(problem.path),
+                problem: problem.problem,
+            }));
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod test {
+    use rss::Channel;
+
+    use crate::config::Identity;
+    use crate::cross_publisher::rss_item_ext::stubs::create_extension;
+    use crate::cross_publisher::stubs::rss::gen_items;
+
+    use super::lint;
+
+    #[test]
+    fn test_lint_should_report_the_problems_of_the_items() {
+        let feed = "http://example.com/rss.xml";
+        let mut items = gen_items(&[feed]).remove(feed).unwrap();
+        items[1].link = None;
+        items[1].guid.as_mut().unwrap().set_permalink(false);
+        items[1].title = Some(String::from("Without a link"));
+        items[2]
+            .extensions
+            .get_mut("iwt")
+            .and_then(|root| root.get_mut("extension"))
+            .unwrap()[0]
+            .children
+            .insert(
+                String::from("visibility"),
+                vec![create_extension("iwt:visibility", "everyone")],
+            );
+        items[3].extensions.clear();
+        let guid = items[2].guid().unwrap().value().to_string();
+
+        let diagnostics = lint(
+            Channel {
+                items,
+                ..Default::default()
+            },
+            feed,
+            Identity::Guid,
+        );
+
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                String::from("Without a link |> it has no link"),
+                format!(
+                    "{guid} |> iwt:extension/iwt:visibility: unknown visibility \"everyone\", it \
+                     should be public, unlisted, private or direct"
+                ),
+            ]
+        );
+    }
+}
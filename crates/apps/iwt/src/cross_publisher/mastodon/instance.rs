@@ -0,0 +1,493 @@
+use std::rc::Rc;
+
+use reqwest::Client;
+use rss::Enclosure;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::IwtError;
+
+const DEFAULT_MAX_CHARACTERS: usize = 500;
+const DEFAULT_CHARACTERS_RESERVED_PER_URL: usize = 23;
+
+/// Limits of a Mastodon (or compatible) instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceLimits {
+    pub max_characters: usize,
+    pub characters_reserved_per_url: usize,
+    pub max_media_attachments: Option<usize>,
+    /// Bytes
+    pub image_size_limit: Option<u64>,
+    /// Bytes
+    pub video_size_limit: Option<u64>,
+    /// Empty when the instance doesn't report it
+    pub supported_mime_types: Vec<String>,
+}
+
+impl Default for InstanceLimits {
+    /// The limits of a vanilla Mastodon instance
+    fn default() -> Self {
+        Self {
+            max_characters: DEFAULT_MAX_CHARACTERS,
+            characters_reserved_per_url: DEFAULT_CHARACTERS_RESERVED_PER_URL,
+            max_media_attachments: None,
+            image_size_limit: None,
+            video_size_limit: None,
+            supported_mime_types: Vec::new(),
+        }
+    }
+}
+
+/// Limits set in the config, they take precedence over the reported ones
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LimitOverrides {
+    pub max_characters: Option<usize>,
+    pub characters_reserved_per_url: Option<usize>,
+}
+
+impl InstanceLimits {
+    #[must_use]
+    pub fn with_overrides(self, overrides: &LimitOverrides) -> Self {
+        Self {
+            max_characters: overrides.max_characters.unwrap_or(self.max_characters),
+            characters_reserved_per_url: overrides
+                .characters_reserved_per_url
+                .unwrap_or(self.characters_reserved_per_url),
+            ..self
+        }
+    }
+
+    /// Checks whether the enclosure could be attached to a status on this instance
+    pub fn validate_media(&self, enclosure: &Enclosure) -> Result<(), String> {
+        let mime_type = enclosure.mime_type();
+
+        if !self.supported_mime_types.is_empty()
+            && !self.supported_mime_types.iter().any(|t| t == mime_type)
+        {
+            return Err(format!("{mime_type} is not supported by the instance"));
+        }
+
+        let size_limit = if mime_type.starts_with("image/") {
+            self.image_size_limit
+        } else if mime_type.starts_with("video/") || mime_type.starts_with("audio/") {
+            self.video_size_limit
+        } else {
+            None
+        };
+
+        match (size_limit, enclosure.length().parse::<u64>()) {
+            (Some(limit), Ok(length)) if length > limit => Err(format!(
+                "{} is {length} bytes, the instance allows {limit} bytes",
+                enclosure.url()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct InstanceResponse {
+    configuration: Option<ConfigurationResponse>,
+    /// Reported by Pleroma and Akkoma in the v1 API
+    max_toot_chars: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+struct ConfigurationResponse {
+    statuses: Option<StatusesResponse>,
+    media_attachments: Option<MediaAttachmentsResponse>,
+}
+
+#[derive(serde::Deserialize)]
+struct StatusesResponse {
+    max_characters: Option<usize>,
+    characters_reserved_per_url: Option<usize>,
+    max_media_attachments: Option<usize>,
+}
+
+#[derive(serde::Deserialize)]
+struct MediaAttachmentsResponse {
+    supported_mime_types: Option<Vec<String>>,
+    image_size_limit: Option<u64>,
+    video_size_limit: Option<u64>,
+}
+
+impl From<InstanceResponse> for InstanceLimits {
+    fn from(response: InstanceResponse) -> Self {
+        let defaults = InstanceLimits::default();
+        let configuration = response.configuration;
+        let statuses = configuration.as_ref().and_then(|c| c.statuses.as_ref());
+        let media = configuration
+            .as_ref()
+            .and_then(|c| c.media_attachments.as_ref());
+
+        Self {
+            max_characters: statuses
+                .and_then(|s| s.max_characters)
+                .or(response.max_toot_chars)
+                .unwrap_or(defaults.max_characters),
+            characters_reserved_per_url: statuses
+                .and_then(|s| s.characters_reserved_per_url)
+                .unwrap_or(defaults.characters_reserved_per_url),
+            max_media_attachments: statuses.and_then(|s| s.max_media_attachments),
+            image_size_limit: media.and_then(|m| m.image_size_limit),
+            video_size_limit: media.and_then(|m| m.video_size_limit),
+            supported_mime_types: media
+                .and_then(|m| m.supported_mime_types.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Queries the limits from the v2 instance API, falls back to v1 for older servers
+pub async fn fetch(
+    http_client: &Client,
+    base_uri: &str,
+) -> Result<InstanceLimits, Box<dyn std::error::Error>> {
+    match fetch_version(http_client, base_uri, "v2").await {
+        Ok(limits) => Ok(limits),
+        Err(err) => {
+            log::debug!("Couldn't query the v2 instance API, trying v1: {err}");
+            fetch_version(http_client, base_uri, "v1").await
+        }
+    }
+}
+
+async fn fetch_version(
+    http_client: &Client,
+    base_uri: &str,
+    version: &str,
+) -> Result<InstanceLimits, Box<dyn std::error::Error>> {
+    let response = http_client
+        .get(format!("{base_uri}/api/{version}/instance"))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(Box::new(IwtError::new(&format!(
+            "Instance API {version} responded with {}",
+            response.status()
+        ))));
+    }
+
+    Ok(response.json::<InstanceResponse>().await?.into())
+}
+
+/// Caches the limits, so they are available when the instance API is not
+pub trait LimitsStorage {
+    fn get(&self, base_uri: &str) -> Result<Option<InstanceLimits>, Box<dyn std::error::Error>>;
+    fn store(
+        &self,
+        base_uri: &str,
+        limits: &InstanceLimits,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+pub struct SqliteLimitsStorage {
+    conn: Rc<Connection>,
+}
+
+impl SqliteLimitsStorage {
+    pub fn new(conn: Rc<Connection>) -> Self {
+        Self { conn }
+    }
+
+    pub fn init_table(&self) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS mastodon_instance (
+                  base_uri TEXT NOT NULL PRIMARY KEY,
+                  max_characters INTEGER NOT NULL,
+                  characters_reserved_per_url INTEGER NOT NULL,
+                  max_media_attachments INTEGER,
+                  image_size_limit INTEGER,
+                  video_size_limit INTEGER,
+                  supported_mime_types TEXT NOT NULL
+                )",
+                (),
+            )
+            .map(|_| ())
+    }
+}
+
+impl LimitsStorage for SqliteLimitsStorage {
+    fn get(&self, base_uri: &str) -> Result<Option<InstanceLimits>, Box<dyn std::error::Error>> {
+        self.conn
+            .query_row(
+                "SELECT max_characters, characters_reserved_per_url, max_media_attachments,
+                    image_size_limit, video_size_limit, supported_mime_types
+                 FROM mastodon_instance WHERE base_uri = :base_uri",
+                &[(":base_uri", base_uri)],
+                |row| {
+                    Ok(InstanceLimits {
+                        max_characters: row.get(0)?,
+                        characters_reserved_per_url: row.get(1)?,
+                        max_media_attachments: row.get(2)?,
+                        image_size_limit: row.get(3)?,
+                        video_size_limit: row.get(4)?,
+                        supported_mime_types: row
+                            .get::<_, String>(5)?
+                            .split_whitespace()
+                            .map(String::from)
+                            .collect(),
+                    })
+                },
+            )
+            .optional()
+            .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
+    }
+
+    fn store(
+        &self,
+        base_uri: &str,
+        limits: &InstanceLimits,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO mastodon_instance (base_uri, max_characters,
+                    characters_reserved_per_url, max_media_attachments, image_size_limit,
+                    video_size_limit, supported_mime_types)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                (
+                    base_uri,
+                    limits.max_characters,
+                    limits.characters_reserved_per_url,
+                    limits.max_media_attachments,
+                    limits.image_size_limit,
+                    limits.video_size_limit,
+                    limits.supported_mime_types.join(" "),
+                ),
+            )
+            .map(|_| ())
+            .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
+    }
+}
+
+/// Queries the limits of the instance and caches them, when the instance API is not available the
+/// cached (or the default) limits are used
+pub async fn resolve<S: LimitsStorage + ?Sized>(
+    http_client: &Client,
+    base_uri: &str,
+    storage: &S,
+    overrides: &LimitOverrides,
+) -> InstanceLimits {
+    let limits = match fetch(http_client, base_uri).await {
+        Ok(limits) => {
+            log::debug!("Mastodon instance limits: {limits:?}");
+            if let Err(err) = storage.store(base_uri, &limits) {
+                log::warn!("Couldn't cache the Mastodon instance limits: {err}");
+            }
+            limits
+        }
+        Err(err) => {
+            let cached = storage.get(base_uri).unwrap_or_else(|err| {
+                log::warn!("Couldn't read the cached Mastodon instance limits: {err}");
+                None
+            });
+
+            log::warn!(
+                "Couldn't query the Mastodon instance limits, using the {} ones: {err}",
+                if cached.is_some() {
+                    "cached"
+                } else {
+                    "default"
+                }
+            );
+
+            cached.unwrap_or_default()
+        }
+    };
+
+    limits.with_overrides(overrides)
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use reqwest::Client;
+    use rss::EnclosureBuilder;
+    use rusqlite::Connection;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{
+        fetch, resolve, InstanceLimits, LimitOverrides, LimitsStorage, SqliteLimitsStorage,
+    };
+
+    fn storage() -> SqliteLimitsStorage {
+        let storage = SqliteLimitsStorage::new(Rc::new(Connection::open_in_memory().unwrap()));
+        storage.init_table().unwrap();
+        storage
+    }
+
+    fn v2_response() -> serde_json::Value {
+        json!({
+            "domain": "example.com",
+            "configuration": {
+                "statuses": {
+                    "max_characters": 5000,
+                    "max_media_attachments": 4,
+                    "characters_reserved_per_url": 23
+                },
+                "media_attachments": {
+                    "supported_mime_types": ["image/jpeg", "image/png", "video/mp4"],
+                    "image_size_limit": 10_485_760,
+                    "video_size_limit": 41_943_040
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_fetch_should_read_the_v2_api() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/instance"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(v2_response()))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let limits = fetch(&Client::new(), &mock_server.uri()).await.unwrap();
+
+        assert_eq!(
+            limits,
+            InstanceLimits {
+                max_characters: 5000,
+                characters_reserved_per_url: 23,
+                max_media_attachments: Some(4),
+                image_size_limit: Some(10_485_760),
+                video_size_limit: Some(41_943_040),
+                supported_mime_types: vec![
+                    String::from("image/jpeg"),
+                    String::from("image/png"),
+                    String::from("video/mp4")
+                ],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_should_fall_back_to_the_v1_api() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/instance"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/instance"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "max_toot_chars": 5000 })),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let limits = fetch(&Client::new(), &mock_server.uri()).await.unwrap();
+
+        assert_eq!(limits.max_characters, 5000);
+        assert_eq!(limits.characters_reserved_per_url, 23);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_should_cache_the_limits() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/instance"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(v2_response()))
+            .mount(&mock_server)
+            .await;
+        let storage = storage();
+
+        let limits = resolve(
+            &Client::new(),
+            &mock_server.uri(),
+            &storage,
+            &LimitOverrides::default(),
+        )
+        .await;
+
+        assert_eq!(storage.get(&mock_server.uri()).unwrap(), Some(limits));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_should_use_the_cached_limits_when_the_instance_api_fails() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+        let storage = storage();
+        let cached = InstanceLimits {
+            max_characters: 1000,
+            ..InstanceLimits::default()
+        };
+        storage.store(&mock_server.uri(), &cached).unwrap();
+
+        let limits = resolve(
+            &Client::new(),
+            &mock_server.uri(),
+            &storage,
+            &LimitOverrides::default(),
+        )
+        .await;
+
+        assert_eq!(limits, cached);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_should_apply_the_overrides() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&mock_server)
+            .await;
+
+        let limits = resolve(
+            &Client::new(),
+            &mock_server.uri(),
+            &storage(),
+            &LimitOverrides {
+                max_characters: Some(3000),
+                characters_reserved_per_url: None,
+            },
+        )
+        .await;
+
+        assert_eq!(limits.max_characters, 3000);
+        assert_eq!(limits.characters_reserved_per_url, 23);
+    }
+
+    #[test]
+    fn test_validate_media() {
+        let limits = InstanceLimits {
+            image_size_limit: Some(1000),
+            supported_mime_types: vec![String::from("image/png")],
+            ..InstanceLimits::default()
+        };
+        let enclosure = |mime_type: &str, length: &str| {
+            EnclosureBuilder::default()
+                .url("https://example.com/media")
+                .mime_type(mime_type)
+                .length(length)
+                .build()
+        };
+
+        assert!(limits
+            .validate_media(&enclosure("image/png", "1000"))
+            .is_ok());
+        assert!(limits
+            .validate_media(&enclosure("image/png", "1001"))
+            .is_err());
+        assert!(limits
+            .validate_media(&enclosure("image/webp", "100"))
+            .is_err());
+        assert!(InstanceLimits::default()
+            .validate_media(&enclosure("image/webp", "100000000"))
+            .is_ok());
+    }
+}
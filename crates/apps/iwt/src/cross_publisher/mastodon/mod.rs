@@ -1,35 +1,393 @@
 use std::rc::Rc;
 
-use super::rss_item_ext::IwtRssExtension;
-use super::syndicated_post::SyndicatedPost;
-use super::target::Target;
-use crate::commons::{text, url_shortener};
-use crate::social::Network;
+use super::adopt;
+use super::render::{self, StatusRenderer};
+use super::rss_item_ext::{Interaction, IwtRssExtension};
+use super::target::{Media, PublishedStatus, Status, Target};
+use crate::commons::html;
+use crate::commons::rate_limit::RateLimiter;
+use crate::commons::text::LengthModel;
+use crate::commons::url_shortener;
+use crate::social::{Account, Network, Visibility};
+use crate::IwtError;
+use async_mutex::Mutex;
 use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
 use futures::TryFutureExt;
+use instance::{InstanceLimits, LimitOverrides, LimitsStorage};
 use oauth2::AccessToken;
-use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use rss::Item;
 
-pub struct Mastodon<USClient: url_shortener::Client> {
+pub mod instance;
+
+/// Mastodon rejects statuses scheduled sooner
+const MIN_SCHEDULE_AHEAD_MINUTES: i64 = 5;
+
+pub struct Mastodon<USClient: url_shortener::Client + ?Sized> {
     base_uri: String,
     access_token: AccessToken,
+    /// The default account of Mastodon, unless it is named
+    account: Account,
     http_client: Client,
     url_shortener_client: Rc<USClient>,
+    limits_storage: Rc<dyn LimitsStorage>,
+    limit_overrides: LimitOverrides,
+    // Resolved by `prepare`, when the registry builds the target
+    limits: Mutex<Option<InstanceLimits>>,
+    renderer: StatusRenderer,
+    /// Hand the not yet due posts to the instance as scheduled statuses
+    schedule_on_server: bool,
+    rate_limiter: RateLimiter,
 }
 
-impl<USClient: url_shortener::Client> Mastodon<USClient> {
+impl<USClient: url_shortener::Client + ?Sized> Mastodon<USClient> {
     pub fn new(
         base_uri: String,
         access_token: AccessToken,
         url_shortener_client: Rc<USClient>,
+        limits_storage: Rc<dyn LimitsStorage>,
+        limit_overrides: LimitOverrides,
+        renderer: StatusRenderer,
+        schedule_on_server: bool,
     ) -> Self {
         Self {
             base_uri,
             access_token,
+            account: Account::from(Network::Mastodon),
             http_client: Client::new(),
             url_shortener_client,
+            limits_storage,
+            limit_overrides,
+            limits: Mutex::new(None),
+            renderer,
+            schedule_on_server,
+            rate_limiter: RateLimiter::default(),
+        }
+    }
+
+    /// The target of a named account, see `mastodon.accounts` in the config
+    #[must_use]
+    pub fn named(self, name: &str) -> Self {
+        Self {
+            account: Account::named(Network::Mastodon, name),
+            ..self
+        }
+    }
+
+    /// Sends the request to the instance, unless its rate limit is exhausted
+    async fn send(&self, request: RequestBuilder) -> Result<Response, Box<dyn std::error::Error>> {
+        self.rate_limiter.check()?;
+        let response = request.send().await?;
+        self.rate_limiter.record(&response)?;
+
+        Ok(response)
+    }
+
+    async fn limits(&self) -> InstanceLimits {
+        let mut limits = self.limits.lock().await;
+
+        if limits.is_none() {
+            *limits = Some(
+                instance::resolve(
+                    &self.http_client,
+                    &self.base_uri,
+                    self.limits_storage.as_ref(),
+                    &self.limit_overrides,
+                )
+                .await,
+            );
+        }
+
+        limits.clone().unwrap_or_default()
+    }
+
+    /// Uploads the media, returns the id of the media attachment
+    async fn upload_media(&self, media: &Media) -> Result<String, Box<dyn std::error::Error>> {
+        let content = self
+            .http_client
+            .get(&media.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let file_name = media
+            .url
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("media")
+            .to_string();
+        let file = Part::bytes(content.to_vec())
+            .file_name(file_name)
+            .mime_str(&media.mime_type)?;
+
+        let response = self
+            .send(
+                self.http_client
+                    .post(format!("{}/api/v2/media", self.base_uri))
+                    .bearer_auth(self.access_token.secret().clone())
+                    .multipart(Form::new().part("file", file)),
+            )
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json::<MastodonResponse>().await?.id)
+    }
+
+    /// Renders the status, the photo is attached when the instance accepts it, otherwise it is
+    /// linked
+    async fn render_status(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+        attach_photo: bool,
+    ) -> Result<Status, Box<dyn std::error::Error>> {
+        let link = post
+            .link()
+            .ok_or_else(|| IwtError::new("The post has no link to cite"))?;
+        let permashort_citation = self.url_shortener_client.put_uri(link).await?;
+
+        let limits = self.limits().await;
+
+        let mut media = Vec::new();
+        let mut linked_media = Vec::new();
+        if let Some(photo) = render::photo(post, extension) {
+            match limits.validate_media(photo) {
+                Ok(()) if attach_photo => media.push(Media {
+                    url: photo.url().to_string(),
+                    mime_type: photo.mime_type().to_string(),
+                }),
+                Ok(()) => linked_media.push(photo.url().to_string()),
+                Err(problem) => {
+                    log::warn!(
+                        "{} |> The photo is not accepted by the instance, linking it: {problem}",
+                        post.link().unwrap_or_default()
+                    );
+                    linked_media.push(photo.url().to_string());
+                }
+            }
         }
+
+        let text = self.renderer.render(
+            post,
+            extension,
+            &permashort_citation,
+            &linked_media,
+            limits.max_characters,
+            &LengthModel::Mastodon {
+                characters_reserved_per_url: limits.characters_reserved_per_url,
+            },
+        )?;
+
+        Ok(Status {
+            media,
+            ..Status::new(text, extension)
+        })
+    }
+
+    /// Renders the status and uploads its media, the media is linked when the upload fails
+    async fn render_and_upload(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+    ) -> Result<(Status, Vec<String>), Box<dyn std::error::Error>> {
+        let status = self.render_status(post, extension, true).await?;
+
+        match self.upload_all(&status.media).await {
+            Ok(media_ids) => Ok((status, media_ids)),
+            Err(problem) => {
+                log::warn!(
+                    "{} |> The photo can't be attached on Mastodon, linking it: {problem}",
+                    post.link().unwrap_or_default()
+                );
+                Ok((
+                    self.render_status(post, extension, false).await?,
+                    Vec::new(),
+                ))
+            }
+        }
+    }
+
+    async fn upload_all(&self, media: &[Media]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut media_ids = Vec::new();
+        for media in media {
+            media_ids.push(self.upload_media(media).await?);
+        }
+
+        Ok(media_ids)
+    }
+
+    /// Posts the status, or schedules it when `scheduled_at` is set, returns the id of the
+    /// (scheduled) status. Responses to statuses known by the instance are native replies,
+    /// reblogs and favourites
+    async fn post_status(
+        &self,
+        status: &Status,
+        media_ids: Vec<String>,
+        scheduled_at: Option<DateTime<Utc>>,
+        idempotency_key: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut in_reply_to_id = None;
+        if let Some(interaction) = &status.interaction {
+            match (interaction, self.find_status(interaction.url()).await?) {
+                (Interaction::Reply(_), Some(status_id)) => in_reply_to_id = Some(status_id),
+                (Interaction::Repost(_), Some(status_id)) => {
+                    return self.act_on_status(&status_id, "reblog").await
+                }
+                (Interaction::Like(_), Some(status_id)) => {
+                    return self.act_on_status(&status_id, "favourite").await
+                }
+                (_, None) => log::info!(
+                    "{} is not found on Mastodon, posting a status instead",
+                    interaction.url()
+                ),
+            }
+        }
+
+        let mut request = self
+            .http_client
+            .post(format!("{}/api/v1/statuses", self.base_uri))
+            .bearer_auth(self.access_token.secret().clone());
+        if let Some(idempotency_key) = idempotency_key {
+            request = request.header("Idempotency-Key", idempotency_key);
+        }
+
+        self.send(request.json(&UpdateStatusRequest {
+            status: status.text.clone(),
+            spoiler_text: status.content_warning.clone(),
+            media_ids,
+            in_reply_to_id,
+            visibility: status.visibility,
+            language: status.language.as_deref().map(language_code),
+            sensitive: status.sensitive,
+            scheduled_at: scheduled_at.map(|at| at.to_rfc3339()),
+        }))
+        .and_then(|response| async {
+            let body = response.text().await.expect("Response body expected");
+
+            serde_json::from_str::<MastodonResponse>(&body)
+                .map(|response| response.id)
+                .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
+        })
+        .await
+    }
+
+    /// The last statuses of the account, reblogs excluded
+    async fn account_statuses(
+        &self,
+    ) -> Result<Vec<AccountStatusResponse>, Box<dyn std::error::Error>> {
+        let account = self
+            .send(
+                self.http_client
+                    .get(format!(
+                        "{}/api/v1/accounts/verify_credentials",
+                        self.base_uri
+                    ))
+                    .bearer_auth(self.access_token.secret().clone()),
+            )
+            .await?
+            .error_for_status()?
+            .json::<MastodonResponse>()
+            .await?;
+
+        Ok(self
+            .send(
+                self.http_client
+                    .get(format!(
+                        "{}/api/v1/accounts/{}/statuses",
+                        self.base_uri, account.id
+                    ))
+                    .query(&[("limit", "40"), ("exclude_reblogs", "true")])
+                    .bearer_auth(self.access_token.secret().clone()),
+            )
+            .await?
+            .error_for_status()?
+            .json::<Vec<AccountStatusResponse>>()
+            .await?)
+    }
+
+    /// Finds the status that was published from a scheduled status, i.e. the first status of the
+    /// account from the scheduled time that cites the post, the statuses shared by other means
+    /// in the meantime are skipped
+    async fn find_published(
+        &self,
+        link: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        // Publishing can start a bit early, depending on the clock of the instance
+        let from = at - Duration::minutes(1);
+
+        let mut statuses = self
+            .account_statuses()
+            .await?
+            .into_iter()
+            .filter_map(|status| {
+                DateTime::parse_from_rfc3339(&status.created_at)
+                    .ok()
+                    .map(|created_at| (created_at.with_timezone(&Utc), status))
+            })
+            .filter(|(created_at, _)| *created_at >= from)
+            .collect::<Vec<_>>();
+        statuses.sort_by_key(|(created_at, _)| *created_at);
+
+        let statuses = statuses
+            .into_iter()
+            .map(|(_, status)| published_status(status))
+            .collect::<Vec<_>>();
+        let citations = adopt::citations(link, self.url_shortener_client.as_ref()).await;
+
+        Ok(adopt::citing_status(&statuses, &citations))
+    }
+
+    /// Looks up the local id of a status by its url, remote statuses are fetched by the instance
+    async fn find_status(&self, url: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let response = self
+            .send(
+                self.http_client
+                    .get(format!("{}/api/v2/search", self.base_uri))
+                    .query(&[
+                        ("q", url),
+                        ("type", "statuses"),
+                        ("resolve", "true"),
+                        ("limit", "1"),
+                    ])
+                    .bearer_auth(self.access_token.secret().clone()),
+            )
+            .await?
+            .error_for_status()?;
+
+        Ok(response
+            .json::<SearchResponse>()
+            .await?
+            .statuses
+            .into_iter()
+            .next()
+            .map(|status| status.id))
+    }
+
+    /// Reblogs or favourites the status, returns the id of the resulting status
+    async fn act_on_status(
+        &self,
+        status_id: &str,
+        action: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let response = self
+            .send(
+                self.http_client
+                    .post(format!(
+                        "{}/api/v1/statuses/{status_id}/{action}",
+                        self.base_uri
+                    ))
+                    .bearer_auth(self.access_token.secret().clone()),
+            )
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json::<MastodonResponse>().await?.id)
     }
 }
 
@@ -37,6 +395,27 @@ impl<USClient: url_shortener::Client> Mastodon<USClient> {
 struct UpdateStatusRequest {
     status: String,
     spoiler_text: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    media_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    in_reply_to_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visibility: Option<Visibility>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    sensitive: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_at: Option<String>,
+}
+
+/// Mastodon accepts ISO 639 codes, feeds often use language tags like `en-us`
+fn language_code(language: &str) -> String {
+    language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
 }
 
 #[derive(serde::Deserialize)]
@@ -44,48 +423,776 @@ struct MastodonResponse {
     id: String,
 }
 
+#[derive(serde::Deserialize)]
+struct SearchResponse {
+    statuses: Vec<MastodonResponse>,
+}
+
+#[derive(serde::Deserialize)]
+struct AccountStatusResponse {
+    id: String,
+    created_at: String,
+    /// HTML of the status
+    #[serde(default)]
+    content: String,
+    card: Option<CardResponse>,
+}
+
+#[derive(serde::Deserialize)]
+struct CardResponse {
+    url: String,
+}
+
+/// The status with the links it cites, in its text or by its preview card
+fn published_status(status: AccountStatusResponse) -> PublishedStatus {
+    let mut links = html::links(&status.content);
+    if let Some(card) = status.card {
+        links.push(card.url);
+    }
+
+    PublishedStatus {
+        id: status.id,
+        links,
+    }
+}
+
+/// Id of the status of the instance the url points to, e.g. `https://mastodon.social/@someone/1234`
+/// or `https://mastodon.social/users/someone/statuses/1234`
+fn status_id(base_uri: &str, url: &str) -> Option<String> {
+    let base_uri = url::Url::parse(base_uri).ok()?;
+    let url = url::Url::parse(url).ok()?;
+
+    if url.host_str() != base_uri.host_str() {
+        return None;
+    }
+
+    let id = match url.path_segments()?.collect::<Vec<_>>()[..] {
+        [account, id] if account.starts_with('@') => id,
+        ["users", _, "statuses", id] => id,
+        _ => return None,
+    };
+
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_digit())).then(|| id.to_string())
+}
+
 #[async_trait(?Send)]
-impl<WHClient: url_shortener::Client> Target for Mastodon<WHClient> {
+impl<WHClient: url_shortener::Client + ?Sized> Target for Mastodon<WHClient> {
+    async fn render<'a>(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+    ) -> Result<Status, Box<dyn std::error::Error + 'a>> {
+        Ok(self.render_status(post, extension, true).await?)
+    }
+
+    async fn publish_status<'a>(
+        &self,
+        status: &Status,
+        idempotency_key: &str,
+    ) -> Result<String, Box<dyn std::error::Error + 'a>> {
+        // The status is published as it is rendered, so it fails without its media
+        let media_ids = self.upload_all(&status.media).await?;
+
+        Ok(self
+            .post_status(status, media_ids, None, Some(idempotency_key))
+            .await?)
+    }
+
     async fn publish<'a>(
         &self,
         post: &Item,
         extension: &IwtRssExtension,
-    ) -> Result<SyndicatedPost, Box<dyn std::error::Error + 'a>> {
+        idempotency_key: &str,
+    ) -> Result<String, Box<dyn std::error::Error + 'a>> {
         log::debug!("processing post: {:?},\nextension: {:?}", post, extension);
 
-        let permashort_citation = self
-            .url_shortener_client
-            .put_uri(post.link.as_ref().unwrap())
+        let (status, media_ids) = self.render_and_upload(post, extension).await?;
+
+        Ok(self
+            .post_status(&status, media_ids, None, Some(idempotency_key))
+            .await?)
+    }
+
+    async fn schedule<'a>(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+        at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        // Reblogs and favourites can't be scheduled, neither can statuses due soon
+        let schedulable = !matches!(
+            extension.interaction,
+            Some(Interaction::Repost(_) | Interaction::Like(_))
+        );
+        if !self.schedule_on_server
+            || !schedulable
+            || at < Utc::now() + Duration::minutes(MIN_SCHEDULE_AHEAD_MINUTES)
+        {
+            return Ok(None);
+        }
+
+        let (status, media_ids) = self.render_and_upload(post, extension).await?;
+
+        Ok(Some(
+            self.post_status(&status, media_ids, Some(at), None).await?,
+        ))
+    }
+
+    async fn find_scheduled<'a>(
+        &self,
+        post: &Item,
+        scheduled_id: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        let response = self
+            .send(
+                self.http_client
+                    .get(format!(
+                        "{}/api/v1/scheduled_statuses/{scheduled_id}",
+                        self.base_uri
+                    ))
+                    .bearer_auth(self.access_token.secret().clone()),
+            )
             .await?;
 
-        let status = text::shorten_with_permashort_citation(
-            post.description().unwrap(),
-            500,
-            &permashort_citation,
-            &extension.tags,
+        if response.status() != StatusCode::NOT_FOUND {
+            response.error_for_status()?;
+            return Ok(None);
+        }
+
+        // Scheduled statuses are removed when they are published
+        let link = post.link().unwrap_or_default();
+        match self.find_published(link, at).await? {
+            Some(id) => Ok(Some(id)),
+            None => {
+                log::warn!(
+                    "{link} |> The scheduled status {scheduled_id} is gone, but no status citing \
+                     the post is found from {at}"
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    async fn recent_statuses<'a>(
+        &self,
+    ) -> Result<Vec<PublishedStatus>, Box<dyn std::error::Error + 'a>> {
+        Ok(self
+            .account_statuses()
+            .await?
+            .into_iter()
+            .map(published_status)
+            .collect())
+    }
+
+    async fn find_copy<'a>(
+        &self,
+        link: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        let citations = adopt::citations(link, self.url_shortener_client.as_ref()).await;
+
+        Ok(adopt::citing_status(
+            &self.recent_statuses().await?,
+            &citations,
+        ))
+    }
+
+    fn status_id(&self, url: &str) -> Option<String> {
+        status_id(&self.base_uri, url)
+    }
+
+    async fn prepare<'a>(&self) -> Result<(), Box<dyn std::error::Error + 'a>> {
+        let limits = self.limits().await;
+        log::info!(
+            "{} allows {} characters, links count as {}",
+            self.base_uri,
+            limits.max_characters,
+            limits.characters_reserved_per_url
         );
 
-        self.http_client
-            // TODO: make mastodon instance configurable
-            .post(format!("{}/api/v1/statuses", self.base_uri))
-            .bearer_auth(self.access_token.secret().clone())
-            .json(&UpdateStatusRequest {
-                status,
-                spoiler_text: extension.content_warning.clone(),
-            })
-            .send()
-            .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
-            .and_then(|response| async {
-                let body = response.text().await.expect("Response body expected");
+        Ok(())
+    }
 
-                serde_json::from_str::<MastodonResponse>(&body)
-                    .map(|response| SyndicatedPost::new(Network::Mastodon, &response.id, post))
-                    .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
-            })
-            .await
+    async fn length_limit(&self) -> (LengthModel, usize) {
+        let limits = self.limits().await;
+
+        (
+            LengthModel::Mastodon {
+                characters_reserved_per_url: limits.characters_reserved_per_url,
+            },
+            limits.max_characters,
+        )
     }
 
     fn network(&self) -> Network {
         Network::Mastodon
     }
+
+    fn account(&self) -> Account {
+        self.account.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use chrono::{DateTime, Duration, Utc};
+    use oauth2::AccessToken;
+    use rss::{EnclosureBuilder, GuidBuilder, Item, ItemBuilder};
+    use rusqlite::Connection;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{body_json, header, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::instance::{LimitOverrides, SqliteLimitsStorage};
+    use super::{status_id, Mastodon};
+    use crate::commons::html::LinkPolicy;
+    use crate::commons::rate_limit::RateLimited;
+    use crate::commons::template::Templates;
+    use crate::commons::url_shortener::NoopClient;
+    use crate::config::ContentSource;
+    use crate::cross_publisher::render::StatusRenderer;
+    use crate::cross_publisher::rss_item_ext::{Interaction, IwtRssExtension, PostKind};
+    use crate::cross_publisher::target::{PublishedStatus, Target};
+    use crate::social::Visibility;
+
+    fn mastodon(base_uri: &str) -> Mastodon<NoopClient> {
+        let storage = SqliteLimitsStorage::new(Rc::new(Connection::open_in_memory().unwrap()));
+        storage.init_table().unwrap();
+
+        Mastodon::new(
+            base_uri.to_string(),
+            AccessToken::new(String::from("token")),
+            Rc::new(NoopClient),
+            Rc::new(storage),
+            LimitOverrides::default(),
+            StatusRenderer::new(
+                Rc::new(Templates::new(&BTreeMap::new()).unwrap()),
+                None,
+                LinkPolicy::Inline,
+            ),
+            true,
+        )
+    }
+
+    fn photo(base_uri: &str) -> (Item, IwtRssExtension) {
+        let item = ItemBuilder::default()
+            .guid(Some(GuidBuilder::default().value("1").build()))
+            .link(Some(String::from("https://example.com/posts/1")))
+            .description(Some(String::from("<p>Caption</p>")))
+            .enclosure(Some(
+                EnclosureBuilder::default()
+                    .url(format!("{base_uri}/media/photo.jpg"))
+                    .mime_type("image/jpeg")
+                    .length("4")
+                    .build(),
+            ))
+            .build();
+        let extension = IwtRssExtension {
+            target_networks: Vec::new(),
+            target_accounts: Vec::new(),
+            content_warning: None,
+            tags: Vec::new(),
+            template: None,
+            kind: PostKind::Photo,
+            interaction: None,
+            visibility: None,
+            language: None,
+            sensitive: None,
+            publish_at: None,
+            summary: None,
+            content: ContentSource::PRECEDENCE.to_vec(),
+        };
+
+        (item, extension)
+    }
+
+    fn response(interaction: Interaction) -> (Item, IwtRssExtension) {
+        let item = ItemBuilder::default()
+            .guid(Some(GuidBuilder::default().value("1").build()))
+            .link(Some(String::from("https://example.com/posts/1")))
+            .description(Some(String::from("<p>Indeed</p>")))
+            .build();
+        let extension = IwtRssExtension {
+            target_networks: Vec::new(),
+            target_accounts: Vec::new(),
+            content_warning: None,
+            tags: Vec::new(),
+            template: None,
+            kind: PostKind::Note,
+            interaction: Some(interaction),
+            visibility: None,
+            language: None,
+            sensitive: None,
+            publish_at: None,
+            summary: None,
+            content: ContentSource::PRECEDENCE.to_vec(),
+        };
+
+        (item, extension)
+    }
+
+    async fn mount_search(mock_server: &MockServer, statuses: serde_json::Value) {
+        Mock::given(method("GET"))
+            .and(path("/api/v2/search"))
+            .and(query_param("q", "https://mastodon.social/@someone/1"))
+            .and(query_param("resolve", "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "statuses": statuses })))
+            .expect(1)
+            .mount(mock_server)
+            .await;
+    }
+
+    async fn mount_photo(mock_server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/media/photo.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![1, 2, 3, 4]))
+            .mount(mock_server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_attach_the_photo() {
+        let mock_server = MockServer::start().await;
+        mount_photo(&mock_server).await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/media"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "42"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_json(json!({
+                "status": "Caption\n(example.com posts/1)",
+                "spoiler_text": null,
+                "media_ids": ["42"]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, extension) = photo(&mock_server.uri());
+        let id = mastodon(&mock_server.uri())
+            .publish(&item, &extension, "key")
+            .await
+            .unwrap();
+
+        assert_eq!(id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_link_the_photo_when_the_upload_fails() {
+        let mock_server = MockServer::start().await;
+        mount_photo(&mock_server).await;
+        Mock::given(method("POST"))
+            .and(path("/api/v2/media"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_json(json!({
+                "status": format!(
+                    "{}/media/photo.jpg\nCaption\n(example.com posts/1)",
+                    mock_server.uri()
+                ),
+                "spoiler_text": null
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, extension) = photo(&mock_server.uri());
+        let id = mastodon(&mock_server.uri())
+            .publish(&item, &extension, "key")
+            .await
+            .unwrap();
+
+        assert_eq!(id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_hold_back_while_rate_limited() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "300"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, mut extension) = response(Interaction::Reply(String::new()));
+        extension.interaction = None;
+        let mastodon = mastodon(&mock_server.uri());
+
+        for _ in 0..2 {
+            let err = mastodon
+                .publish(&item, &extension, "key")
+                .await
+                .unwrap_err();
+            let rate_limited = err.downcast_ref::<RateLimited>().unwrap();
+
+            assert!(rate_limited.until > Utc::now() + Duration::minutes(4));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_reply_natively() {
+        let mock_server = MockServer::start().await;
+        mount_search(&mock_server, json!([{"id": "7"}])).await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_json(json!({
+                "status": "Indeed\n(example.com posts/1)",
+                "spoiler_text": null,
+                "in_reply_to_id": "7"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, extension) = response(Interaction::Reply(String::from(
+            "https://mastodon.social/@someone/1",
+        )));
+        let id = mastodon(&mock_server.uri())
+            .publish(&item, &extension, "key")
+            .await
+            .unwrap();
+
+        assert_eq!(id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_reblog_and_favourite_natively() {
+        for (interaction, action, id) in [
+            (
+                Interaction::Repost as fn(String) -> Interaction,
+                "reblog",
+                "8",
+            ),
+            (Interaction::Like, "favourite", "7"),
+        ] {
+            let mock_server = MockServer::start().await;
+            mount_search(&mock_server, json!([{"id": "7"}])).await;
+            Mock::given(method("POST"))
+                .and(path(format!("/api/v1/statuses/7/{action}")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": id })))
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+            Mock::given(method("POST"))
+                .and(path("/api/v1/statuses"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+                .expect(0)
+                .mount(&mock_server)
+                .await;
+
+            let (item, extension) = response(interaction(String::from(
+                "https://mastodon.social/@someone/1",
+            )));
+            let id = mastodon(&mock_server.uri())
+                .publish(&item, &extension, "key")
+                .await
+                .unwrap();
+
+            assert_eq!(id, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_post_a_status_when_the_responded_status_is_not_found() {
+        let mock_server = MockServer::start().await;
+        mount_search(&mock_server, json!([])).await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_json(json!({
+                "status": "Indeed\n(example.com posts/1)",
+                "spoiler_text": null
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, extension) = response(Interaction::Like(String::from(
+            "https://mastodon.social/@someone/1",
+        )));
+        let id = mastodon(&mock_server.uri())
+            .publish(&item, &extension, "key")
+            .await
+            .unwrap();
+
+        assert_eq!(id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_send_the_status_metadata() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_json(json!({
+                "status": "Indeed\n(example.com posts/1)",
+                "spoiler_text": null,
+                "visibility": "unlisted",
+                "language": "en",
+                "sensitive": true
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, mut extension) = response(Interaction::Reply(String::new()));
+        extension.interaction = None;
+        extension.visibility = Some(Visibility::Unlisted);
+        extension.language = Some(String::from("en-US"));
+        extension.sensitive = Some(true);
+
+        let id = mastodon(&mock_server.uri())
+            .publish(&item, &extension, "key")
+            .await
+            .unwrap();
+
+        assert_eq!(id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_send_the_idempotency_key() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(header("Idempotency-Key", "a1b2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "1"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, mut extension) = response(Interaction::Reply(String::new()));
+        extension.interaction = None;
+
+        let id = mastodon(&mock_server.uri())
+            .publish(&item, &extension, "a1b2")
+            .await
+            .unwrap();
+
+        assert_eq!(id, "1");
+    }
+
+    #[tokio::test]
+    async fn test_schedule_should_create_a_scheduled_status() {
+        let mock_server = MockServer::start().await;
+        let at = DateTime::parse_from_rfc3339("2099-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Mock::given(method("POST"))
+            .and(path("/api/v1/statuses"))
+            .and(body_json(json!({
+                "status": "Indeed\n(example.com posts/1)",
+                "spoiler_text": null,
+                "scheduled_at": "2099-01-01T10:00:00+00:00"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "3"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, mut extension) = response(Interaction::Reply(String::new()));
+        extension.interaction = None;
+        let mastodon = mastodon(&mock_server.uri());
+
+        assert_eq!(
+            mastodon.schedule(&item, &extension, at).await.unwrap(),
+            Some(String::from("3"))
+        );
+        assert_eq!(
+            mastodon
+                .schedule(&item, &extension, Utc::now() + Duration::minutes(1))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_scheduled_should_find_the_published_status() {
+        let mock_server = MockServer::start().await;
+        let at = DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Mock::given(method("GET"))
+            .and(path("/api/v1/scheduled_statuses/3"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/accounts/verify_credentials"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "100"})))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/accounts/100/statuses"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {
+                    "id": "13",
+                    "created_at": "2024-01-01T11:00:00.000Z",
+                    "content": "<p>Again <a href=\"https://example.com/posts/1\">a post</a></p>"
+                },
+                {
+                    "id": "12",
+                    "created_at": "2024-01-01T10:00:05.000Z",
+                    "content": "<p>New post <a href=\"https://example.com/posts/1\">a post</a></p>"
+                },
+                {"id": "11", "created_at": "2024-01-01T10:00:01.000Z", "content": "<p>By hand</p>"},
+                {
+                    "id": "10",
+                    "created_at": "2024-01-01T09:00:00.000Z",
+                    "content": "<p>Earlier <a href=\"https://example.com/posts/1\">a post</a></p>"
+                }
+            ])))
+            .mount(&mock_server)
+            .await;
+
+        let (item, _) = response(Interaction::Reply(String::new()));
+        let id = mastodon(&mock_server.uri())
+            .find_scheduled(&item, "3", at)
+            .await
+            .unwrap();
+
+        assert_eq!(id, Some(String::from("12")));
+    }
+
+    #[tokio::test]
+    async fn test_find_scheduled_should_not_take_the_statuses_that_dont_cite_the_post() {
+        let mock_server = MockServer::start().await;
+        let at = DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Mock::given(method("GET"))
+            .and(path("/api/v1/scheduled_statuses/3"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/accounts/verify_credentials"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "100"})))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/accounts/100/statuses"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {"id": "11", "created_at": "2024-01-01T10:00:01.000Z", "content": "<p>By hand</p>"}
+            ])))
+            .mount(&mock_server)
+            .await;
+
+        let (item, _) = response(Interaction::Reply(String::new()));
+        let id = mastodon(&mock_server.uri())
+            .find_scheduled(&item, "3", at)
+            .await
+            .unwrap();
+
+        assert_eq!(id, None);
+    }
+
+    #[tokio::test]
+    async fn test_find_scheduled_should_wait_for_the_scheduled_status() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/scheduled_statuses/3"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "3"})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (item, _) = response(Interaction::Reply(String::new()));
+        let id = mastodon(&mock_server.uri())
+            .find_scheduled(&item, "3", Utc::now())
+            .await
+            .unwrap();
+
+        assert_eq!(id, None);
+    }
+
+    #[tokio::test]
+    async fn test_recent_statuses_should_list_the_links_of_the_statuses() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/accounts/verify_credentials"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "100"})))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/accounts/100/statuses"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {
+                    "id": "12",
+                    "created_at": "2024-01-01T11:00:00.000Z",
+                    "content": "<p>New post <a href=\"https://vdx.hu/s/Df3l\">vdx.hu/s/Df3l</a></p>",
+                    "card": {"url": "https://example.com/posts/1"}
+                },
+                {"id": "11", "created_at": "2024-01-01T10:00:05.000Z", "content": "<p>Hi</p>"}
+            ])))
+            .mount(&mock_server)
+            .await;
+
+        let statuses = mastodon(&mock_server.uri())
+            .recent_statuses()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            statuses,
+            vec![
+                PublishedStatus {
+                    id: String::from("12"),
+                    links: vec![
+                        String::from("https://vdx.hu/s/Df3l"),
+                        String::from("https://example.com/posts/1")
+                    ],
+                },
+                PublishedStatus {
+                    id: String::from("11"),
+                    links: Vec::new(),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_id() {
+        let base_uri = "https://mastodon.social";
+
+        assert_eq!(
+            status_id(base_uri, "https://mastodon.social/@someone/1234"),
+            Some(String::from("1234"))
+        );
+        assert_eq!(
+            status_id(
+                base_uri,
+                "https://mastodon.social/users/someone/statuses/1234"
+            ),
+            Some(String::from("1234"))
+        );
+        assert_eq!(
+            status_id(base_uri, "https://mastodon.social/@someone"),
+            None
+        );
+        assert_eq!(
+            status_id(base_uri, "https://fosstodon.org/@someone/1234"),
+            None
+        );
+        assert_eq!(
+            status_id(base_uri, "https://twitter.com/someone/status/1234"),
+            None
+        );
+    }
 }
@@ -1,56 +1,166 @@
 use std::rc::Rc;
 
-use crate::commons::auth::token_db::SqliteTokenDB;
-use crate::commons::url_shortener::ReqwestClient;
+use chrono::Duration;
+
+use crate::commons::auth::token_db;
+use crate::commons::run_lock::RunLock;
+use crate::commons::template::Templates;
+use crate::commons::url_shortener::{self, NoopClient, ReqwestClient};
 use crate::config::Config;
-use mastodon::Mastodon;
+use mastodon::instance::SqliteLimitsStorage;
+use queue::SqliteQueue;
+use registry::{Dependencies, Registry};
 use rusqlite::Connection;
 use syndicated_post::SqliteSyndycatedPostStorage;
 use target::Target;
-use twitter::Twitter;
 
+pub mod adopt;
+mod content;
+mod identity;
+pub mod import_seen;
+pub mod lint;
 mod mastodon;
+mod pacing;
+pub mod preview;
+pub mod queue;
+mod registry;
+mod render;
 mod rss;
 mod rss_item_ext;
+mod rules;
 mod syndicate;
 mod syndicated_post;
 mod target;
 mod twitter;
 
-pub async fn execute(config: &Config, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// Name of the run lock of cross-publish
+pub const RUN_LOCK: &str = "cross-publish";
+
+/// Minutes a run holds the lock without renewing it
+const RUN_LOCK_TTL_MINUTES: i64 = 10;
+
+pub async fn execute(
+    config: &Config,
+    dry_run: bool,
+    wait: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     let conn = Rc::new(Connection::open(&config.db.path).expect("Couldn't open DB"));
 
-    let token_db = Rc::new(SqliteTokenDB::new(Rc::clone(&conn)));
+    // Overlapping runs would both publish the posts that are not syndicated yet, dry runs don't
+    // publish anything
+    if dry_run {
+        return syndicate(config, &conn, dry_run).await;
+    }
 
-    let url_shortener_client = Rc::new(ReqwestClient::new(
-        &config.url_shortener.protocol,
-        &config.url_shortener.domain,
-        config.url_shortener.put_base_uri.as_ref(),
-    ));
+    let run_lock = RunLock::new(
+        Rc::clone(&conn),
+        RUN_LOCK,
+        Duration::minutes(RUN_LOCK_TTL_MINUTES),
+    );
+    run_lock.init_table()?;
+    run_lock.acquire(wait).await?;
 
-    let targets: Vec<Box<dyn Target>> = vec![
-        Box::new(Twitter::new(
-            config.twitter.client_id.clone(),
-            token_db,
-            Rc::clone(&url_shortener_client),
-        )),
-        Box::new(Mastodon::new(
-            config.mastodon.base_uri.clone(),
-            config.mastodon.access_token.clone(),
-            Rc::clone(&url_shortener_client),
-        )),
-    ];
+    let result = run_lock.hold_while(syndicate(config, &conn, dry_run)).await;
+    run_lock.release()?;
 
-    let storage = SqliteSyndycatedPostStorage::new(Rc::clone(&conn));
+    result
+}
+
+async fn syndicate(
+    config: &Config,
+    conn: &Rc<Connection>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let targets = targets(config, conn, url_shortener_client(config)).await?;
+
+    if targets.is_empty() {
+        log::warn!("None of the social networks are configured, nothing to syndicate to");
+    }
+
+    let storage = SqliteSyndycatedPostStorage::new(Rc::clone(conn));
     storage
         .init_table()
         .expect("Couldn't initialise post storage");
 
-    syndicate::syndicate(config, &rss::ReqwestClient, &targets, &storage, dry_run).await
+    let queue = SqliteQueue::new(Rc::clone(conn));
+    queue.init_table().expect("Couldn't initialise the queue");
+
+    syndicate::syndicate(
+        config,
+        &rss::ReqwestClient,
+        &targets,
+        &storage,
+        &queue,
+        dry_run,
+    )
+    .await
+}
+
+/// Releases the run lock of cross-publish, e.g. when it is left by a crashed run on another host
+pub fn unlock(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let run_lock = RunLock::new(
+        Rc::new(Connection::open(&config.db.path)?),
+        RUN_LOCK,
+        Duration::minutes(RUN_LOCK_TTL_MINUTES),
+    );
+    run_lock.init_table()?;
+
+    match run_lock.force_release()? {
+        Some(lease) => log::info!(
+            "Released the lock of pid {} on {}, held since {}",
+            lease.pid,
+            lease.host,
+            lease.acquired_at
+        ),
+        None => log::info!("The lock is not held"),
+    }
+
+    Ok(())
+}
+
+/// The client of the configured url shortener, the original links are cited without it
+fn url_shortener_client(config: &Config) -> Rc<dyn url_shortener::Client> {
+    match &config.url_shortener {
+        Some(url_shortener) => Rc::new(ReqwestClient::new(
+            &url_shortener.protocol,
+            &url_shortener.domain,
+            url_shortener.put_base_uri.as_ref(),
+        )),
+        None => {
+            log::info!("Url shortener is not configured, citing the original links");
+            Rc::new(NoopClient)
+        }
+    }
+}
+
+/// The targets of the configured networks
+async fn targets(
+    config: &Config,
+    conn: &Rc<Connection>,
+    url_shortener_client: Rc<dyn url_shortener::Client>,
+) -> Result<Vec<Box<dyn Target>>, Box<dyn std::error::Error>> {
+    let token_db = token_db::open(Rc::clone(conn), config.db.token_key.as_ref())?;
+
+    let mastodon_limits_storage = SqliteLimitsStorage::new(Rc::clone(conn));
+    mastodon_limits_storage.init_table()?;
+
+    Registry::default()
+        .targets(
+            config,
+            &Dependencies {
+                token_db,
+                url_shortener_client,
+                mastodon_limits_storage: Rc::new(mastodon_limits_storage),
+                templates: Rc::new(Templates::new(&config.templates)?),
+            },
+        )
+        .await
 }
 
 #[cfg(test)]
 pub mod stubs {
+    pub use crate::cross_publisher::identity::stubs as identity;
+    pub use crate::cross_publisher::queue::stubs as queue;
     pub use crate::cross_publisher::rss::stubs as rss;
     pub use crate::cross_publisher::syndicated_post::stubs as syndycated_post;
     pub use crate::cross_publisher::target::stubs as target;
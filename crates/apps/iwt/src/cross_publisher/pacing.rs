@@ -0,0 +1,237 @@
+use std::cell::Cell;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use async_mutex::Mutex;
+use chrono::{DateTime, Utc};
+use rss::Item;
+
+use super::rss_item_ext::IwtRssExtension;
+use super::target::{Status, Target};
+use crate::commons::rate_limit::RateLimited;
+use crate::config::Pacing;
+use crate::social::{Account, Network};
+
+/// The post is left for a later run, it doesn't fail the current one
+#[derive(Debug)]
+pub struct Postponed {
+    network: Network,
+    reason: String,
+}
+
+impl Display for Postponed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Postponed: not published to {} in this run, {}",
+            self.network, self.reason
+        )
+    }
+}
+
+impl std::error::Error for Postponed {}
+
+/// Publishes to a target at the pace configured for its network, and holds back while the
+/// network is rate limited
+pub struct PacedTarget<'a> {
+    target: &'a dyn Target,
+    pacing: Pacing,
+    published: Cell<usize>,
+    /// Held while publishing, so the posts of the feeds are published one at a time
+    last_published: Mutex<Option<Instant>>,
+    rate_limited_until: Cell<Option<DateTime<Utc>>>,
+}
+
+impl<'a> PacedTarget<'a> {
+    pub fn new(target: &'a dyn Target, pacing: Pacing) -> Self {
+        Self {
+            target,
+            pacing,
+            published: Cell::new(0),
+            last_published: Mutex::new(None),
+            rate_limited_until: Cell::new(None),
+        }
+    }
+
+    pub fn network(&self) -> Network {
+        self.target.network()
+    }
+
+    pub async fn render(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+    ) -> Result<Status, Box<dyn std::error::Error>> {
+        self.watch(self.target.render(post, extension).await)
+    }
+
+    /// Publishes the post, after the post interval of the network is passed since the last one
+    pub async fn publish(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+        idempotency_key: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut last_published = self.last_published.lock().await;
+
+        if let Some(postponed) = self.postponed() {
+            return Err(Box::new(postponed));
+        }
+
+        if let Some(wait) = last_published.and_then(|last| {
+            Duration::from_secs(self.pacing.post_interval).checked_sub(last.elapsed())
+        }) {
+            log::debug!(
+                "{} |> Waiting {}s before publishing to {}",
+                post.link().unwrap_or_default(),
+                wait.as_secs(),
+                self.network()
+            );
+            tokio::time::sleep(wait).await;
+        }
+
+        let result = self.watch(self.target.publish(post, extension, idempotency_key).await);
+        *last_published = Some(Instant::now());
+
+        if result.is_ok() {
+            self.published.set(self.published.get() + 1);
+        }
+
+        result
+    }
+
+    pub async fn schedule(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+        at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        self.watch(self.target.schedule(post, extension, at).await)
+    }
+
+    pub async fn find_scheduled(
+        &self,
+        post: &Item,
+        scheduled_id: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        self.watch(self.target.find_scheduled(post, scheduled_id, at).await)
+    }
+
+    pub async fn find_copy(
+        &self,
+        link: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        self.watch(self.target.find_copy(link).await)
+    }
+
+    pub fn account(&self) -> Account {
+        self.target.account()
+    }
+
+    /// Why no more posts are published in this run
+    fn postponed(&self) -> Option<Postponed> {
+        let reason = match (self.rate_limited_until.get(), self.pacing.max_posts_per_run) {
+            (Some(until), _) if until > Utc::now() => format!("it is rate limited until {until}"),
+            (_, Some(max)) if self.published.get() >= max => {
+                format!("{max} posts are published already")
+            }
+            _ => return None,
+        };
+
+        Some(Postponed {
+            network: self.network(),
+            reason,
+        })
+    }
+
+    /// Remembers the rate limits reported by the network
+    fn watch<T>(
+        &self,
+        result: Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        if let Some(rate_limited) = result
+            .as_ref()
+            .err()
+            .and_then(|err| err.downcast_ref::<RateLimited>())
+        {
+            self.rate_limited_until.set(Some(rate_limited.until));
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use rss::{GuidBuilder, Item};
+
+    use crate::commons::rate_limit::RateLimited;
+    use crate::config::Pacing;
+    use crate::cross_publisher::rss_item_ext::stubs::create_iwt_extension_map;
+    use crate::cross_publisher::rss_item_ext::RssItemExt;
+    use crate::cross_publisher::stubs::target::StubTarget;
+    use crate::social::Network;
+
+    use super::{PacedTarget, Postponed};
+
+    fn post(i: usize) -> Item {
+        Item {
+            link: Some(format!("https://example.com/posts/{i}")),
+            guid: Some(
+                GuidBuilder::default()
+                    .value(format!("https://example.com/posts/{i}"))
+                    .build(),
+            ),
+            extensions: create_iwt_extension_map(&[Network::Mastodon], None, &[]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_postpone_the_posts_over_the_max_posts_per_run() {
+        let target = StubTarget::new(Network::Mastodon);
+        let paced = PacedTarget::new(
+            &target,
+            Pacing {
+                max_posts_per_run: Some(2),
+                post_interval: 0,
+            },
+        );
+
+        for i in 0..3 {
+            let post = post(i);
+            let result = paced
+                .publish(&post, &post.get_iwt_extension().unwrap().unwrap(), "key")
+                .await;
+
+            assert_eq!(result.is_ok(), i < 2);
+            if i >= 2 {
+                assert!(result.unwrap_err().is::<Postponed>());
+            }
+        }
+
+        assert_eq!(target.calls.lock().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_publish_should_be_postponed_while_rate_limited() {
+        let target = StubTarget::new(Network::Mastodon);
+        let paced = PacedTarget::new(&target, Pacing::default());
+
+        paced
+            .watch::<()>(Err(Box::new(RateLimited {
+                until: Utc::now() + Duration::minutes(5),
+            })))
+            .unwrap_err();
+
+        let post = post(0);
+        let result = paced
+            .publish(&post, &post.get_iwt_extension().unwrap().unwrap(), "key")
+            .await;
+
+        assert!(result.unwrap_err().is::<Postponed>());
+        assert_eq!(*target.calls.lock().await, []);
+    }
+}
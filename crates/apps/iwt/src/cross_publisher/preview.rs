@@ -0,0 +1,349 @@
+use std::rc::Rc;
+
+use rusqlite::Connection;
+use serde_derive::Serialize;
+
+use super::identity::{identified, IdentifiedChannel, IdentifiedItem};
+use super::rules::Rules;
+use super::syndicate::{apply_feed_settings, extension};
+use super::target::{Status, Target};
+use super::{rss, rss::Client};
+use crate::commons::template::hashtag;
+use crate::commons::url_shortener::{self, NoopClient, PlaceholderClient};
+use crate::config::{Config, ContentSource};
+use crate::social::Network;
+use crate::IwtError;
+
+#[derive(clap::Args)]
+pub struct PreviewArgs {
+    /// Url of the feed, all the configured feeds are previewed by default
+    #[clap(long, value_parser)]
+    feed: Option<String>,
+    /// Guid of the post, the first post of the feeds is previewed by default
+    #[clap(long, value_parser)]
+    guid: Option<String>,
+    /// Preview only for this network
+    #[clap(long, value_parser = parse_network)]
+    network: Option<Network>,
+    /// Print the previews as JSON
+    #[clap(long, action)]
+    json: bool,
+    /// Cite the link shortened by the url shortener, instead of a placeholder
+    #[clap(long, action)]
+    shorten: bool,
+}
+
+/// The status that would be published for a post
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Preview {
+    pub feed: String,
+    pub guid: String,
+    pub link: String,
+    pub network: String,
+    /// `mastodon:german` for a named account, the name of the network for the default one
+    pub account: String,
+    /// Field of the item the text is taken from, `None` when the item has no text
+    pub content: Option<ContentSource>,
+    pub hashtags: Vec<String>,
+    /// Length of the text, counted the way the network counts it
+    pub length: usize,
+    pub limit: usize,
+    pub status: Status,
+}
+
+pub async fn execute(args: PreviewArgs, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = Rc::new(Connection::open(&config.db.path)?);
+    let url_shortener_client = if args.shorten {
+        super::url_shortener_client(config)
+    } else {
+        placeholder_client(config)
+    };
+    let targets = super::targets(config, &conn, url_shortener_client).await?;
+
+    let urls = match &args.feed {
+        Some(feed) => vec![feed.clone()],
+        None => config.rss.urls.clone(),
+    };
+
+    let mut channels = Vec::new();
+    for url in urls {
+        let (channel, invalid_items) = identified(
+            rss::ReqwestClient.get_channel(&url).await?,
+            &url,
+            config.identity(&url),
+        );
+        for invalid_item in invalid_items {
+            log::warn!("{invalid_item}, skipping");
+        }
+        channels.push((url, channel));
+    }
+
+    let previews = previews(
+        config,
+        &channels,
+        &targets,
+        args.guid.as_deref(),
+        args.network.as_ref(),
+    )
+    .await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&previews)?);
+    } else if previews.is_empty() {
+        println!("Nothing to preview");
+    } else {
+        for preview in &previews {
+            print_preview(preview);
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders the selected posts of the channels for the targets they should be syndicated to
+#[allow(unknown_lints, clippy::unnecessary_map_or)] // `Option::is_none_or` needs Rust 1.82
+async fn previews(
+    config: &Config,
+    channels: &[(String, IdentifiedChannel)],
+    targets: &[Box<dyn Target>],
+    guid: Option<&str>,
+    network: Option<&Network>,
+) -> Result<Vec<Preview>, Box<dyn std::error::Error>> {
+    let rules = Rules::compile(&config.rules)?;
+    let mut previews = Vec::new();
+
+    for (url, channel) in channels {
+        let feed = config.rss.feeds.get(url);
+
+        for identified in select_posts(channel, guid) {
+            let (link, post) = (&identified.link, &identified.item);
+            let Some(extension) = extension(post, feed)? else {
+                log::warn!("{link} |> Rss Item doesn't have an IWT extension, nothing to preview");
+                continue;
+            };
+
+            for target in targets
+                .iter()
+                .filter(|target| network.map_or(true, |network| target.network() == *network))
+            {
+                let mut extension = extension.clone();
+                apply_feed_settings(
+                    &mut extension,
+                    feed,
+                    channel.channel.language(),
+                    &target.network(),
+                );
+                rules.apply(url, post, &mut extension, &target.account(), false);
+                if !extension.targets(&target.account()) {
+                    continue;
+                }
+
+                let status = target.render(post, &extension).await?;
+                let (length_model, limit) = target.length_limit().await;
+
+                previews.push(Preview {
+                    feed: url.clone(),
+                    guid: identified.identity.clone(),
+                    link: link.clone(),
+                    network: target.network().to_string(),
+                    account: target.account().to_string(),
+                    content: extension.body(post).map(|body| body.source),
+                    hashtags: extension.tags.iter().cloned().map(hashtag).collect(),
+                    length: length_model.length(&status.text),
+                    limit,
+                    status,
+                });
+            }
+        }
+    }
+
+    if previews.is_empty() {
+        if let Some(guid) = guid {
+            return Err(Box::new(IwtError::new(&format!(
+                "No post with guid {guid} to preview"
+            ))));
+        }
+    }
+
+    Ok(previews)
+}
+
+/// The post with the guid (the identity of the post), or the first post of the channel
+fn select_posts<'a>(channel: &'a IdentifiedChannel, guid: Option<&str>) -> Vec<&'a IdentifiedItem> {
+    match guid {
+        Some(guid) => channel
+            .items
+            .iter()
+            .filter(|item| item.identity == guid)
+            .collect(),
+        None => channel.items.iter().take(1).collect(),
+    }
+}
+
+/// Cites a placeholder on the domain of the url shortener, the original links are cited without
+/// a shortener
+fn placeholder_client(config: &Config) -> Rc<dyn url_shortener::Client> {
+    match &config.url_shortener {
+        Some(url_shortener) => Rc::new(PlaceholderClient::new(
+            &url_shortener.protocol,
+            &url_shortener.domain,
+        )),
+        None => Rc::new(NoopClient),
+    }
+}
+
+pub fn parse_network(network: &str) -> Result<Network, String> {
+    Network::parse(network).ok_or_else(|| format!("Unknown social network: {network}"))
+}
+
+fn print_preview(preview: &Preview) {
+    println!("== {} |> {}", preview.link, preview.account);
+    println!("length:          {}/{}", preview.length, preview.limit);
+    if let Some(content) = &preview.content {
+        println!("content:         {content}");
+    }
+    if !preview.hashtags.is_empty() {
+        println!("hashtags:        {}", preview.hashtags.join(" "));
+    }
+    print_status(&preview.status);
+    println!();
+}
+
+/// Prints the status as it would be published
+pub fn print_status(status: &Status) {
+    if let Some(interaction) = &status.interaction {
+        println!("interaction:     {interaction:?}");
+    }
+    if let Some(content_warning) = &status.content_warning {
+        println!("content warning: {content_warning}");
+    }
+    if let Some(visibility) = &status.visibility {
+        println!("visibility:      {visibility:?}");
+    }
+    if let Some(language) = &status.language {
+        println!("language:        {language}");
+    }
+    if status.sensitive {
+        println!("sensitive:       true");
+    }
+    for media in &status.media {
+        println!("media:           {} ({})", media.url, media.mime_type);
+    }
+    println!("\n{}", status.text);
+}
+
+#[cfg(test)]
+mod test {
+    use rss::Channel;
+
+    use crate::config::{Config, ContentSource, Feed, Identity};
+    use crate::cross_publisher::identity::{identified, IdentifiedChannel};
+    use crate::cross_publisher::rss::stubs::gen_items_with_extension;
+    use crate::cross_publisher::rss_item_ext::stubs::create_iwt_extension_map;
+    use crate::cross_publisher::stubs::target::StubTarget;
+    use crate::cross_publisher::target::{Status, Target};
+    use crate::social::{Network, Visibility};
+
+    use super::{previews, Preview};
+
+    fn channel(feed: &str) -> IdentifiedChannel {
+        let channel = Channel {
+            items: gen_items_with_extension(
+                &[feed],
+                3,
+                0,
+                &create_iwt_extension_map(
+                    &[Network::Mastodon],
+                    Some(String::from("spoilers")),
+                    &["rust"],
+                ),
+            )
+            .remove(feed)
+            .unwrap(),
+            ..Default::default()
+        };
+
+        identified(channel, feed, Identity::Guid).0
+    }
+
+    #[tokio::test]
+    async fn test_previews_should_render_the_selected_post_per_target() {
+        let feed = "http://example.com/rss.xml";
+        let channel = channel(feed);
+        let post = channel.items[1].clone();
+        let mut config: Config = toml::from_str("").unwrap();
+        config.rss.feeds.insert(
+            feed.to_string(),
+            Feed {
+                visibility: Some(Visibility::Unlisted),
+                ..Default::default()
+            },
+        );
+        let targets: Vec<Box<dyn Target>> = vec![
+            StubTarget::new(Network::Mastodon).into(),
+            StubTarget::new(Network::Twitter).into(),
+        ];
+
+        let previews = previews(
+            &config,
+            &[(feed.to_string(), channel)],
+            &targets,
+            Some(&post.identity),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let text = post.item.title().unwrap().to_string();
+        assert_eq!(
+            previews,
+            vec![Preview {
+                feed: feed.to_string(),
+                guid: post.identity,
+                link: post.link,
+                network: String::from("mastodon"),
+                account: String::from("mastodon"),
+                // The items of the stub feed have only titles
+                content: Some(ContentSource::Title),
+                hashtags: vec![String::from("#Rust")],
+                // The url of the feed counts as 23 characters
+                length: 18 + 23,
+                limit: 500,
+                status: Status {
+                    text,
+                    content_warning: Some(String::from("spoilers")),
+                    media: Vec::new(),
+                    interaction: None,
+                    visibility: Some(Visibility::Unlisted),
+                    language: None,
+                    sensitive: false,
+                },
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_previews_should_filter_by_network_and_guid() {
+        let feed = "http://example.com/rss.xml";
+        let channels = [(feed.to_string(), channel(feed))];
+        let config: Config = toml::from_str("").unwrap();
+        let targets: Vec<Box<dyn Target>> = vec![StubTarget::new(Network::Mastodon).into()];
+
+        let first = previews(&config, &channels, &targets, None, None)
+            .await
+            .unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].guid, channels[0].1.items[0].identity);
+
+        let for_twitter = previews(&config, &channels, &targets, None, Some(&Network::Twitter))
+            .await
+            .unwrap();
+        assert_eq!(for_twitter, []);
+
+        assert!(
+            previews(&config, &channels, &targets, Some("unknown"), None)
+                .await
+                .is_err()
+        );
+    }
+}
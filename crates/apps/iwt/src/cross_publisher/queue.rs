@@ -0,0 +1,703 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::rc::Rc;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, Row};
+
+use super::identity::IdentifiedItem;
+use super::preview::print_status;
+use super::syndicated_post::{self, idempotency_key, Attempt, StorageError};
+use super::target::{Status, Target};
+use crate::config::Config;
+use crate::social::Account;
+use crate::IwtError;
+
+/// A rendered status that waits for approval, it is published exactly as it is stored
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueuedStatus {
+    pub id: i64,
+    pub account: Account,
+    pub original_guid: String,
+    pub original_uri: String,
+    pub status: Status,
+    pub state: QueueState,
+    pub queued_at: DateTime<Utc>,
+}
+
+impl QueuedStatus {
+    /// The id is assigned when the status is enqueued
+    pub fn new(account: Account, item: &IdentifiedItem, status: Status) -> Self {
+        Self {
+            id: 0,
+            account,
+            original_guid: item.identity.clone(),
+            original_uri: item.link.clone(),
+            status,
+            state: QueueState::Pending,
+            queued_at: Utc::now(),
+        }
+    }
+}
+
+/// Rejected statuses stay in the queue, so the post is not queued again by the next run
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QueueState {
+    Pending,
+    Rejected,
+}
+
+impl QueueState {
+    fn parse(state: &str) -> Option<Self> {
+        match state {
+            "pending" => Some(QueueState::Pending),
+            "rejected" => Some(QueueState::Rejected),
+            _ => None,
+        }
+    }
+}
+
+impl Display for QueueState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueState::Pending => write!(f, "pending"),
+            QueueState::Rejected => write!(f, "rejected"),
+        }
+    }
+}
+
+pub trait Queue {
+    /// Stores the status and returns its id
+    fn enqueue(&self, queued_status: QueuedStatus) -> Result<i64, StorageError>;
+    fn get(&self, id: i64) -> Result<Option<QueuedStatus>, StorageError>;
+    fn find(
+        &self,
+        original_guid: &str,
+        account: &Account,
+    ) -> Result<Option<QueuedStatus>, StorageError>;
+    /// All the queued statuses in the order they were queued
+    fn list(&self) -> Result<Vec<QueuedStatus>, StorageError>;
+    /// Updates the status and the state
+    fn update(&self, queued_status: &QueuedStatus) -> Result<(), StorageError>;
+    fn remove(&self, id: i64) -> Result<(), StorageError>;
+}
+
+pub struct SqliteQueue {
+    conn: Rc<Connection>,
+}
+
+impl SqliteQueue {
+    pub fn new(conn: Rc<Connection>) -> Self {
+        Self { conn }
+    }
+
+    pub fn init_table(&self) -> Result<(), StorageError> {
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS queue (
+              id INTEGER PRIMARY KEY AUTOINCREMENT,
+              social_network VARCHAR(20) NOT NULL,
+              original_guid TEXT NOT NULL,
+              original_uri TEXT NOT NULL,
+              status TEXT NOT NULL,
+              state VARCHAR(20) NOT NULL,
+              queued_at TEXT NOT NULL,
+
+              UNIQUE (original_guid, social_network)
+            )",
+                (),
+            )
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+
+    fn query(
+        &self,
+        condition: &str,
+        params: &[(&str, &dyn rusqlite::ToSql)],
+    ) -> Result<Vec<QueuedStatus>, StorageError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT id, social_network, original_guid, original_uri, status, state, queued_at
+            FROM queue WHERE {condition} ORDER BY id"
+        ))?;
+
+        let rows = statement.query_map(params, |row| Ok(Self::read_row(row)))?;
+
+        rows.map(|row| row?).collect()
+    }
+
+    fn read_row(row: &Row) -> Result<QueuedStatus, StorageError> {
+        let status: String = row.get(4)?;
+        let state: String = row.get(5)?;
+        let queued_at: String = row.get(6)?;
+
+        Ok(QueuedStatus {
+            id: row.get(0)?,
+            account: row.get(1)?,
+            original_guid: row.get(2)?,
+            original_uri: row.get(3)?,
+            status: serde_json::from_str(&status).map_err(|err| {
+                StorageError::PersistenceError(format!("Invalid queued status: {err}"))
+            })?,
+            state: QueueState::parse(&state).ok_or_else(|| {
+                StorageError::PersistenceError(format!("Invalid queue state: {state}"))
+            })?,
+            queued_at: DateTime::parse_from_rfc3339(&queued_at)
+                .map_err(|err| {
+                    StorageError::PersistenceError(format!("Invalid queueing date: {err}"))
+                })?
+                .with_timezone(&Utc),
+        })
+    }
+
+    fn to_json(status: &Status) -> Result<String, StorageError> {
+        serde_json::to_string(status)
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+}
+
+impl Queue for SqliteQueue {
+    fn enqueue(&self, queued_status: QueuedStatus) -> Result<i64, StorageError> {
+        self.conn
+            .execute(
+                "INSERT INTO queue
+                 (social_network, original_guid, original_uri, status, state, queued_at)
+                 VALUES (:social_network, :original_guid, :original_uri, :status, :state,
+                         :queued_at)",
+                rusqlite::named_params! {
+                    ":social_network": queued_status.account.to_string(),
+                    ":original_guid": queued_status.original_guid,
+                    ":original_uri": queued_status.original_uri,
+                    ":status": Self::to_json(&queued_status.status)?,
+                    ":state": queued_status.state.to_string(),
+                    ":queued_at": queued_status.queued_at.to_rfc3339(),
+                },
+            )
+            .map(|_| self.conn.last_insert_rowid())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+
+    fn get(&self, id: i64) -> Result<Option<QueuedStatus>, StorageError> {
+        Ok(self.query("id = :id", &[(":id", &id)])?.pop())
+    }
+
+    fn find(
+        &self,
+        original_guid: &str,
+        account: &Account,
+    ) -> Result<Option<QueuedStatus>, StorageError> {
+        Ok(self
+            .query(
+                "original_guid = :original_guid AND social_network = :social_network",
+                &[
+                    (":original_guid", &original_guid),
+                    (":social_network", &account.to_string()),
+                ],
+            )?
+            .pop())
+    }
+
+    fn list(&self) -> Result<Vec<QueuedStatus>, StorageError> {
+        self.query("1 = 1", &[])
+    }
+
+    fn update(&self, queued_status: &QueuedStatus) -> Result<(), StorageError> {
+        self.conn
+            .execute(
+                "UPDATE queue SET status = :status, state = :state WHERE id = :id",
+                rusqlite::named_params! {
+                    ":id": queued_status.id,
+                    ":status": Self::to_json(&queued_status.status)?,
+                    ":state": queued_status.state.to_string(),
+                },
+            )
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+
+    fn remove(&self, id: i64) -> Result<(), StorageError> {
+        self.conn
+            .execute("DELETE FROM queue WHERE id = :id", &[(":id", &id)])
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+}
+
+#[derive(clap::Subcommand)]
+pub enum QueueSubcommand {
+    /// List the queued statuses
+    List,
+    /// Show a queued status as it would be published
+    Show { id: i64 },
+    /// Publish a queued status exactly as it is shown
+    Approve { id: i64 },
+    /// Reject a queued status, the post won't be queued again
+    Reject { id: i64 },
+    /// Edit the text of a queued status, in $EDITOR unless the text is given
+    Edit {
+        id: i64,
+        #[clap(long, value_parser)]
+        text: Option<String>,
+    },
+}
+
+pub async fn execute(
+    command: QueueSubcommand,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let conn = Rc::new(Connection::open(&config.db.path)?);
+    let queue = SqliteQueue::new(Rc::clone(&conn));
+    queue.init_table()?;
+
+    match command {
+        QueueSubcommand::List => list(&queue),
+        QueueSubcommand::Show { id } => show(&queue, id),
+        QueueSubcommand::Approve { id } => {
+            let storage = syndicated_post::SqliteSyndycatedPostStorage::new(Rc::clone(&conn));
+            storage.init_table()?;
+            let targets =
+                super::targets(config, &conn, super::url_shortener_client(config)).await?;
+
+            approve(&queue, &storage, &targets, id).await
+        }
+        QueueSubcommand::Reject { id } => reject(&queue, id),
+        QueueSubcommand::Edit { id, text } => {
+            let text = match text {
+                Some(text) => text,
+                None => edit_in_editor(&get(&queue, id)?)?,
+            };
+            let targets =
+                super::targets(config, &conn, super::url_shortener_client(config)).await?;
+
+            edit(&queue, &targets, id, text).await
+        }
+    }
+}
+
+fn list<Q: Queue>(queue: &Q) -> Result<(), Box<dyn std::error::Error>> {
+    let queued_statuses = queue.list()?;
+
+    if queued_statuses.is_empty() {
+        println!("The queue is empty");
+    }
+
+    for queued_status in queued_statuses {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            queued_status.id,
+            queued_status.account,
+            queued_status.state,
+            queued_status.queued_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            queued_status.original_uri
+        );
+    }
+
+    Ok(())
+}
+
+fn show<Q: Queue>(queue: &Q, id: i64) -> Result<(), Box<dyn std::error::Error>> {
+    let QueuedStatus {
+        account,
+        original_uri,
+        status,
+        state,
+        queued_at,
+        ..
+    } = get(queue, id)?;
+
+    println!("account:         {account}");
+    println!("post:            {original_uri}");
+    println!("state:           {state}");
+    println!(
+        "queued at:       {}",
+        queued_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    print_status(&status);
+
+    Ok(())
+}
+
+async fn approve<Q: Queue, S: syndicated_post::Storage>(
+    queue: &Q,
+    storage: &S,
+    targets: &[Box<dyn Target>],
+    id: i64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let queued_status = get(queue, id)?;
+    let account = &queued_status.account;
+    let link = &queued_status.original_uri;
+
+    if queued_status.state != QueueState::Pending {
+        return Err(Box::new(IwtError::new(&format!(
+            "The queued status {id} is {}, only pending statuses can be approved",
+            queued_status.state
+        ))));
+    }
+
+    if storage
+        .find(&queued_status.original_guid, account)?
+        .is_some()
+    {
+        log::info!(
+            "{link} |> Has been already syndicated to {account}, dropping it from the queue"
+        );
+        return Ok(queue.remove(id)?);
+    }
+
+    let target = target(targets, account)?;
+
+    let attempt = Attempt {
+        account: account.clone(),
+        original_guid: queued_status.original_guid.clone(),
+        original_uri: link.clone(),
+        idempotency_key: idempotency_key(&queued_status.original_guid, account),
+        started_at: Utc::now(),
+    };
+    storage.store_attempt(attempt.clone())?;
+
+    log::info!("{link} |> Publishing the approved status to {account}");
+    let status_id = target
+        .publish_status(&queued_status.status, &attempt.idempotency_key)
+        .await?;
+
+    storage.store(attempt.syndicated(&status_id))?;
+    storage.remove_attempt(&attempt.original_guid, account)?;
+    queue.remove(id)?;
+    log::info!("{link} |> Published to {account}");
+
+    Ok(())
+}
+
+fn reject<Q: Queue>(queue: &Q, id: i64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut queued_status = get(queue, id)?;
+    queued_status.state = QueueState::Rejected;
+
+    Ok(queue.update(&queued_status)?)
+}
+
+/// Replaces the text of the status, the text has to fit in the length limit of the account
+async fn edit<Q: Queue>(
+    queue: &Q,
+    targets: &[Box<dyn Target>],
+    id: i64,
+    text: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut queued_status = get(queue, id)?;
+    let account = &queued_status.account;
+
+    let (length_model, limit) = target(targets, account)?.length_limit().await;
+    let length = length_model.length(&text);
+    if length > limit {
+        return Err(Box::new(IwtError::new(&format!(
+            "The text is {length} characters long, {account} allows {limit}, the status is not \
+             changed"
+        ))));
+    }
+
+    queued_status.status.text = text;
+
+    Ok(queue.update(&queued_status)?)
+}
+
+/// Opens the text of the status in $VISUAL or $EDITOR and returns the saved text
+fn edit_in_editor(queued_status: &QueuedStatus) -> Result<String, Box<dyn std::error::Error>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let path = std::env::temp_dir().join(format!(
+        "iwt-queue-{}-{:016x}.txt",
+        queued_status.id,
+        rand::random::<u64>()
+    ));
+    write_private(&path, &queued_status.status.text)?;
+
+    let mut args = editor.split_whitespace();
+    let exit_status = std::process::Command::new(args.next().unwrap_or("vi"))
+        .args(args)
+        .arg(&path)
+        .status()?;
+    let text = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path)?;
+
+    if !exit_status.success() {
+        return Err(Box::new(IwtError::new(&format!(
+            "{editor} exited with {exit_status}, the status is not changed"
+        ))));
+    }
+
+    Ok(text?.trim_end_matches('\n').to_string())
+}
+
+/// Creates the file readable only by the user, it fails instead of following an existing file or
+/// link, as the status is not published yet
+fn write_private(path: &std::path::Path, text: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(text.as_bytes())
+}
+
+fn target<'a>(
+    targets: &'a [Box<dyn Target>],
+    account: &Account,
+) -> Result<&'a dyn Target, Box<dyn std::error::Error>> {
+    targets
+        .iter()
+        .find(|target| target.account() == *account)
+        .map(AsRef::as_ref)
+        .ok_or_else(|| Box::new(IwtError::new(&format!("{account} is not configured"))).into())
+}
+
+fn get<Q: Queue>(queue: &Q, id: i64) -> Result<QueuedStatus, Box<dyn std::error::Error>> {
+    queue
+        .get(id)?
+        .ok_or_else(|| Box::new(IwtError::new(&format!("No queued status with id {id}"))).into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use rss::{GuidBuilder, ItemBuilder};
+    use rusqlite::Connection;
+
+    use crate::cross_publisher::rss_item_ext::Interaction;
+    use crate::cross_publisher::stubs::identity::identified_item;
+    use crate::cross_publisher::stubs::syndycated_post::SyndicatedPostStorageStub;
+    use crate::cross_publisher::stubs::target::StubTarget;
+    use crate::cross_publisher::syndicated_post::{Storage, SyndicatedPost};
+    use crate::cross_publisher::target::{Media, Status, Target};
+    use crate::social::{Account, Network, Visibility};
+
+    use super::{approve, edit, reject, Queue, QueueState, QueuedStatus, SqliteQueue};
+
+    fn queued_status(account: Account) -> QueuedStatus {
+        let item = identified_item(
+            &ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value("guid").build()))
+                .link(Some(String::from("https://example.com/posts/1")))
+                .build(),
+        );
+
+        QueuedStatus::new(
+            account,
+            &item,
+            Status {
+                text: String::from("Lorem ipsum https://example.com/posts/1"),
+                content_warning: Some(String::from("spoilers")),
+                media: vec![Media {
+                    url: String::from("https://example.com/photo.jpg"),
+                    mime_type: String::from("image/jpeg"),
+                }],
+                interaction: Some(Interaction::Reply(String::from(
+                    "https://example.com/posts/0",
+                ))),
+                visibility: Some(Visibility::Unlisted),
+                language: Some(String::from("en")),
+                sensitive: true,
+            },
+        )
+    }
+
+    fn sqlite_queue() -> SqliteQueue {
+        let queue = SqliteQueue::new(Rc::new(Connection::open_in_memory().unwrap()));
+        queue.init_table().unwrap();
+        queue
+    }
+
+    #[tokio::test]
+    async fn test_queued_statuses_should_be_stored_updated_and_removed() {
+        let queue = sqlite_queue();
+
+        let mut mastodon = queued_status(Network::Mastodon.into());
+        mastodon.id = queue.enqueue(mastodon.clone()).unwrap();
+        let mut twitter = queued_status(Network::Twitter.into());
+        twitter.id = queue.enqueue(twitter.clone()).unwrap();
+
+        assert_eq!(queue.get(mastodon.id).unwrap(), Some(mastodon.clone()));
+        assert_eq!(
+            queue.find("guid", &Network::Twitter.into()).unwrap(),
+            Some(twitter.clone())
+        );
+        assert_eq!(queue.list().unwrap(), vec![mastodon.clone(), twitter]);
+
+        let targets: Vec<Box<dyn Target>> = vec![StubTarget::new(Network::Mastodon).into()];
+        reject(&queue, mastodon.id).unwrap();
+        edit(&queue, &targets, mastodon.id, String::from("Edited"))
+            .await
+            .unwrap();
+        mastodon.state = QueueState::Rejected;
+        mastodon.status.text = String::from("Edited");
+        assert_eq!(queue.get(mastodon.id).unwrap(), Some(mastodon.clone()));
+
+        queue.remove(mastodon.id).unwrap();
+        assert_eq!(queue.get(mastodon.id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_a_post_should_be_queued_once_per_account() {
+        let queue = sqlite_queue();
+
+        queue
+            .enqueue(queued_status(Network::Mastodon.into()))
+            .unwrap();
+
+        assert!(queue
+            .enqueue(queued_status(Network::Mastodon.into()))
+            .is_err());
+        assert!(queue
+            .enqueue(queued_status(Account::named(Network::Mastodon, "german")))
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_approve_should_publish_the_queued_status_and_record_it() {
+        let queue = sqlite_queue();
+        let storage = SyndicatedPostStorageStub::default();
+        let german = Account::named(Network::Mastodon, "german");
+        let target = StubTarget::named(Network::Mastodon, "german");
+        let published = target.published.clone();
+        let targets: Vec<Box<dyn Target>> =
+            vec![StubTarget::new(Network::Mastodon).into(), target.into()];
+
+        let mut queued = queued_status(german.clone());
+        queued.id = queue.enqueue(queued.clone()).unwrap();
+
+        approve(&queue, &storage, &targets, queued.id)
+            .await
+            .unwrap();
+
+        assert_eq!(*published.lock().await, vec![queued.status.clone()]);
+        assert_eq!(
+            storage.find("guid", &Network::Mastodon.into()).unwrap(),
+            None
+        );
+        assert_eq!(
+            storage.find("guid", &german).unwrap(),
+            Some(SyndicatedPost {
+                account: german.clone(),
+                id: String::from("0"),
+                original_guid: String::from("guid"),
+                original_uri: String::from("https://example.com/posts/1"),
+            })
+        );
+        assert_eq!(queue.get(queued.id).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_approve_should_fail_for_unconfigured_networks() {
+        let queue = sqlite_queue();
+        let storage = SyndicatedPostStorageStub::default();
+        let targets: Vec<Box<dyn Target>> = vec![StubTarget::new(Network::Twitter).into()];
+
+        let id = queue
+            .enqueue(queued_status(Network::Mastodon.into()))
+            .unwrap();
+
+        assert!(approve(&queue, &storage, &targets, id).await.is_err());
+        assert!(queue.get(id).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_approve_should_fail_for_rejected_statuses() {
+        let queue = sqlite_queue();
+        let storage = SyndicatedPostStorageStub::default();
+        let target = StubTarget::new(Network::Mastodon);
+        let published = target.published.clone();
+        let targets: Vec<Box<dyn Target>> = vec![target.into()];
+
+        let id = queue
+            .enqueue(queued_status(Network::Mastodon.into()))
+            .unwrap();
+        reject(&queue, id).unwrap();
+
+        assert!(approve(&queue, &storage, &targets, id).await.is_err());
+        assert!(published.lock().await.is_empty());
+        assert_eq!(
+            storage.find("guid", &Network::Mastodon.into()).unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_should_fail_when_the_text_is_too_long() {
+        let queue = sqlite_queue();
+        let targets: Vec<Box<dyn Target>> = vec![StubTarget::new(Network::Mastodon).into()];
+        let queued = queued_status(Network::Mastodon.into());
+        let id = queue.enqueue(queued.clone()).unwrap();
+
+        assert!(edit(&queue, &targets, id, "a".repeat(501)).await.is_err());
+        assert_eq!(queue.get(id).unwrap().unwrap().status, queued.status);
+
+        edit(&queue, &targets, id, "a".repeat(500)).await.unwrap();
+        assert_eq!(queue.get(id).unwrap().unwrap().status.text, "a".repeat(500));
+    }
+}
+
+#[cfg(test)]
+pub mod stubs {
+    use std::sync::Mutex;
+
+    use crate::cross_publisher::syndicated_post::StorageError;
+    use crate::social::Account;
+
+    use super::{Queue, QueuedStatus};
+
+    #[derive(Default)]
+    pub struct QueueStub {
+        pub queued_statuses: Mutex<Vec<QueuedStatus>>,
+    }
+
+    impl Queue for QueueStub {
+        fn enqueue(&self, mut queued_status: QueuedStatus) -> Result<i64, StorageError> {
+            let mut queued_statuses = self.queued_statuses.lock().unwrap();
+            queued_status.id = i64::try_from(queued_statuses.len()).unwrap() + 1;
+            queued_statuses.push(queued_status);
+
+            Ok(queued_statuses.last().unwrap().id)
+        }
+
+        fn get(&self, id: i64) -> Result<Option<QueuedStatus>, StorageError> {
+            let queued_statuses = self.queued_statuses.lock().unwrap();
+
+            Ok(queued_statuses.iter().find(|q| q.id == id).cloned())
+        }
+
+        fn find(
+            &self,
+            original_guid: &str,
+            account: &Account,
+        ) -> Result<Option<QueuedStatus>, StorageError> {
+            let queued_statuses = self.queued_statuses.lock().unwrap();
+
+            Ok(queued_statuses
+                .iter()
+                .find(|q| q.original_guid == *original_guid && q.account == *account)
+                .cloned())
+        }
+
+        fn list(&self) -> Result<Vec<QueuedStatus>, StorageError> {
+            Ok(self.queued_statuses.lock().unwrap().clone())
+        }
+
+        fn update(&self, queued_status: &QueuedStatus) -> Result<(), StorageError> {
+            let mut queued_statuses = self.queued_statuses.lock().unwrap();
+            for q in queued_statuses
+                .iter_mut()
+                .filter(|q| q.id == queued_status.id)
+            {
+                *q = queued_status.clone();
+            }
+
+            Ok(())
+        }
+
+        fn remove(&self, id: i64) -> Result<(), StorageError> {
+            self.queued_statuses.lock().unwrap().retain(|q| q.id != id);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use oauth2::AccessToken;
+
+use crate::commons::auth::token_db::TokenDB;
+use crate::commons::template::Templates;
+use crate::commons::url_shortener;
+use crate::config::Config;
+use crate::social::Network;
+
+use super::mastodon::instance::{LimitOverrides, LimitsStorage};
+use super::mastodon::Mastodon;
+use super::render::StatusRenderer;
+use super::target::Target;
+use super::twitter::Twitter;
+
+/// Shared resources the targets can be constructed from
+pub struct Dependencies {
+    pub token_db: Rc<dyn TokenDB>,
+    pub url_shortener_client: Rc<dyn url_shortener::Client>,
+    pub mastodon_limits_storage: Rc<dyn LimitsStorage>,
+    pub templates: Rc<Templates>,
+}
+
+/// The targets of the accounts of a network
+pub type Targets = Result<Vec<Box<dyn Target>>, Box<dyn std::error::Error>>;
+
+/// Constructs the targets of a network, returns `None` when the network is not configured
+pub type Constructor = fn(&Config, &Dependencies) -> Option<Targets>;
+
+/// Maps social networks to the constructor of their target
+pub struct Registry {
+    constructors: BTreeMap<Network, Constructor>,
+}
+
+impl Registry {
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            constructors: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, network: Network, constructor: Constructor) {
+        self.constructors.insert(network, constructor);
+    }
+
+    /// Instantiates and prepares the targets of the configured accounts, e.g. the Mastodon
+    /// instance limits are resolved here
+    pub async fn targets(
+        &self,
+        config: &Config,
+        dependencies: &Dependencies,
+    ) -> Result<Vec<Box<dyn Target>>, Box<dyn std::error::Error>> {
+        let mut targets = Vec::new();
+
+        for (network, constructor) in &self.constructors {
+            match constructor(config, dependencies) {
+                Some(network_targets) => {
+                    for target in network_targets? {
+                        target.prepare().await?;
+                        log::debug!("{} is configured, target created", target.account());
+                        targets.push(target);
+                    }
+                }
+                None => log::debug!("{network} is not configured, skipping"),
+            }
+        }
+
+        Ok(targets)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Network::Twitter, twitter);
+        registry.register(Network::Mastodon, mastodon);
+        registry
+    }
+}
+
+fn twitter(config: &Config, dependencies: &Dependencies) -> Option<Targets> {
+    config.twitter.as_ref().map(|twitter| {
+        Twitter::new(
+            twitter.oauth_client(),
+            Rc::clone(&dependencies.token_db),
+            Rc::clone(&dependencies.url_shortener_client),
+            StatusRenderer::new(
+                Rc::clone(&dependencies.templates),
+                twitter.template.as_ref(),
+                twitter.links,
+            ),
+        )
+        .map(|target| vec![Box::new(target) as Box<dyn Target>])
+    })
+}
+
+/// The default account, then the named accounts with the settings of the section
+fn mastodon(config: &Config, dependencies: &Dependencies) -> Option<Targets> {
+    config.mastodon.as_ref().map(|mastodon| {
+        let target = |base_uri: &String, access_token: &AccessToken, template: Option<&String>| {
+            Mastodon::new(
+                base_uri.clone(),
+                access_token.clone(),
+                Rc::clone(&dependencies.url_shortener_client),
+                Rc::clone(&dependencies.mastodon_limits_storage),
+                LimitOverrides {
+                    max_characters: mastodon.max_characters,
+                    characters_reserved_per_url: mastodon.characters_reserved_per_url,
+                },
+                StatusRenderer::new(Rc::clone(&dependencies.templates), template, mastodon.links),
+                mastodon.schedule_on_server,
+            )
+        };
+
+        let mut targets = vec![Box::new(target(
+            &mastodon.base_uri,
+            &mastodon.access_token,
+            mastodon.template.as_ref(),
+        )) as Box<dyn Target>];
+        for (name, account) in &mastodon.accounts {
+            let target = target(
+                account.base_uri.as_ref().unwrap_or(&mastodon.base_uri),
+                &account.access_token,
+                account.template.as_ref().or(mastodon.template.as_ref()),
+            );
+            targets.push(Box::new(target.named(name)));
+        }
+
+        Ok(targets)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::rc::Rc;
+
+    use oauth2::{AccessToken, ClientId};
+
+    use rusqlite::Connection;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::commons::template::Templates;
+    use crate::commons::url_shortener::NoopClient;
+    use crate::config::{Config, Mastodon, MastodonAccount, Rss, Twitter, DB};
+    use crate::cross_publisher::mastodon::instance::SqliteLimitsStorage;
+    use crate::social::{Account, Network};
+    use crate::stubs::auth::token_db::stubs::StubTokenDB;
+
+    use super::{Dependencies, Registry};
+
+    fn dependencies() -> Dependencies {
+        Dependencies {
+            token_db: Rc::new(StubTokenDB::new()),
+            url_shortener_client: Rc::new(NoopClient),
+            mastodon_limits_storage: Rc::new(SqliteLimitsStorage::new(Rc::new(
+                Connection::open_in_memory().unwrap(),
+            ))),
+            templates: Rc::new(Templates::new(&BTreeMap::new()).unwrap()),
+        }
+    }
+
+    fn config(twitter: Option<Twitter>, mastodon: Option<Mastodon>) -> Config {
+        Config {
+            rss: Rss::default(),
+            db: DB {
+                path: String::from("some/path"),
+                token_key: None,
+            },
+            twitter,
+            mastodon,
+            url_shortener: None,
+            templates: BTreeMap::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_targets_should_be_created_only_for_configured_networks() {
+        let mock_server = MockServer::start().await;
+        let config = config(
+            None,
+            Some(Mastodon::new(
+                mock_server.uri(),
+                AccessToken::new(String::from("some-access-token")),
+            )),
+        );
+
+        let targets = Registry::default()
+            .targets(&config, &dependencies())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            targets.iter().map(|t| t.network()).collect::<Vec<_>>(),
+            vec![Network::Mastodon]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_targets_should_be_created_for_all_configured_networks() {
+        let mock_server = MockServer::start().await;
+        let config = config(
+            Some(Twitter::new(ClientId::new(String::from("some_client_id")))),
+            Some(Mastodon::new(
+                mock_server.uri(),
+                AccessToken::new(String::from("some-access-token")),
+            )),
+        );
+
+        let targets = Registry::default()
+            .targets(&config, &dependencies())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            targets.iter().map(|t| t.network()).collect::<Vec<_>>(),
+            vec![Network::Twitter, Network::Mastodon]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_targets_should_be_empty_when_nothing_is_configured() {
+        let targets = Registry::default()
+            .targets(&config(None, None), &dependencies())
+            .await
+            .unwrap();
+
+        assert!(targets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_targets_should_resolve_the_mastodon_instance_limits() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/instance"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "configuration": {
+                    "statuses": {
+                        "max_characters": 1000,
+                        "characters_reserved_per_url": 23
+                    }
+                }
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let config = config(
+            None,
+            Some(Mastodon::new(
+                mock_server.uri(),
+                AccessToken::new(String::from("some-access-token")),
+            )),
+        );
+
+        let targets = Registry::default()
+            .targets(&config, &dependencies())
+            .await
+            .unwrap();
+
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+        assert_eq!(targets[0].length_limit().await.1, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_targets_should_be_created_for_the_named_accounts() {
+        let mock_server = MockServer::start().await;
+        let german_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v2/instance"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "configuration": {
+                    "statuses": {
+                        "max_characters": 1000,
+                        "characters_reserved_per_url": 23
+                    }
+                }
+            })))
+            .expect(1)
+            .mount(&german_server)
+            .await;
+        let mut mastodon = Mastodon::new(
+            mock_server.uri(),
+            AccessToken::new(String::from("some-access-token")),
+        );
+        mastodon.accounts.insert(
+            String::from("german"),
+            MastodonAccount {
+                base_uri: Some(german_server.uri()),
+                access_token: AccessToken::new(String::from("german-access-token")),
+                template: None,
+            },
+        );
+        mastodon.accounts.insert(
+            String::from("project"),
+            MastodonAccount {
+                base_uri: None,
+                access_token: AccessToken::new(String::from("project-access-token")),
+                template: None,
+            },
+        );
+
+        let targets = Registry::default()
+            .targets(&config(None, Some(mastodon)), &dependencies())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            targets.iter().map(|t| t.account()).collect::<Vec<_>>(),
+            vec![
+                Account::from(Network::Mastodon),
+                Account::named(Network::Mastodon, "german"),
+                Account::named(Network::Mastodon, "project"),
+            ]
+        );
+        // The german account is on its own instance
+        assert_eq!(targets[1].length_limit().await.1, 1000);
+        assert_eq!(targets[2].length_limit().await.1, 500);
+    }
+}
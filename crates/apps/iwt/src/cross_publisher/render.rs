@@ -0,0 +1,107 @@
+use std::rc::Rc;
+
+use rss::{Enclosure, Item};
+use url::Url;
+
+use crate::commons::html::{self, LinkPolicy};
+use crate::commons::permashort_link::PermashortCitation;
+use crate::commons::template::{StatusContext, TemplateError, Templates};
+use crate::commons::text::LengthModel;
+
+use super::rss_item_ext::{Interaction, IwtRssExtension, PostKind};
+
+/// Renders the statuses of a network from its template
+pub struct StatusRenderer {
+    templates: Rc<Templates>,
+    template: Option<String>,
+    links: LinkPolicy,
+}
+
+impl StatusRenderer {
+    /// Without a template the built-in template of the post kind is used
+    #[must_use]
+    pub fn new(templates: Rc<Templates>, template: Option<&String>, links: LinkPolicy) -> Self {
+        Self {
+            templates,
+            template: template.cloned(),
+            links,
+        }
+    }
+
+    /// Renders the status of the post, the template of the item takes precedence over the one
+    /// of the network, which takes precedence over the one of the post kind.
+    /// `linked_media` is the media of the post that is not attached to the status
+    pub fn render(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+        permashort_citation: &PermashortCitation,
+        linked_media: &[String],
+        limit: usize,
+        model: &LengthModel,
+    ) -> Result<String, TemplateError> {
+        let kind_template = extension.kind.to_string();
+        let template = extension
+            .template
+            .as_ref()
+            .or(self.template.as_ref())
+            .unwrap_or(&kind_template);
+
+        let mut context = self.status_context(post, extension, permashort_citation);
+        context.media = linked_media.to_vec();
+
+        self.templates.render(template, &context, limit, model)
+    }
+
+    /// Collects the values of the post that the status templates can use
+    fn status_context(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+        permashort_citation: &PermashortCitation,
+    ) -> StatusContext {
+        let base = post.link().and_then(|link| Url::parse(link).ok());
+        let body = extension.body(post);
+        let body = html::to_plain_text(
+            body.as_ref().map_or("", |body| &body.html),
+            self.links,
+            base.as_ref(),
+        );
+
+        let interaction_url = |matches: fn(&Interaction) -> bool| {
+            extension
+                .interaction
+                .as_ref()
+                .filter(|interaction| matches(interaction))
+                .map(|interaction| interaction.url().to_string())
+        };
+
+        StatusContext {
+            title: post.title().unwrap_or_default().to_string(),
+            summary: body.text,
+            excerpt: body.excerpt,
+            footnotes: body.footnotes,
+            kind: extension.kind.to_string(),
+            media: Vec::new(),
+            tags: extension.tags.clone(),
+            content_warning: extension.content_warning.clone(),
+            in_reply_to: interaction_url(|i| matches!(i, Interaction::Reply(_))),
+            repost_of: interaction_url(|i| matches!(i, Interaction::Repost(_))),
+            like_of: interaction_url(|i| matches!(i, Interaction::Like(_))),
+            permashort_uri: permashort_citation.to_uri(),
+            permashort_citation: permashort_citation.to_string(),
+            link: post.link().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+/// The image of a photo post, other kinds have no media to show
+#[must_use]
+pub fn photo<'a>(post: &'a Item, extension: &IwtRssExtension) -> Option<&'a Enclosure> {
+    if extension.kind != PostKind::Photo {
+        return None;
+    }
+
+    post.enclosure()
+        .filter(|enclosure| enclosure.mime_type().starts_with("image/"))
+}
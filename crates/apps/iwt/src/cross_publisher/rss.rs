@@ -119,7 +119,7 @@ pub mod stubs {
                         Err(Box::new(RssClientError))
                     } else {
                         let channel = Channel {
-                            items: self.items.get(&url.to_string()).unwrap().clone(),
+                            items: self.items.get(url).unwrap().clone(),
                             link: url.to_owned(),
                             ..Default::default()
                         };
@@ -1,85 +1,474 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
 use rss::{extension::Extension, Item};
+use serde_derive::{Deserialize, Serialize};
+use url::Url;
 
-use crate::social;
+use super::content::{self, Body};
+use crate::commons::html::{self, LinkPolicy};
+use crate::config::{ContentSource, ItemDefaults};
+use crate::social::{self, Account, Visibility};
 
 /// Rust representation of the Indieweb Tools RSS extension
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct IwtRssExtension {
-    /// The target networks where Item should be syndicated to
+    /// The target networks where Item should be syndicated to, by their default accounts
     pub target_networks: Vec<IwtRssTargetNetwork>,
+    /// Named accounts the item is syndicated to as well, set by the rules
+    pub target_accounts: Vec<Account>,
     /// Content Warning, this is only used by Mastodon
     pub content_warning: Option<String>,
     /// Tags of the item
     pub tags: Vec<String>,
+    /// Name of the status template, set from the feed settings
+    pub template: Option<String>,
+    /// Set explicitly by `iwt:kind`, or discovered from the item
+    pub kind: PostKind,
+    /// Set by `iwt:inReplyTo`, `iwt:repostOf` or `iwt:likeOf`
+    pub interaction: Option<Interaction>,
+    /// Set by `iwt:visibility`, or from the feed settings
+    pub visibility: Option<Visibility>,
+    /// Set by `iwt:language`, or from the feed settings or the channel
+    pub language: Option<String>,
+    /// Whether the media is sensitive, set by `iwt:sensitive` or from the feed settings
+    pub sensitive: Option<bool>,
+    /// Set by `iwt:publishAt`, the item is not syndicated before it
+    pub publish_at: Option<DateTime<Utc>>,
+    /// Set by `iwt:summary`, the text of the statuses instead of the content of the item
+    pub summary: Option<String>,
+    /// Fields of the item the text of the statuses is taken from, in order of precedence, set
+    /// from the feed settings
+    pub content: Vec<ContentSource>,
 }
 
-#[derive(Debug, PartialEq)]
+impl IwtRssExtension {
+    /// The extension of an item without `iwt:extension`, from the defaults of its feed
+    #[must_use]
+    pub fn from_defaults(item: &Item, defaults: &ItemDefaults) -> Self {
+        let tags = if defaults.tags_from_categories {
+            item.categories()
+                .iter()
+                .map(|category| category.name().trim())
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            target_networks: defaults
+                .target_networks
+                .iter()
+                .map(|network| IwtRssTargetNetwork {
+                    network: network.clone(),
+                })
+                .collect(),
+            target_accounts: Vec::new(),
+            content_warning: defaults.content_warning.clone(),
+            tags,
+            template: None,
+            kind: PostKind::discover(item, None),
+            interaction: None,
+            visibility: None,
+            language: None,
+            sensitive: None,
+            publish_at: None,
+            summary: None,
+            content: ContentSource::PRECEDENCE.to_vec(),
+        }
+    }
+
+    /// Whether the item is syndicated to the account: the default accounts by the target networks,
+    /// the named accounts when the rules add them
+    #[must_use]
+    pub fn targets(&self, account: &Account) -> bool {
+        match &account.name {
+            Some(_) => self.target_accounts.contains(account),
+            None => self
+                .target_networks
+                .iter()
+                .any(|tn| tn.network == account.network),
+        }
+    }
+
+    /// The body of the item the statuses are rendered from
+    #[must_use]
+    pub fn body(&self, item: &Item) -> Option<Body> {
+        content::body(item, self.summary.as_deref(), &self.content)
+    }
+}
+
+/// The post responds to another post, see https://indieweb.org/responses
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interaction {
+    Reply(String),
+    Repost(String),
+    Like(String),
+}
+
+impl Interaction {
+    /// Url of the post that is responded to
+    #[must_use]
+    pub fn url(&self) -> &str {
+        match self {
+            Interaction::Reply(url) | Interaction::Repost(url) | Interaction::Like(url) => url,
+        }
+    }
+}
+
+/// Kind of the post, see https://indieweb.org/post-type-discovery
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum PostKind {
+    #[default]
+    Note,
+    Article,
+    Photo,
+}
+
+impl PostKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "note" => Some(PostKind::Note),
+            "article" => Some(PostKind::Article),
+            "photo" => Some(PostKind::Photo),
+            _ => None,
+        }
+    }
+
+    /// Post type discovery: items with an image enclosure are photos, items with a title that is
+    /// not the beginning of the text are articles, everything else is a note. The text is the
+    /// body of the item in the default order of its fields, `summary` is the value of
+    /// `iwt:summary`
+    #[must_use]
+    pub fn discover(item: &Item, summary: Option<&str>) -> Self {
+        if item
+            .enclosure()
+            .is_some_and(|enclosure| enclosure.mime_type().starts_with("image/"))
+        {
+            return PostKind::Photo;
+        }
+
+        let title = item.title().map(str::trim).unwrap_or_default();
+        if title.is_empty() {
+            return PostKind::Note;
+        }
+
+        let body = content::body(item, summary, &ContentSource::PRECEDENCE);
+        let content = html::to_plain_text(
+            body.as_ref().map_or("", |body| &body.html),
+            LinkPolicy::Drop,
+            None,
+        )
+        .text;
+
+        if normalize(&content).starts_with(&normalize(title)) {
+            PostKind::Note
+        } else {
+            PostKind::Article
+        }
+    }
+}
+
+impl Display for PostKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostKind::Note => write!(f, "note"),
+            PostKind::Article => write!(f, "article"),
+            PostKind::Photo => write!(f, "photo"),
+        }
+    }
+}
+
+/// Whitespace insensitive form of the text, e.g. feeds often repeat the text as the title
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct IwtRssTargetNetwork {
     pub network: social::Network,
 }
 
+/// The elements of `iwt:extension`
+const ELEMENTS: [&str; 12] = [
+    "targetNetworks",
+    "tags",
+    "contentWarning",
+    "kind",
+    "inReplyTo",
+    "repostOf",
+    "likeOf",
+    "visibility",
+    "language",
+    "sensitive",
+    "publishAt",
+    "summary",
+];
+
+/// A problem of an element of the extension
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExtensionProblem {
+    /// Path of the element, e.g. `iwt:extension/iwt:targetNetworks/iwt:targetNetwork`
+    pub path: String,
+    pub problem: String,
+}
+
+impl Display for ExtensionProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// The extension of the item doesn't follow the schema of the IWT RSS extension
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidExtension {
+    /// Guid of the item, or its link when it has no guid
+    pub item: String,
+    pub problems: Vec<ExtensionProblem>,
+}
+
+impl Display for InvalidExtension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "InvalidExtension: {}, {}",
+            self.item,
+            self.problems
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+    }
+}
+
+impl std::error::Error for InvalidExtension {}
+
 pub trait RssItemExt {
-    fn get_iwt_extension(&self) -> Option<IwtRssExtension>;
+    /// The IWT extension of the item, `None` when the item doesn't have one
+    fn get_iwt_extension(&self) -> Result<Option<IwtRssExtension>, InvalidExtension>;
 }
 
-fn get_children<'a>(ext: &'a Extension, key: &str) -> Vec<&'a Extension> {
-    ext.children()
-        .get(key)
-        .iter()
-        .flat_map(|children| children.iter())
-        .collect::<Vec<_>>()
+fn path(key: &str) -> String {
+    format!("iwt:extension/iwt:{key}")
 }
 
-fn get_key<'a>(ext: &'a Extension, key: &str) -> Option<&'a Extension> {
-    ext.children()
-        .get(key)
-        .and_then(|children| children.first())
+/// Reads the extension, and collects the problems of its elements on the way
+#[derive(Default)]
+struct ExtensionParser {
+    problems: Vec<ExtensionProblem>,
 }
 
-fn get_value<'a>(ext: &'a Extension, key: &str) -> Option<&'a str> {
-    get_key(ext, key).and_then(rss::extension::Extension::value)
+impl ExtensionParser {
+    fn problem(&mut self, path: &str, problem: String) {
+        self.problems.push(ExtensionProblem {
+            path: path.to_string(),
+            problem,
+        });
+    }
+
+    /// The value of an element that can be set once
+    fn value<'a>(&mut self, ext: &'a Extension, key: &str) -> Option<&'a str> {
+        let elements = ext.children().get(key)?;
+        if elements.len() > 1 {
+            self.problem(&path(key), String::from("it can be set only once"));
+        }
+
+        self.text(&path(key), elements.first()?)
+    }
+
+    /// The value of an element that can be set once, parsed. `parse` describes the problem of
+    /// the invalid values
+    fn parsed<T>(
+        &mut self,
+        ext: &Extension,
+        key: &str,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Option<T> {
+        let value = self.value(ext, key)?;
+
+        parse(value)
+            .map_err(|problem| self.problem(&path(key), problem))
+            .ok()
+    }
+
+    /// The values of the `child` elements of the `key` lists
+    fn list<'a>(&mut self, ext: &'a Extension, key: &str, child: &str) -> Vec<&'a str> {
+        let mut values = Vec::new();
+
+        for list in ext.children().get(key).into_iter().flatten() {
+            for name in list.children().keys().filter(|name| *name != child) {
+                self.problem(
+                    &format!("{}/iwt:{name}", path(key)),
+                    format!("unknown element, iwt:{key} can only have iwt:{child} elements"),
+                );
+            }
+
+            for element in list.children().get(child).into_iter().flatten() {
+                values.extend(self.text(&format!("{}/iwt:{child}", path(key)), element));
+            }
+        }
+
+        values
+    }
+
+    /// The trimmed value of the element, it should not be blank
+    fn text<'a>(&mut self, path: &str, element: &'a Extension) -> Option<&'a str> {
+        let value = element
+            .value()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        if value.is_none() {
+            self.problem(path, String::from("it has no value"));
+        }
+
+        value
+    }
+
+    fn parse(&mut self, item: &Item, ext: &Extension) -> IwtRssExtension {
+        for name in ext
+            .children()
+            .keys()
+            .filter(|name| !ELEMENTS.contains(&name.as_str()))
+        {
+            self.problem(&path(name), String::from("unknown element"));
+        }
+
+        let mut target_networks = Vec::new();
+        for network in self.list(ext, "targetNetworks", "targetNetwork") {
+            match social::Network::parse(network) {
+                Some(network) => target_networks.push(IwtRssTargetNetwork { network }),
+                None => self.problem(
+                    &format!("{}/iwt:targetNetwork", path("targetNetworks")),
+                    format!("unknown network \"{network}\", it should be twitter or mastodon"),
+                ),
+            }
+        }
+
+        let tags = self
+            .list(ext, "tags", "tag")
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let content_warning = self.value(ext, "contentWarning").map(String::from);
+        let summary = self.value(ext, "summary").map(String::from);
+
+        let kind = self
+            .parsed(ext, "kind", |kind| {
+                PostKind::parse(kind).ok_or_else(|| {
+                    format!("unknown kind \"{kind}\", it should be note, article or photo")
+                })
+            })
+            .unwrap_or_else(|| PostKind::discover(item, summary.as_deref()));
+
+        let interactions = [
+            ("inReplyTo", Interaction::Reply as fn(String) -> Interaction),
+            ("repostOf", Interaction::Repost),
+            ("likeOf", Interaction::Like),
+        ]
+        .into_iter()
+        .filter_map(|(key, interaction)| {
+            self.parsed(ext, key, |url| {
+                Url::parse(url)
+                    .map(|_| interaction(url.to_string()))
+                    .map_err(|err| format!("invalid url \"{url}\": {err}"))
+            })
+        })
+        .collect::<Vec<_>>();
+        if interactions.len() > 1 {
+            self.problem(
+                "iwt:extension",
+                String::from("only one of iwt:inReplyTo, iwt:repostOf and iwt:likeOf can be set"),
+            );
+        }
+        let interaction = interactions.into_iter().next();
+
+        let visibility = self.parsed(ext, "visibility", |visibility| {
+            Visibility::parse(visibility).ok_or_else(|| {
+                format!(
+                    "unknown visibility \"{visibility}\", it should be public, unlisted, private \
+                     or direct"
+                )
+            })
+        });
+
+        let language = self.value(ext, "language").map(String::from);
+
+        let sensitive = self.parsed(ext, "sensitive", |sensitive| match sensitive {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(format!(
+                "invalid flag \"{sensitive}\", it should be true or false"
+            )),
+        });
+
+        let publish_at = self.parsed(ext, "publishAt", |publish_at| {
+            DateTime::parse_from_rfc3339(publish_at)
+                .map(|publish_at| publish_at.with_timezone(&Utc))
+                .map_err(|err| format!("invalid RFC 3339 time \"{publish_at}\": {err}"))
+        });
+
+        IwtRssExtension {
+            target_networks,
+            target_accounts: Vec::new(),
+            content_warning,
+            tags,
+            template: None,
+            kind,
+            interaction,
+            visibility,
+            language,
+            sensitive,
+            publish_at,
+            summary,
+            content: ContentSource::PRECEDENCE.to_vec(),
+        }
+    }
 }
 
 impl RssItemExt for Item {
-    fn get_iwt_extension(&self) -> Option<IwtRssExtension> {
-        // todo!()
-        self.extensions()
-            .get(&"iwt".to_string())
-            .and_then(|iwt_root| iwt_root.get("extension").map(|extensions| &extensions[0]))
-            .map(|iwt_extension| {
-                // println!("iwt: {:?}", iwt_extension);
-                let target_networks = get_key(iwt_extension, "targetNetworks")
-                    .iter()
-                    .flat_map(|target_networks| get_children(target_networks, "targetNetwork"))
-                    .map(|target_network| {
-                        let target_network_name = target_network.value().unwrap();
-                        match target_network_name {
-                            "twitter" => IwtRssTargetNetwork {
-                                network: social::Network::Twitter,
-                            },
-                            "mastodon" => IwtRssTargetNetwork {
-                                network: social::Network::Mastodon,
-                            },
-                            _ => panic!("Unknown netowrk: {target_network_name}"),
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                let tags = get_children(iwt_extension, "tags")
-                    .iter()
-                    .flat_map(|tags| get_children(tags, "tag"))
-                    .map(|tag| tag.value().unwrap().to_string())
-                    .collect();
-
-                let content_warning =
-                    get_value(iwt_extension, "contentWarning").map(std::borrow::ToOwned::to_owned);
-
-                IwtRssExtension {
-                    target_networks,
-                    content_warning,
-                    tags,
-                }
+    fn get_iwt_extension(&self) -> Result<Option<IwtRssExtension>, InvalidExtension> {
+        let Some(iwt_root) = self.extensions().get("iwt") else {
+            return Ok(None);
+        };
+
+        let mut parser = ExtensionParser::default();
+        for name in iwt_root.keys().filter(|name| *name != "extension") {
+            parser.problem(
+                &format!("iwt:{name}"),
+                String::from("unknown element, the values should be in iwt:extension"),
+            );
+        }
+
+        let extensions = iwt_root
+            .get("extension")
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        if extensions.len() > 1 {
+            parser.problem(
+                "iwt:extension",
+                String::from("the item can have only one extension"),
+            );
+        }
+        let extension = extensions.first().map(|ext| parser.parse(self, ext));
+
+        if parser.problems.is_empty() {
+            Ok(extension)
+        } else {
+            Err(InvalidExtension {
+                item: self
+                    .guid()
+                    .map(rss::Guid::value)
+                    .or(self.link())
+                    .unwrap_or_default()
+                    .to_string(),
+                problems: parser.problems,
             })
+        }
     }
 }
 
@@ -91,7 +480,7 @@ pub mod stubs {
 
     use crate::social;
 
-    fn create_extension(name: &str, value: &str) -> Extension {
+    pub fn create_extension(name: &str, value: &str) -> Extension {
         ExtensionBuilder::default()
             .name(name.to_string())
             .value(Some(value.to_string()))
@@ -179,19 +568,22 @@ pub mod stubs {
 #[cfg(test)]
 mod test {
     use crate::{
-        cross_publisher::rss_item_ext::{IwtRssExtension, IwtRssTargetNetwork},
-        social,
+        config::{ContentSource, ItemDefaults},
+        cross_publisher::rss_item_ext::{
+            ExtensionProblem, Interaction, IwtRssExtension, IwtRssTargetNetwork, PostKind,
+        },
+        social::{self, Visibility},
     };
-    use rss::Item;
+    use rss::{CategoryBuilder, EnclosureBuilder, GuidBuilder, Item};
 
-    use super::stubs::create_iwt_extension_map;
+    use super::stubs::{create_extension, create_iwt_extension_map};
     use super::RssItemExt;
 
     #[test]
     fn test_get_iwt_extension_should_return_none_when_extension_is_not_available() {
         let item = Item::default();
 
-        let extension = item.get_iwt_extension();
+        let extension = item.get_iwt_extension().unwrap();
 
         assert_eq!(extension, None);
     }
@@ -203,14 +595,24 @@ mod test {
             extensions: create_iwt_extension_map(&[], None, &Vec::new()),
             ..Default::default()
         };
-        let extension = item.get_iwt_extension();
+        let extension = item.get_iwt_extension().unwrap();
 
         assert_eq!(
             extension,
             Some(IwtRssExtension {
                 target_networks: vec![],
+                target_accounts: Vec::new(),
                 content_warning: None,
-                tags: Vec::new()
+                tags: Vec::new(),
+                template: None,
+                kind: PostKind::Note,
+                interaction: None,
+                visibility: None,
+                language: None,
+                sensitive: None,
+                publish_at: None,
+                summary: None,
+                content: ContentSource::PRECEDENCE.to_vec(),
             })
         );
     }
@@ -225,7 +627,7 @@ mod test {
             ),
             ..Default::default()
         };
-        let extension = item.get_iwt_extension();
+        let extension = item.get_iwt_extension().unwrap();
 
         assert_eq!(
             extension,
@@ -238,8 +640,18 @@ mod test {
                         network: social::Network::Twitter
                     },
                 ],
+                target_accounts: Vec::new(),
                 content_warning: None,
-                tags: Vec::new()
+                tags: Vec::new(),
+                template: None,
+                kind: PostKind::Note,
+                interaction: None,
+                visibility: None,
+                language: None,
+                sensitive: None,
+                publish_at: None,
+                summary: None,
+                content: ContentSource::PRECEDENCE.to_vec(),
             })
         );
     }
@@ -253,7 +665,7 @@ mod test {
             ),
             ..Default::default()
         };
-        let extension = item.get_iwt_extension();
+        let extension = item.get_iwt_extension().unwrap();
 
         assert_eq!(
             extension,
@@ -261,8 +673,18 @@ mod test {
                 target_networks: vec![IwtRssTargetNetwork {
                     network: social::Network::Mastodon
                 },],
+                target_accounts: Vec::new(),
                 content_warning: Some("This is a content_warning".to_string()),
-                tags: Vec::new()
+                tags: Vec::new(),
+                template: None,
+                kind: PostKind::Note,
+                interaction: None,
+                visibility: None,
+                language: None,
+                sensitive: None,
+                publish_at: None,
+                summary: None,
+                content: ContentSource::PRECEDENCE.to_vec(),
             })
         );
     }
@@ -277,7 +699,7 @@ mod test {
             ),
             ..Default::default()
         };
-        let extension = item.get_iwt_extension();
+        let extension = item.get_iwt_extension().unwrap();
 
         assert_eq!(
             extension,
@@ -285,9 +707,287 @@ mod test {
                 target_networks: vec![IwtRssTargetNetwork {
                     network: social::Network::Mastodon
                 },],
+                target_accounts: Vec::new(),
                 content_warning: Some("This is a content_warning".to_string()),
-                tags: vec!["tag-1".to_string(), "tag-2".to_string()]
+                tags: vec!["tag-1".to_string(), "tag-2".to_string()],
+                template: None,
+                kind: PostKind::Note,
+                interaction: None,
+                visibility: None,
+                language: None,
+                sensitive: None,
+                publish_at: None,
+                summary: None,
+                content: ContentSource::PRECEDENCE.to_vec(),
             })
         );
     }
+
+    #[test]
+    fn test_get_iwt_extension_should_read_the_explicit_kind() {
+        let mut extensions = create_iwt_extension_map(&[social::Network::Mastodon], None, &[]);
+        let iwt_extension = extensions
+            .get_mut("iwt")
+            .and_then(|root| root.get_mut("extension"))
+            .map(|extensions| &mut extensions[0])
+            .unwrap();
+        iwt_extension.children.insert(
+            String::from("kind"),
+            vec![create_extension("iwt:kind", "article")],
+        );
+
+        let item = Item {
+            extensions,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            item.get_iwt_extension().unwrap().unwrap().kind,
+            PostKind::Article
+        );
+    }
+
+    #[test]
+    fn test_get_iwt_extension_should_read_the_interaction() {
+        let item = |key: &str| {
+            let mut extensions = create_iwt_extension_map(&[social::Network::Mastodon], None, &[]);
+            extensions
+                .get_mut("iwt")
+                .and_then(|root| root.get_mut("extension"))
+                .map(|extensions| &mut extensions[0])
+                .unwrap()
+                .children
+                .insert(
+                    key.to_string(),
+                    vec![create_extension(
+                        &format!("iwt:{key}"),
+                        "https://mastodon.social/@someone/1",
+                    )],
+                );
+
+            Item {
+                extensions,
+                ..Default::default()
+            }
+        };
+        let url = String::from("https://mastodon.social/@someone/1");
+
+        assert_eq!(
+            item("inReplyTo")
+                .get_iwt_extension()
+                .unwrap()
+                .unwrap()
+                .interaction,
+            Some(Interaction::Reply(url.clone()))
+        );
+        assert_eq!(
+            item("repostOf")
+                .get_iwt_extension()
+                .unwrap()
+                .unwrap()
+                .interaction,
+            Some(Interaction::Repost(url.clone()))
+        );
+        assert_eq!(
+            item("likeOf")
+                .get_iwt_extension()
+                .unwrap()
+                .unwrap()
+                .interaction,
+            Some(Interaction::Like(url))
+        );
+        assert_eq!(
+            item("bookmarkOf").get_iwt_extension().unwrap_err().problems,
+            [ExtensionProblem {
+                path: String::from("iwt:extension/iwt:bookmarkOf"),
+                problem: String::from("unknown element"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_get_iwt_extension_should_read_the_status_metadata() {
+        let item = |values: &[(&str, &str)]| {
+            let mut extensions = create_iwt_extension_map(&[social::Network::Mastodon], None, &[]);
+            let iwt_extension = extensions
+                .get_mut("iwt")
+                .and_then(|root| root.get_mut("extension"))
+                .map(|extensions| &mut extensions[0])
+                .unwrap();
+            for (key, value) in values {
+                iwt_extension.children.insert(
+                    key.to_string(),
+                    vec![create_extension(&format!("iwt:{key}"), value)],
+                );
+            }
+
+            Item {
+                extensions,
+                ..Default::default()
+            }
+        };
+
+        let extension = item(&[
+            ("visibility", "unlisted"),
+            ("language", "hu"),
+            ("sensitive", "true"),
+            ("summary", "Short"),
+        ])
+        .get_iwt_extension()
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(extension.visibility, Some(Visibility::Unlisted));
+        assert_eq!(extension.language, Some(String::from("hu")));
+        assert_eq!(extension.sensitive, Some(true));
+        assert_eq!(extension.summary, Some(String::from("Short")));
+
+        let err = item(&[("visibility", "friends"), ("sensitive", "yes")])
+            .get_iwt_extension()
+            .unwrap_err();
+
+        assert_eq!(
+            err.problems
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "iwt:extension/iwt:visibility: unknown visibility \"friends\", it should be public, \
+                 unlisted, private or direct",
+                "iwt:extension/iwt:sensitive: invalid flag \"yes\", it should be true or false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_iwt_extension_should_report_the_invalid_elements() {
+        let mut extensions = create_iwt_extension_map(
+            &[social::Network::Mastodon, social::Network::Twitter],
+            None,
+            &["rust"],
+        );
+        let root = extensions.get_mut("iwt").unwrap();
+        let iwt_extension = &mut root.get_mut("extension").unwrap()[0];
+        iwt_extension.children.get_mut("targetNetworks").unwrap()[0]
+            .children
+            .get_mut("targetNetwork")
+            .unwrap()
+            .push(create_extension("iwt:targetNetwork", "myspace"));
+        iwt_extension.children.insert(
+            String::from("kind"),
+            vec![
+                create_extension("iwt:kind", "note"),
+                create_extension("iwt:kind", "photo"),
+            ],
+        );
+        iwt_extension.children.insert(
+            String::from("inReplyTo"),
+            vec![create_extension("iwt:inReplyTo", "not a url")],
+        );
+        let duplicate = iwt_extension.clone();
+        root.get_mut("extension").unwrap().push(duplicate);
+
+        let item = Item {
+            guid: Some(GuidBuilder::default().value("post-1").build()),
+            extensions,
+            ..Default::default()
+        };
+        let err = item.get_iwt_extension().unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "InvalidExtension: post-1, \
+             iwt:extension: the item can have only one extension; \
+             iwt:extension/iwt:targetNetworks/iwt:targetNetwork: unknown network \"myspace\", \
+             it should be twitter or mastodon; \
+             iwt:extension/iwt:kind: it can be set only once; \
+             iwt:extension/iwt:inReplyTo: invalid url \"not a url\": relative URL without a base"
+        );
+    }
+
+    #[test]
+    fn test_from_defaults_should_take_the_tags_from_the_categories() {
+        let item = Item {
+            categories: vec![
+                CategoryBuilder::default().name("rust").build(),
+                CategoryBuilder::default().name(" ").build(),
+            ],
+            ..Default::default()
+        };
+        let defaults = ItemDefaults {
+            target_networks: vec![social::Network::Mastodon],
+            tags_from_categories: true,
+            content_warning: Some(String::from("spoilers")),
+        };
+
+        let extension = IwtRssExtension::from_defaults(&item, &defaults);
+
+        assert_eq!(
+            extension.target_networks,
+            [IwtRssTargetNetwork {
+                network: social::Network::Mastodon
+            }]
+        );
+        assert_eq!(extension.tags, [String::from("rust")]);
+        assert_eq!(extension.content_warning, Some(String::from("spoilers")));
+
+        let extension = IwtRssExtension::from_defaults(
+            &item,
+            &ItemDefaults {
+                tags_from_categories: false,
+                ..defaults
+            },
+        );
+        assert_eq!(extension.tags, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_post_kind_discovery() {
+        let item = |title: Option<&str>, description: &str, mime_type: Option<&str>| Item {
+            title: title.map(String::from),
+            description: Some(description.to_string()),
+            enclosure: mime_type.map(|mime_type| {
+                EnclosureBuilder::default()
+                    .url("https://example.com/media")
+                    .mime_type(mime_type)
+                    .build()
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            PostKind::discover(&item(None, "<p>Just a note</p>", None), None),
+            PostKind::Note
+        );
+        assert_eq!(
+            PostKind::discover(&item(Some("Just a"), "<p>Just a  note</p>", None), None),
+            PostKind::Note
+        );
+        assert_eq!(
+            PostKind::discover(&item(Some("Title"), "<p>Some text</p>", None), None),
+            PostKind::Article
+        );
+        assert_eq!(
+            PostKind::discover(
+                &item(Some("Title"), "<p>Caption</p>", Some("image/jpeg")),
+                None
+            ),
+            PostKind::Photo
+        );
+        assert_eq!(
+            PostKind::discover(&item(None, "<p>Episode</p>", Some("audio/mpeg")), None),
+            PostKind::Note
+        );
+        assert_eq!(
+            PostKind::discover(&item(Some("Title"), " ", None), None),
+            PostKind::Note
+        );
+        assert_eq!(
+            PostKind::discover(
+                &item(Some("Title"), "<p>Title and text</p>", None),
+                Some("Text")
+            ),
+            PostKind::Article
+        );
+    }
 }
@@ -0,0 +1,513 @@
+use log::Level;
+use regex::Regex;
+use rss::Item;
+
+use super::rss_item_ext::{IwtRssExtension, IwtRssTargetNetwork};
+use crate::commons::html::{self, LinkPolicy};
+use crate::config::{Actions, Conditions, Rule};
+use crate::social::{Account, Network};
+use crate::IwtError;
+
+/// The rules of the config, their regexes are compiled once, before the items are syndicated
+pub struct Rules<'a> {
+    rules: Vec<(&'a Rule, Option<Regex>)>,
+}
+
+impl<'a> Rules<'a> {
+    pub fn compile(rules: &'a [Rule]) -> Result<Self, IwtError> {
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                let text = rule
+                    .when
+                    .text
+                    .as_ref()
+                    .map(|text| {
+                        Regex::new(text).map_err(|err| {
+                            IwtError::new(&format!(
+                                "rules[{i}].when.text: invalid regex {text}: {err}"
+                            ))
+                        })
+                    })
+                    .transpose()?;
+
+                Ok((rule, text))
+            })
+            .collect::<Result<_, IwtError>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Applies the rules that match the item to its extension, in order, so the conditions of a
+    /// rule see the changes of the earlier ones. The evaluation is logged on debug level, the
+    /// matching rules are logged on info level in dry runs
+    pub fn apply(
+        &self,
+        feed: &str,
+        post: &Item,
+        extension: &mut IwtRssExtension,
+        account: &Account,
+        dry_run: bool,
+    ) {
+        let link = post.link().unwrap_or_default();
+        let level = if dry_run { Level::Info } else { Level::Debug };
+
+        for (i, (rule, text)) in self.rules.iter().enumerate() {
+            let name = rule.name.clone().unwrap_or_else(|| format!("rules[{i}]"));
+
+            match mismatch(&rule.when, text.as_ref(), feed, post, extension) {
+                Some(condition) => {
+                    log::debug!("{link} |> Rule {name} doesn't match for {account}, {condition}");
+                }
+                None => {
+                    log::log!(
+                        level,
+                        "{link} |> Rule {name} matches for {account}: {}",
+                        describe(&rule.then)
+                    );
+                    apply(&rule.then, extension);
+                }
+            }
+        }
+    }
+
+    /// Applies the rules that match the item without logging them, e.g. to find out where the
+    /// item is routed before it is syndicated
+    pub fn route(&self, feed: &str, post: &Item, extension: &mut IwtRssExtension) {
+        for (rule, text) in &self.rules {
+            if mismatch(&rule.when, text.as_ref(), feed, post, extension).is_none() {
+                apply(&rule.then, extension);
+            }
+        }
+    }
+}
+
+/// The first condition the item doesn't match, `None` when it matches all of them. `text` is the
+/// compiled `text` condition
+fn mismatch(
+    conditions: &Conditions,
+    text: Option<&Regex>,
+    feed: &str,
+    post: &Item,
+    extension: &IwtRssExtension,
+) -> Option<String> {
+    if let Some(expected) = &conditions.feed {
+        if expected != feed {
+            return Some(format!("the feed is not {expected}"));
+        }
+    }
+
+    if let Some(tag) = &conditions.tag {
+        let tagged = extension
+            .tags
+            .iter()
+            .map(String::as_str)
+            .chain(post.categories().iter().map(rss::Category::name))
+            .any(|name| name.trim().eq_ignore_ascii_case(tag));
+        if !tagged {
+            return Some(format!("it is not tagged {tag}"));
+        }
+    }
+
+    if let Some(language) = &conditions.language {
+        if !extension
+            .language
+            .as_deref()
+            .is_some_and(|actual| is_language(actual, language))
+        {
+            return Some(format!("its language is not {language}"));
+        }
+    }
+
+    if let Some(kind) = &conditions.kind {
+        if extension.kind.to_string() != *kind {
+            return Some(format!("it is not a {kind}"));
+        }
+    }
+
+    if let Some(regex) = text {
+        if !regex.is_match(&plain_text(post, extension)) {
+            return Some(format!("its text doesn't match {regex}"));
+        }
+    }
+
+    None
+}
+
+/// The language or one of its regional variants, e.g. `de` matches `de-AT`
+fn is_language(actual: &str, expected: &str) -> bool {
+    actual.eq_ignore_ascii_case(expected)
+        || actual
+            .split_once('-')
+            .is_some_and(|(primary, _)| primary.eq_ignore_ascii_case(expected))
+}
+
+/// The title and the plain text of the body of the item
+fn plain_text(post: &Item, extension: &IwtRssExtension) -> String {
+    let body = extension.body(post);
+    let body = html::to_plain_text(
+        body.as_ref().map_or("", |body| &body.html),
+        LinkPolicy::Inline,
+        None,
+    );
+
+    format!("{}\n{}", post.title().unwrap_or_default(), body.text)
+}
+
+fn apply(actions: &Actions, extension: &mut IwtRssExtension) {
+    if let Some(networks) = &actions.only_networks {
+        extension.target_networks.clear();
+        extension.target_accounts.clear();
+        networks
+            .iter()
+            .for_each(|network| add_network(extension, network));
+    }
+
+    for network in &actions.add_networks {
+        add_network(extension, network);
+    }
+
+    extension
+        .target_networks
+        .retain(|tn| !actions.remove_networks.contains(&tn.network));
+    extension
+        .target_accounts
+        .retain(|account| !actions.remove_networks.contains(&account.network));
+
+    if let Some(accounts) = &actions.only_accounts {
+        extension.target_networks.clear();
+        extension.target_accounts.clear();
+        accounts
+            .iter()
+            .for_each(|account| add_account(extension, account));
+    }
+
+    for account in &actions.add_accounts {
+        add_account(extension, account);
+    }
+
+    for account in &actions.remove_accounts {
+        match &account.name {
+            Some(_) => extension.target_accounts.retain(|a| a != account),
+            None => extension
+                .target_networks
+                .retain(|tn| tn.network != account.network),
+        }
+    }
+
+    for tag in &actions.tags {
+        if !extension.tags.contains(tag) {
+            extension.tags.push(tag.clone());
+        }
+    }
+
+    if let Some(content_warning) = &actions.content_warning {
+        extension.content_warning = Some(content_warning.clone());
+    }
+
+    if let Some(template) = &actions.template {
+        extension.template = Some(template.clone());
+    }
+}
+
+fn add_network(extension: &mut IwtRssExtension, network: &Network) {
+    if !extension
+        .target_networks
+        .iter()
+        .any(|tn| tn.network == *network)
+    {
+        extension.target_networks.push(IwtRssTargetNetwork {
+            network: network.clone(),
+        });
+    }
+}
+
+/// Adds the named account, or the network of the default account
+fn add_account(extension: &mut IwtRssExtension, account: &Account) {
+    match &account.name {
+        Some(_) if !extension.target_accounts.contains(account) => {
+            extension.target_accounts.push(account.clone());
+        }
+        Some(_) => {}
+        None => add_network(extension, &account.network),
+    }
+}
+
+/// Lists the changes of the actions for the logs
+fn describe(actions: &Actions) -> String {
+    fn list<T: ToString>(values: &[T]) -> String {
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    let networks = list::<Network>;
+    let accounts = list::<Account>;
+
+    let mut changes = Vec::new();
+    if let Some(only_networks) = &actions.only_networks {
+        changes.push(format!("only {}", networks(only_networks)));
+    }
+    if !actions.add_networks.is_empty() {
+        changes.push(format!("adds {}", networks(&actions.add_networks)));
+    }
+    if !actions.remove_networks.is_empty() {
+        changes.push(format!("removes {}", networks(&actions.remove_networks)));
+    }
+    if let Some(only_accounts) = &actions.only_accounts {
+        changes.push(format!("only {}", accounts(only_accounts)));
+    }
+    if !actions.add_accounts.is_empty() {
+        changes.push(format!("adds {}", accounts(&actions.add_accounts)));
+    }
+    if !actions.remove_accounts.is_empty() {
+        changes.push(format!("removes {}", accounts(&actions.remove_accounts)));
+    }
+    if !actions.tags.is_empty() {
+        changes.push(format!("tags {}", actions.tags.join(", ")));
+    }
+    if let Some(content_warning) = &actions.content_warning {
+        changes.push(format!("content warning \"{content_warning}\""));
+    }
+    if let Some(template) = &actions.template {
+        changes.push(format!("template {template}"));
+    }
+
+    if changes.is_empty() {
+        String::from("no changes")
+    } else {
+        changes.join("; ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rss::{CategoryBuilder, Item};
+
+    use crate::config::{Actions, Conditions, Rule};
+    use crate::cross_publisher::rss_item_ext::stubs::create_iwt_extension_map;
+    use crate::cross_publisher::rss_item_ext::{IwtRssExtension, RssItemExt};
+    use crate::social::{Account, Network};
+
+    use super::Rules;
+
+    const FEED: &str = "http://example.com/rss.xml";
+
+    fn post(tags: &[&str], category: &str) -> (Item, IwtRssExtension) {
+        let item = Item {
+            title: Some(String::from("Borrow checker tips")),
+            description: Some(String::from("<p>Lifetimes in Rust</p>")),
+            categories: vec![CategoryBuilder::default().name(category).build()],
+            extensions: create_iwt_extension_map(
+                &[Network::Mastodon, Network::Twitter],
+                None,
+                tags,
+            ),
+            ..Default::default()
+        };
+        let mut extension = item.get_iwt_extension().unwrap().unwrap();
+        extension.language = Some(String::from("de-AT"));
+
+        (item, extension)
+    }
+
+    fn networks(extension: &IwtRssExtension) -> Vec<Network> {
+        extension
+            .target_networks
+            .iter()
+            .map(|tn| tn.network.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_apply_rules_should_change_the_matching_items() {
+        let rules = [
+            Rule {
+                name: Some(String::from("politics")),
+                when: Conditions {
+                    tag: Some(String::from("Politics")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    content_warning: Some(String::from("Politics")),
+                    ..Default::default()
+                },
+            },
+            Rule {
+                name: None,
+                when: Conditions {
+                    language: Some(String::from("de")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    only_networks: Some(vec![Network::Mastodon]),
+                    tags: vec![String::from("deutsch")],
+                    ..Default::default()
+                },
+            },
+            Rule {
+                name: Some(String::from("rust")),
+                when: Conditions {
+                    feed: Some(FEED.to_string()),
+                    text: Some(String::from("(?i)\\brust\\b")),
+                    kind: Some(String::from("article")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    remove_networks: vec![Network::Mastodon],
+                    add_networks: vec![Network::Twitter],
+                    template: Some(String::from("longform")),
+                    ..Default::default()
+                },
+            },
+        ];
+
+        let (item, mut extension) = post(&["rust"], "politics");
+        Rules::compile(&rules).unwrap().apply(
+            FEED,
+            &item,
+            &mut extension,
+            &Account::from(Network::Mastodon),
+            false,
+        );
+
+        assert_eq!(extension.content_warning, Some(String::from("Politics")));
+        assert_eq!(networks(&extension), [Network::Twitter]);
+        assert_eq!(extension.tags, ["rust", "deutsch"]);
+        assert_eq!(extension.template, Some(String::from("longform")));
+    }
+
+    #[test]
+    fn test_apply_rules_should_skip_the_rules_that_do_not_match() {
+        let rules = [Rule {
+            name: None,
+            when: Conditions {
+                feed: Some(String::from("https://other.example.com/rss.xml")),
+                ..Default::default()
+            },
+            then: Actions {
+                remove_networks: vec![Network::Twitter],
+                ..Default::default()
+            },
+        }];
+
+        let (item, mut extension) = post(&[], "misc");
+        Rules::compile(&rules).unwrap().apply(
+            FEED,
+            &item,
+            &mut extension,
+            &Account::from(Network::Mastodon),
+            true,
+        );
+
+        assert_eq!(networks(&extension), [Network::Mastodon, Network::Twitter]);
+    }
+
+    #[test]
+    fn test_apply_rules_should_route_the_items_to_the_accounts() {
+        let german = Account::named(Network::Mastodon, "german");
+        let project = Account::named(Network::Mastodon, "project");
+        let rules = [
+            Rule {
+                name: Some(String::from("rust")),
+                when: Conditions {
+                    tag: Some(String::from("rust")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    add_accounts: vec![project.clone()],
+                    ..Default::default()
+                },
+            },
+            Rule {
+                name: Some(String::from("german")),
+                when: Conditions {
+                    language: Some(String::from("de")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    only_accounts: Some(vec![german.clone()]),
+                    ..Default::default()
+                },
+            },
+        ];
+        let rules = Rules::compile(&rules).unwrap();
+
+        let (item, mut extension) = post(&["rust"], "misc");
+        extension.language = Some(String::from("en"));
+        rules.apply(FEED, &item, &mut extension, &project, false);
+
+        assert_eq!(networks(&extension), [Network::Mastodon, Network::Twitter]);
+        assert_eq!(extension.target_accounts, vec![project.clone()]);
+        assert!(extension.targets(&Account::from(Network::Mastodon)));
+        assert!(extension.targets(&project));
+        assert!(!extension.targets(&german));
+
+        let (item, mut extension) = post(&["rust"], "misc");
+        rules.apply(FEED, &item, &mut extension, &german, false);
+
+        assert_eq!(networks(&extension), []);
+        assert_eq!(extension.target_accounts, vec![german.clone()]);
+        assert!(!extension.targets(&Account::from(Network::Mastodon)));
+        assert!(!extension.targets(&project));
+        assert!(extension.targets(&german));
+    }
+
+    #[test]
+    fn test_apply_rules_should_remove_the_accounts_of_the_removed_networks() {
+        let project = Account::named(Network::Mastodon, "project");
+        let rules = [Rule {
+            name: None,
+            when: Conditions::default(),
+            then: Actions {
+                add_accounts: vec![project.clone()],
+                remove_accounts: vec![Account::from(Network::Twitter)],
+                ..Default::default()
+            },
+        }];
+
+        let (item, mut extension) = post(&[], "misc");
+        Rules::compile(&rules)
+            .unwrap()
+            .apply(FEED, &item, &mut extension, &project, false);
+        assert_eq!(networks(&extension), [Network::Mastodon]);
+        assert_eq!(extension.target_accounts, vec![project.clone()]);
+
+        let rules = [Rule {
+            name: None,
+            when: Conditions::default(),
+            then: Actions {
+                remove_networks: vec![Network::Mastodon],
+                ..Default::default()
+            },
+        }];
+        Rules::compile(&rules)
+            .unwrap()
+            .apply(FEED, &item, &mut extension, &project, false);
+        assert_eq!(networks(&extension), []);
+        assert_eq!(extension.target_accounts, []);
+    }
+
+    #[test]
+    fn test_compile_should_fail_on_an_invalid_regex() {
+        let rules = [Rule {
+            name: None,
+            when: Conditions {
+                text: Some(String::from("(rust")),
+                ..Default::default()
+            },
+            then: Actions::default(),
+        }];
+
+        match Rules::compile(&rules) {
+            Err(err) => assert!(
+                err.to_string()
+                    .contains("rules[0].when.text: invalid regex (rust"),
+                "{err}"
+            ),
+            Ok(_) => panic!("The invalid regex should fail"),
+        }
+    }
+}
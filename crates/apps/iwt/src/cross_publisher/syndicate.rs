@@ -1,117 +1,181 @@
 use super::rss;
-use ::rss::Channel;
+use ::rss::Item;
+use chrono::{DateTime, Duration, Utc};
 use futures::{Future, FutureExt, StreamExt, TryFutureExt};
 
-use super::rss_item_ext::RssItemExt;
-use super::syndicated_post;
+use super::identity::{identified, IdentifiedChannel, IdentifiedItem};
+use super::pacing::{PacedTarget, Postponed};
+use super::queue::{Queue, QueuedStatus};
+use super::rss_item_ext::{InvalidExtension, IwtRssExtension, RssItemExt};
+use super::rules::Rules;
+use super::syndicated_post::{self, idempotency_key, Attempt, PendingPost, SyndicatedPost};
 use super::target::Target;
-use crate::{Config, IwtError};
+use crate::commons::rate_limit::RateLimited;
+use crate::config::Feed;
+use crate::social::{Account, Network};
+use crate::Config;
 
 /// Orchestrates syndication
-pub async fn syndicate<R, S>(
+pub async fn syndicate<R, S, Q>(
     config: &Config,
     rss_client: &R,
     targets: &[Box<dyn Target>],
     storage: &S,
+    queue: &Q,
     dry_run: bool,
 ) -> Result<(), Box<dyn std::error::Error>>
 where
     R: rss::Client,
     S: syndicated_post::Storage,
+    Q: Queue,
 {
     log::debug!("Received config: {:?}", config);
+    let targets = targets
+        .iter()
+        .map(|target| PacedTarget::new(target.as_ref(), config.pacing(&target.network())))
+        .collect::<Vec<_>>();
+
+    let rules = Rules::compile(&config.rules)?;
+
+    reconcile_attempts(storage, &targets, dry_run).await?;
+
     run_and_collect(config.rss.urls.iter(), |url| {
-        rss_client
-            .get_channel(url)
-            .and_then(|channel| syndycate_channel(channel, targets, storage, dry_run))
+        rss_client.get_channel(url).and_then(|channel| async {
+            let feed = config.rss.feeds.get(url);
+            let grace_period = feed
+                .and_then(|feed| feed.grace_period)
+                .unwrap_or(config.rss.grace_period);
+            let approval_queue = feed
+                .and_then(|feed| feed.require_approval)
+                .unwrap_or(config.rss.require_approval)
+                .then_some(queue);
+            let max_age = feed.and_then(|feed| feed.max_age).or(config.rss.max_age);
+            let (channel, invalid_items) = identified(channel, url, config.identity(url));
+
+            let settings = ChannelSettings {
+                url,
+                feed,
+                grace_period,
+                rules: &rules,
+            };
+
+            let mut result = syndycate_channel(
+                without_old_items(channel, max_age, Utc::now()),
+                &settings,
+                &targets,
+                storage,
+                approval_queue,
+                dry_run,
+            )
+            .await;
+
+            // The invalid items fail the run, after the rest of the feed is syndicated
+            for invalid_item in invalid_items {
+                if result.is_ok() {
+                    result = Err(Box::new(invalid_item));
+                } else {
+                    log::error!("{invalid_item}");
+                }
+            }
+
+            result
+        })
     })
     .await
 }
 
-/// Syndicates a single channel
-async fn syndycate_channel<S: syndicated_post::Storage>(
-    channel: Channel,
-    targets: &[Box<dyn Target>],
+/// The settings a channel is syndicated by
+struct ChannelSettings<'a> {
+    url: &'a str,
+    feed: Option<&'a Feed>,
+    grace_period: u64,
+    rules: &'a Rules<'a>,
+}
+
+/// Syndicates a single channel, the posts are syndicated to each target oldest first. The statuses
+/// are queued for approval when the queue is given
+async fn syndycate_channel<S: syndicated_post::Storage, Q: Queue>(
+    channel: IdentifiedChannel,
+    settings: &ChannelSettings<'_>,
+    targets: &[PacedTarget<'_>],
     storage: &S,
+    approval_queue: Option<&Q>,
     dry_run: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let feed = settings.feed;
+    warn_about_unconfigured_accounts(&channel, settings, targets);
+
     run_and_collect(targets.iter(), |target| {
-        run_and_collect(channel.items.iter(), |post| {
+        run_in_order(chronological(&channel.items), |identified| {
             log::info!(
                 "{} |> Syndicating post to {}",
-                post.link().unwrap(),
-                target.network().to_string()
+                identified.link,
+                target.account()
             );
-            let stored = storage.find(&post.guid.as_ref().unwrap().value, &target.network());
+            let stored = storage.find(&identified.identity, &target.account());
 
             // println!("Post: {:?}", post);
 
             async {
+                let (link, post) = (&identified.link, &identified.item);
                 match stored {
                     Ok(None) => {
                         log::info!(
-                            "{} |> Post not found in DB, syndycating to {}",
-                            post.link().unwrap(),
-                            target.network().to_string()
+                            "{link} |> Post not found in DB, syndycating to {}",
+                            target.account()
                         );
 
-                        if let Some(extension) = post.get_iwt_extension() {
-                            if extension
-                                .target_networks
-                                .iter()
-                                .any(|tn| tn.network == target.network())
-                            {
-                                if dry_run {
-                                    log::info!(
-                                        "{} |> Publishing to {} is skipped due to --dry-run",
-                                        post.link().unwrap(),
-                                        target.network().to_string()
-                                    );
-                                    Ok(())
+                        match extension(post, feed) {
+                            Ok(Some(mut extension)) => {
+                                apply_feed_settings(
+                                    &mut extension,
+                                    feed,
+                                    channel.channel.language(),
+                                    &target.network(),
+                                );
+                                settings.rules.apply(
+                                    settings.url,
+                                    post,
+                                    &mut extension,
+                                    &target.account(),
+                                    dry_run,
+                                );
+
+                                if extension.targets(&target.account()) {
+                                    let due_at = due_at(post, &extension, settings.grace_period);
+
+                                    publish_when_due(
+                                        identified,
+                                        &extension,
+                                        target,
+                                        storage,
+                                        approval_queue,
+                                        due_at,
+                                        dry_run,
+                                    )
+                                    .await
                                 } else {
                                     log::info!(
-                                        "{} |> Publishing to {}",
-                                        post.link().unwrap(),
-                                        target.network().to_string()
+                                        "{link} |> Not configured to be syndicated to {}",
+                                        target.account()
                                     );
-                                    let result = target
-                                        .publish(post, &extension)
-                                        .map(|result| {
-                                            result.and_then(|syndicated| {
-                                                storage.store(syndicated).map_err(|err| {
-                                                    Box::new(err) as Box<dyn std::error::Error>
-                                                })
-                                            })
-                                        })
-                                        .await;
-                                    log::info!(
-                                        "{} |> Published to {}",
-                                        post.link().unwrap(),
-                                        target.network().to_string()
-                                    );
-
-                                    result
+                                    Ok(())
                                 }
-                            } else {
+                            }
+                            Ok(None) => {
                                 log::info!(
-                                    "{} |> Not configured to be syndicated to {}",
-                                    post.link().unwrap(),
-                                    target.network().to_string()
+                                    "{link} |> Doesn't have an IWT extension and the feed has no \
+                                     defaults, skipping"
                                 );
                                 Ok(())
                             }
-                        } else {
-                            Err(
-                                Box::new(IwtError::new("Rss Item doesn't have an IWT extension"))
-                                    as Box<dyn std::error::Error>,
-                            )
+                            Err(err) => Err(Box::new(err) as Box<dyn std::error::Error>),
                         }
                     }
                     Ok(Some(_)) => {
                         log::info!(
-                            "{} |> Has been already syndicated to {}",
-                            post.link().unwrap(),
-                            target.network().to_string()
+                            "{link} |> Has been already syndicated to {}",
+                            target.account()
                         );
                         Ok(())
                     }
@@ -123,6 +187,361 @@ async fn syndycate_channel<S: syndicated_post::Storage>(
     .await
 }
 
+/// Drops the items published more than `max_age` seconds ago, the items without a publication
+/// date are kept
+fn without_old_items(
+    mut channel: IdentifiedChannel,
+    max_age: Option<u64>,
+    now: DateTime<Utc>,
+) -> IdentifiedChannel {
+    if let Some(max_age) = max_age {
+        let cutoff = now - Duration::seconds(i64::try_from(max_age).unwrap_or(i64::MAX));
+
+        channel.items.retain(|item| {
+            let published = item
+                .item
+                .pub_date()
+                .and_then(|pub_date| DateTime::parse_from_rfc2822(pub_date).ok());
+
+            match published {
+                Some(published) if published < cutoff => {
+                    log::info!("{} |> Published before the max age, ignoring", item.link);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    channel
+}
+
+/// The time the item should be syndicated at, `None` means that it is due now
+fn due_at(post: &Item, extension: &IwtRssExtension, grace_period: u64) -> Option<DateTime<Utc>> {
+    extension.publish_at.or_else(|| {
+        post.pub_date()
+            .and_then(|pub_date| DateTime::parse_from_rfc2822(pub_date).ok())
+            .map(|pub_date| {
+                pub_date.with_timezone(&Utc)
+                    + Duration::seconds(i64::try_from(grace_period).unwrap_or(i64::MAX))
+            })
+    })
+}
+
+/// Publishes the post when it is due, otherwise records it as pending, or lets the network
+/// schedule it. With an approval queue the rendered status is queued instead of publishing it
+async fn publish_when_due<S: syndicated_post::Storage, Q: Queue>(
+    identified: &IdentifiedItem,
+    extension: &IwtRssExtension,
+    target: &PacedTarget<'_>,
+    storage: &S,
+    approval_queue: Option<&Q>,
+    due_at: Option<DateTime<Utc>>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (link, guid, post) = (&identified.link, &identified.identity, &identified.item);
+    let account = target.account();
+    let pending = storage.find_pending(guid, &account)?;
+
+    if let Some(PendingPost {
+        scheduled_id: Some(scheduled_id),
+        due_at,
+        ..
+    }) = &pending
+    {
+        if dry_run {
+            log::info!(
+                "{link} |> Scheduled on {account} at {due_at}, not checked due to --dry-run"
+            );
+            return Ok(());
+        }
+
+        return match target.find_scheduled(post, scheduled_id, *due_at).await? {
+            Some(id) => {
+                log::info!("{link} |> The scheduled status is published on {account}");
+                storage.store(SyndicatedPost::new(account.clone(), &id, identified))?;
+                storage.remove_pending(guid, &account)?;
+                Ok(())
+            }
+            None => {
+                log::info!("{link} |> Scheduled on {account} at {due_at}");
+                Ok(())
+            }
+        };
+    }
+
+    if let Some(due_at) = due_at.filter(|due_at| *due_at > Utc::now()) {
+        if dry_run {
+            log::info!("{link} |> Not due until {due_at}, it would be pending for {account}");
+            return Ok(());
+        }
+
+        let mut pending_post = PendingPost::new(account.clone(), identified, due_at);
+        // The network would publish it without approval
+        if approval_queue.is_none() {
+            pending_post.scheduled_id = target.schedule(post, extension, due_at).await?;
+        }
+
+        match &pending_post.scheduled_id {
+            Some(_) => log::info!("{link} |> Scheduled on {account} at {due_at}"),
+            None => log::info!("{link} |> Not due until {due_at}, pending for {account}"),
+        }
+
+        storage.store_pending(pending_post)?;
+        return Ok(());
+    }
+
+    if let Some(queue) = approval_queue {
+        if let Some(queued) = queue.find(guid, &account)? {
+            log::info!(
+                "{link} |> The status for {account} is {} in the queue as #{}",
+                queued.state,
+                queued.id
+            );
+            return Ok(());
+        }
+
+        if dry_run {
+            log::info!("{link} |> Queueing for {account} is skipped due to --dry-run");
+            return Ok(());
+        }
+
+        let status = target.render(post, extension).await?;
+        let id = queue.enqueue(QueuedStatus::new(account.clone(), identified, status))?;
+        log::info!("{link} |> Queued for approval to {account} as #{id}");
+
+        if pending.is_some() {
+            storage.remove_pending(guid, &account)?;
+        }
+
+        return Ok(());
+    }
+
+    if dry_run {
+        log::info!("{link} |> Publishing to {account} is skipped due to --dry-run");
+        return Ok(());
+    }
+
+    // Recorded first, so the post is not published again when the run is interrupted before the
+    // syndicated post is stored
+    let attempt = match storage.find_attempt(guid, &account)? {
+        Some(attempt) => {
+            log::info!("{link} |> Retrying the interrupted publishing to {account}");
+            attempt
+        }
+        None => {
+            let key = idempotency_key(guid, &account);
+            let attempt = Attempt::new(account.clone(), identified, key);
+            storage.store_attempt(attempt.clone())?;
+            attempt
+        }
+    };
+
+    log::info!("{link} |> Publishing to {account}");
+    target
+        .publish(post, extension, &attempt.idempotency_key)
+        .map(|result| {
+            result.and_then(|id| {
+                storage
+                    .store(SyndicatedPost::new(account.clone(), &id, identified))
+                    .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
+            })
+        })
+        .await?;
+    storage.remove_attempt(guid, &account)?;
+    log::info!("{link} |> Published to {account}");
+
+    if pending.is_some() {
+        storage.remove_pending(guid, &account)?;
+    }
+
+    Ok(())
+}
+
+/// Resolves the attempts of the interrupted runs: when the status of an attempt is found on the
+/// network, it is stored as the syndicated post. Otherwise the attempt is kept, and the post is
+/// retried with the same idempotency key
+async fn reconcile_attempts<S: syndicated_post::Storage>(
+    storage: &S,
+    targets: &[PacedTarget<'_>],
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for attempt in storage.list_attempts()? {
+        let link = &attempt.original_uri;
+        let account = &attempt.account;
+
+        if storage.find(&attempt.original_guid, account)?.is_some() {
+            if !dry_run {
+                storage.remove_attempt(&attempt.original_guid, account)?;
+            }
+            continue;
+        }
+
+        let Some(target) = targets.iter().find(|target| target.account() == *account) else {
+            log::warn!(
+                "{link} |> Publishing to {account} was interrupted, but it is not configured"
+            );
+            continue;
+        };
+
+        match target.find_copy(link).await {
+            Ok(Some(id)) if dry_run => log::info!(
+                "{link} |> The interrupted publishing went through on {account} as {id}, not \
+                 recorded due to --dry-run"
+            ),
+            Ok(Some(id)) => {
+                storage.store(attempt.syndicated(&id))?;
+                storage.remove_attempt(&attempt.original_guid, account)?;
+                log::info!(
+                    "{link} |> The interrupted publishing went through on {account} as {id}"
+                );
+            }
+            Ok(None) => log::info!(
+                "{link} |> Publishing to {account} was interrupted, no status is found, retrying"
+            ),
+            Err(err) => log::warn!(
+                "{link} |> Publishing to {account} was interrupted, couldn't look for its status: \
+                 {err}"
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// The extension of the item, or the defaults of the feed when the item doesn't have one.
+/// `None` when neither is set
+pub fn extension(
+    post: &Item,
+    feed: Option<&Feed>,
+) -> Result<Option<IwtRssExtension>, InvalidExtension> {
+    Ok(post.get_iwt_extension()?.or_else(|| {
+        feed.and_then(|feed| feed.defaults.as_ref())
+            .map(|defaults| IwtRssExtension::from_defaults(post, defaults))
+    }))
+}
+
+/// Completes the extension with the settings of the feed, the values set by the item take
+/// precedence
+pub fn apply_feed_settings(
+    extension: &mut IwtRssExtension,
+    feed: Option<&Feed>,
+    channel_language: Option<&str>,
+    network: &Network,
+) {
+    extension.template = feed.and_then(|feed| feed.templates.get(&network.to_string()).cloned());
+    apply_feed_defaults(extension, feed, channel_language);
+}
+
+/// The settings of the feed that are the same for all the networks
+fn apply_feed_defaults(
+    extension: &mut IwtRssExtension,
+    feed: Option<&Feed>,
+    channel_language: Option<&str>,
+) {
+    if let Some(feed) = feed {
+        extension.visibility = extension.visibility.or(feed.visibility);
+        extension.language = extension.language.take().or_else(|| feed.language.clone());
+        extension.sensitive = extension.sensitive.or(feed.sensitive);
+        if let Some(content) = &feed.content {
+            extension.content.clone_from(content);
+        }
+    }
+
+    extension.language = extension
+        .language
+        .take()
+        .or_else(|| channel_language.map(String::from));
+}
+
+/// Warns about the items that should be syndicated to a network or an account without a
+/// configured target, after the rules routed them
+fn warn_about_unconfigured_accounts(
+    channel: &IdentifiedChannel,
+    settings: &ChannelSettings<'_>,
+    targets: &[PacedTarget<'_>],
+) {
+    let configured = targets
+        .iter()
+        .map(|target| target.account())
+        .collect::<Vec<_>>();
+
+    for post in &channel.items {
+        for account in
+            unconfigured_accounts(post, channel.channel.language(), settings, &configured)
+        {
+            log::warn!(
+                "{} |> Should be syndicated to {account}, but it is not configured, skipping",
+                post.link
+            );
+        }
+    }
+}
+
+/// The accounts the item is routed to by its extension, the feed settings and the rules, that
+/// are not configured. The networks stand for their default accounts
+fn unconfigured_accounts(
+    post: &IdentifiedItem,
+    channel_language: Option<&str>,
+    settings: &ChannelSettings<'_>,
+    configured: &[Account],
+) -> Vec<Account> {
+    // The invalid extensions are reported when the post is syndicated
+    let Ok(Some(mut extension)) = extension(&post.item, settings.feed) else {
+        return Vec::new();
+    };
+    apply_feed_defaults(&mut extension, settings.feed, channel_language);
+    settings
+        .rules
+        .route(settings.url, &post.item, &mut extension);
+
+    extension
+        .target_networks
+        .iter()
+        .map(|target_network| Account::from(target_network.network.clone()))
+        .chain(extension.target_accounts)
+        .filter(|account| !configured.contains(account))
+        .collect()
+}
+
+/// The items ordered by their publication date, oldest first. The items without a date come
+/// first, in the order of the feed
+fn chronological(items: &[IdentifiedItem]) -> Vec<&IdentifiedItem> {
+    let mut items = items.iter().collect::<Vec<_>>();
+    items.sort_by_key(|item| {
+        item.item
+            .pub_date()
+            .and_then(|pub_date| DateTime::parse_from_rfc2822(pub_date).ok())
+    });
+    items
+}
+
+/// Syndicates the posts one after the other. The posts postponed to a later run don't fail the
+/// run, otherwise the first error is returned after all the posts are tried
+async fn run_in_order<'a, F, Fu>(
+    posts: Vec<&'a IdentifiedItem>,
+    f: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(&'a IdentifiedItem) -> Fu,
+    Fu: Future<Output = Result<(), Box<dyn std::error::Error>>>,
+{
+    let mut result = Ok(());
+
+    for post in posts {
+        match f(post).await {
+            Ok(()) => {}
+            Err(err) if err.is::<Postponed>() || err.is::<RateLimited>() => {
+                log::warn!("{} |> {err}", post.link);
+            }
+            Err(err) if result.is_ok() => result = Err(err),
+            Err(err) => log::error!("{} |> {err}", post.link),
+        }
+    }
+
+    result
+}
+
 async fn run_and_collect<C, I, F, Fu>(items: C, f: F) -> Result<(), Box<dyn std::error::Error>>
 where
     C: Iterator<Item = I>,
@@ -142,43 +561,64 @@ where
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::sync::Arc;
 
+    use chrono::{DateTime, Duration, Utc};
     use oauth2::{AccessToken, ClientId};
-    use rss::Item;
-
-    use super::syndicated_post::{Storage, SyndicatedPost};
-    use crate::config::{Config, Mastodon, Rss, Twitter, UrlShortener, DB};
+    use rss::{Channel, Item};
+
+    use super::syndicated_post::{idempotency_key, Attempt, PendingPost, Storage, SyndicatedPost};
+    use crate::config::{
+        Actions, Conditions, Config, ContentSource, Feed, Identity, ItemDefaults, Mastodon, Rss,
+        Rule, Twitter, UrlShortener, DB,
+    };
+    use crate::cross_publisher::queue::QueueState;
     use crate::cross_publisher::rss::stubs::gen_items_with_extension;
     use crate::cross_publisher::rss_item_ext::stubs::create_iwt_extension_map;
     use crate::cross_publisher::rss_item_ext::RssItemExt;
+    use crate::cross_publisher::stubs::queue::QueueStub;
     use crate::cross_publisher::stubs::rss::{gen_items, StubRssClient};
     use crate::cross_publisher::stubs::syndycated_post::SyndicatedPostStorageStub;
     use crate::cross_publisher::stubs::target::FailingStubTarget;
     use crate::cross_publisher::stubs::target::StubTarget;
-    use crate::social::{self, Network};
+    use crate::cross_publisher::target::PublishedStatus;
+    use crate::social::{self, Account, Network, Visibility};
 
-    use super::syndicate;
+    use super::{
+        apply_feed_settings, chronological, due_at, syndicate, unconfigured_accounts,
+        without_old_items, ChannelSettings,
+    };
+    use crate::cross_publisher::identity::{IdentifiedChannel, IdentifiedItem};
+    use crate::cross_publisher::rules::Rules;
+    use crate::cross_publisher::stubs::identity::identified_item;
 
     fn config(urls: Vec<String>) -> Config {
         Config {
-            rss: Rss { urls },
+            rss: Rss {
+                urls,
+                feeds: BTreeMap::new(),
+                grace_period: 0,
+                require_approval: false,
+                max_age: None,
+                identity: Identity::Guid,
+            },
             db: DB {
                 path: String::from("some/path"),
+                token_key: None,
             },
-            twitter: Twitter {
-                client_id: ClientId::new(String::from("some_client_id")),
-            },
-            mastodon: Mastodon {
-                base_uri: String::from("https://example.com/mastodon"),
-                access_token: AccessToken::new(String::from("some-access-token")),
-            },
-            url_shortener: UrlShortener {
+            twitter: Some(Twitter::new(ClientId::new(String::from("some_client_id")))),
+            mastodon: Some(Mastodon::new(
+                String::from("https://example.com/mastodon"),
+                AccessToken::new(String::from("some-access-token")),
+            )),
+            url_shortener: Some(UrlShortener {
                 protocol: String::from("http"),
                 domain: String::from("shortly"),
                 put_base_uri: Some(String::from("http://localhost:9000")),
-            },
+            }),
+            templates: BTreeMap::new(),
+            rules: Vec::new(),
         }
     }
 
@@ -197,6 +637,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await
@@ -223,6 +664,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await
@@ -248,6 +690,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await
@@ -274,16 +717,23 @@ mod test {
         for item in items.get(feed).unwrap() {
             storage
                 .store(SyndicatedPost::new(
-                    Network::Mastodon,
+                    Network::Mastodon.into(),
                     &String::from("id"),
-                    item,
+                    &identified_item(item),
                 ))
                 .unwrap();
         }
 
-        syndicate(&config, &client, &targets, &storage, false)
-            .await
-            .expect("Should be Ok()");
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
 
         let calls = (*target_calls).lock().await;
 
@@ -310,6 +760,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await
@@ -389,6 +840,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await
@@ -401,6 +853,7 @@ mod test {
             .into_iter()
             .filter(|item| {
                 item.get_iwt_extension()
+                    .unwrap()
                     .unwrap()
                     .target_networks
                     .iter()
@@ -417,6 +870,7 @@ mod test {
             .into_iter()
             .filter(|item| {
                 item.get_iwt_extension()
+                    .unwrap()
                     .unwrap()
                     .target_networks
                     .iter()
@@ -449,6 +903,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             true,
         )
         .await
@@ -484,6 +939,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await;
@@ -505,7 +961,7 @@ mod test {
 
         let items = gen_items(&[feed1, feed2]);
         let client = StubRssClient::new(&items);
-        let stub_target1 = FailingStubTarget::default();
+        let stub_target1 = FailingStubTarget;
         let stub_target2 = StubTarget::new(Network::Mastodon);
         let target_calls2 = Arc::clone(&stub_target2.calls);
 
@@ -516,6 +972,7 @@ mod test {
             &client,
             &targets,
             &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
             false,
         )
         .await;
@@ -526,6 +983,277 @@ mod test {
         assert_eq!(*calls2, merged_items(&items, &[feed1, feed2]));
     }
 
+    #[tokio::test]
+    async fn test_syndycate_should_report_the_invalid_items_and_publish_the_rest() {
+        let feed = "http://example.com/rss.xml";
+        let config = config(vec![feed.to_string()]);
+
+        let mut items = gen_items(&[feed]);
+        let posts = items.get_mut(feed).unwrap();
+        let mut without_guid = posts[0].clone();
+        without_guid.guid = None;
+        without_guid.link = Some(format!("{feed}/post-without-guid"));
+        let mut without_link = posts[1].clone();
+        without_link.link = None;
+        without_link.guid.as_mut().unwrap().set_permalink(false);
+        without_link.title = Some(String::from("Without a link"));
+        posts.extend([without_guid.clone(), without_link]);
+
+        let client = StubRssClient::new(&items);
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+        let storage = SyndicatedPostStorageStub::default();
+
+        let err = syndicate(
+            &config,
+            &client,
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("InvalidItem: Without a link in {feed}, it has no link")
+        );
+        let calls = (*target_calls).lock().await;
+        assert_eq!(calls.len(), 5);
+        assert!(storage
+            .find(
+                &format!("{feed}/post-without-guid"),
+                &Network::Mastodon.into()
+            )
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_use_the_feed_defaults_for_the_items_without_extension() {
+        let feed1 = "http://example.com/rss.xml";
+        let feed2 = "https://blog.example.com/rss.xml";
+        let mut config = config(vec![feed1.to_string(), feed2.to_string()]);
+        config.rss.feeds.insert(
+            feed1.to_string(),
+            Feed {
+                defaults: Some(ItemDefaults {
+                    target_networks: vec![Network::Mastodon],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+
+        let mut items = gen_items(&[feed1, feed2]);
+        for item in items.values_mut().flatten() {
+            item.extensions.clear();
+        }
+        let client = StubRssClient::new(&items);
+        let mastodon = StubTarget::new(Network::Mastodon);
+        let mastodon_calls = Arc::clone(&mastodon.calls);
+        let twitter = StubTarget::new(Network::Twitter);
+        let twitter_calls = Arc::clone(&twitter.calls);
+        let targets = vec![mastodon.into(), twitter.into()];
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("The items without defaults should be skipped");
+
+        assert_eq!(*mastodon_calls.lock().await, items[feed1]);
+        assert_eq!(*twitter_calls.lock().await, []);
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_route_the_posts_by_the_rules() {
+        let feed = "http://example.com/rss.xml";
+        let mut config = config(vec![feed.to_string()]);
+        config.rules = vec![Rule {
+            name: Some(String::from("third post")),
+            when: Conditions {
+                text: Some(String::from("pos #2 ")),
+                ..Default::default()
+            },
+            then: Actions {
+                only_networks: Some(vec![Network::Twitter]),
+                ..Default::default()
+            },
+        }];
+
+        let items = gen_items(&[feed]);
+        let client = StubRssClient::new(&items);
+        let mastodon = StubTarget::new(Network::Mastodon);
+        let mastodon_calls = Arc::clone(&mastodon.calls);
+        let twitter = StubTarget::new(Network::Twitter);
+        let twitter_calls = Arc::clone(&twitter.calls);
+        let targets = vec![mastodon.into(), twitter.into()];
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let posts = &items[feed];
+        assert_eq!(
+            *mastodon_calls.lock().await,
+            [posts[0].clone(), posts[1].clone(), posts[3].clone()]
+        );
+        assert_eq!(*twitter_calls.lock().await, *posts);
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_route_the_posts_to_the_accounts_by_the_rules() {
+        let feed = "http://example.com/rss.xml";
+        let german = Account::named(Network::Mastodon, "german");
+        let project = Account::named(Network::Mastodon, "project");
+        let mut config = config(vec![feed.to_string()]);
+        config.rules = vec![
+            Rule {
+                name: Some(String::from("german")),
+                when: Conditions {
+                    text: Some(String::from("pos #1 ")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    only_accounts: Some(vec![german.clone()]),
+                    ..Default::default()
+                },
+            },
+            Rule {
+                name: Some(String::from("rust")),
+                when: Conditions {
+                    text: Some(String::from("pos #2 ")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    add_accounts: vec![project.clone()],
+                    ..Default::default()
+                },
+            },
+        ];
+
+        let items = gen_items(&[feed]);
+        let client = StubRssClient::new(&items);
+        let mastodon = StubTarget::new(Network::Mastodon);
+        let mastodon_calls = Arc::clone(&mastodon.calls);
+        let german_target = StubTarget::named(Network::Mastodon, "german");
+        let german_calls = Arc::clone(&german_target.calls);
+        let project_target = StubTarget::named(Network::Mastodon, "project");
+        let project_calls = Arc::clone(&project_target.calls);
+        let twitter = StubTarget::new(Network::Twitter);
+        let twitter_calls = Arc::clone(&twitter.calls);
+        let targets = vec![
+            mastodon.into(),
+            german_target.into(),
+            project_target.into(),
+            twitter.into(),
+        ];
+        let storage = SyndicatedPostStorageStub::default();
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let posts = &items[feed];
+        let others = [posts[0].clone(), posts[2].clone(), posts[3].clone()];
+        assert_eq!(*mastodon_calls.lock().await, others);
+        assert_eq!(*twitter_calls.lock().await, others);
+        assert_eq!(*german_calls.lock().await, [posts[1].clone()]);
+        assert_eq!(*project_calls.lock().await, [posts[2].clone()]);
+        assert_eq!(
+            storage
+                .find(posts[1].guid().unwrap().value(), &german)
+                .unwrap(),
+            Some(SyndicatedPost::new(
+                german.clone(),
+                "0",
+                &identified_item(&posts[1])
+            ))
+        );
+        assert_eq!(
+            storage
+                .find(posts[1].guid().unwrap().value(), &Network::Mastodon.into())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unconfigured_accounts_should_be_found_after_the_rules() {
+        let feed = "http://example.com/rss.xml";
+        let german = Account::named(Network::Mastodon, "german");
+        let project = Account::named(Network::Mastodon, "project");
+        let rules = [
+            Rule {
+                name: Some(String::from("german")),
+                when: Conditions {
+                    text: Some(String::from("pos #1 ")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    only_accounts: Some(vec![german.clone()]),
+                    ..Default::default()
+                },
+            },
+            Rule {
+                name: Some(String::from("rust")),
+                when: Conditions {
+                    text: Some(String::from("pos #2 ")),
+                    ..Default::default()
+                },
+                then: Actions {
+                    add_accounts: vec![project.clone()],
+                    ..Default::default()
+                },
+            },
+        ];
+        let rules = Rules::compile(&rules).unwrap();
+        let settings = ChannelSettings {
+            url: feed,
+            feed: None,
+            grace_period: 0,
+            rules: &rules,
+        };
+        let configured = [Account::from(Network::Mastodon), german];
+
+        let unconfigured = gen_items(&[feed])[feed]
+            .iter()
+            .map(|post| unconfigured_accounts(&identified_item(post), None, &settings, &configured))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            unconfigured,
+            [
+                vec![Account::from(Network::Twitter)],
+                vec![],
+                vec![Account::from(Network::Twitter), project],
+                vec![Account::from(Network::Twitter)],
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_syndycate_should_store_the_syndicated_posts() {
         let feed1 = "http://example.com/rss.xml";
@@ -540,15 +1268,22 @@ mod test {
         let targets = vec![stub_target1.into(), stub_target2.into()];
         let storage = SyndicatedPostStorageStub::default();
 
-        syndicate(&config, &client, &targets, &storage, false)
-            .await
-            .expect("Should be Ok()");
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
 
         let mut expected = merged_items(&items, &[feed1, feed2])
             .iter()
             .enumerate()
             .map(|(i, item)| SyndicatedPost {
-                social_network: Network::Mastodon,
+                account: Network::Mastodon.into(),
                 id: i.to_string(),
                 original_guid: String::from(item.guid().unwrap().value()),
                 original_uri: String::from(item.link().unwrap()),
@@ -560,7 +1295,7 @@ mod test {
                 .iter()
                 .enumerate()
                 .map(|(i, item)| SyndicatedPost {
-                    social_network: Network::Twitter,
+                    account: Network::Twitter.into(),
                     id: i.to_string(),
                     original_guid: String::from(item.guid().unwrap().value()),
                     original_uri: String::from(item.link().unwrap()),
@@ -571,22 +1306,432 @@ mod test {
         let mut posts = storage.posts.lock().unwrap();
         // Sort vecs as the order doesn't matter
         // TODO: maybe use HashSet?
-        expected.sort_by_key(|i| {
-            (
-                i.social_network.clone(),
-                i.id.clone(),
-                i.original_uri.clone(),
-            )
-        });
-        posts.sort_by_key(|i| {
-            (
-                i.social_network.clone(),
-                i.id.clone(),
-                i.original_uri.clone(),
-            )
-        });
+        expected.sort_by_key(|i| (i.account.clone(), i.id.clone(), i.original_uri.clone()));
+        posts.sort_by_key(|i| (i.account.clone(), i.id.clone(), i.original_uri.clone()));
 
         assert_eq!(posts.len(), expected.len());
         assert_eq!(*posts, expected);
     }
+
+    #[test]
+    fn test_apply_feed_settings_should_complete_the_extension() {
+        let item = Item {
+            extensions: create_iwt_extension_map(&[Network::Mastodon], None, &[]),
+            ..Default::default()
+        };
+        let feed = Feed {
+            templates: BTreeMap::from([(String::from("mastodon"), String::from("article"))]),
+            visibility: Some(Visibility::Unlisted),
+            language: None,
+            sensitive: Some(true),
+            grace_period: None,
+            require_approval: None,
+            max_age: None,
+            identity: None,
+            content: Some(vec![ContentSource::Description]),
+            defaults: None,
+        };
+
+        let mut extension = item.get_iwt_extension().unwrap().unwrap();
+        apply_feed_settings(
+            &mut extension,
+            Some(&feed),
+            Some("en-us"),
+            &Network::Mastodon,
+        );
+
+        assert_eq!(extension.template, Some(String::from("article")));
+        assert_eq!(extension.visibility, Some(Visibility::Unlisted));
+        assert_eq!(extension.language, Some(String::from("en-us")));
+        assert_eq!(extension.sensitive, Some(true));
+        assert_eq!(extension.content, [ContentSource::Description]);
+
+        let mut extension = item.get_iwt_extension().unwrap().unwrap();
+        extension.visibility = Some(Visibility::Private);
+        extension.language = Some(String::from("hu"));
+        apply_feed_settings(
+            &mut extension,
+            Some(&Feed {
+                language: Some(String::from("de")),
+                ..feed
+            }),
+            Some("en-us"),
+            &Network::Twitter,
+        );
+
+        assert_eq!(extension.template, None);
+        assert_eq!(extension.visibility, Some(Visibility::Private));
+        assert_eq!(extension.language, Some(String::from("hu")));
+    }
+
+    fn items_published_at(feed: &str, pub_date: DateTime<Utc>) -> HashMap<String, Vec<Item>> {
+        let mut items = gen_items(&[feed]);
+        for item in items.get_mut(feed).unwrap() {
+            item.pub_date = Some(pub_date.to_rfc2822());
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_record_the_posts_that_are_not_due_as_pending() {
+        let feed = "http://example.com/rss.xml";
+        let mut config = config(vec![feed.to_string()]);
+        config.rss.grace_period = 3600;
+
+        let items = items_published_at(feed, Utc::now() - Duration::minutes(10));
+        let client = StubRssClient::new(&items);
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+        let storage = SyndicatedPostStorageStub::default();
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
+
+        assert_eq!(*target_calls.lock().await, []);
+
+        let pending_posts = storage.pending_posts.lock().unwrap();
+        assert_eq!(pending_posts.len(), items.get(feed).unwrap().len());
+        assert!(pending_posts
+            .iter()
+            .all(|pending| pending.due_at > Utc::now() && pending.scheduled_id.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_publish_the_pending_posts_once_they_are_due() {
+        let feed = "http://example.com/rss.xml";
+        let config = config(vec![feed.to_string()]);
+
+        let items = items_published_at(feed, Utc::now() - Duration::minutes(10));
+        let client = StubRssClient::new(&items);
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+        let storage = SyndicatedPostStorageStub::default();
+
+        for item in items.get(feed).unwrap() {
+            storage
+                .store_pending(PendingPost::new(
+                    Network::Mastodon.into(),
+                    &identified_item(item),
+                    Utc::now() - Duration::minutes(5),
+                ))
+                .unwrap();
+        }
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
+
+        assert_eq!(*target_calls.lock().await, *items.get(feed).unwrap());
+        assert_eq!(*storage.pending_posts.lock().unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_queue_the_statuses_when_approval_is_required() {
+        let feed = "http://example.com/rss.xml";
+        let mut config = config(vec![feed.to_string()]);
+        config.rss.require_approval = true;
+
+        let items = gen_items(&[feed]);
+        let client = StubRssClient::new(&items);
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+        let storage = SyndicatedPostStorageStub::default();
+        let queue = QueueStub::default();
+
+        for _ in 0..2 {
+            syndicate(&config, &client, &targets, &storage, &queue, false)
+                .await
+                .expect("Should be Ok()");
+        }
+
+        assert_eq!(*target_calls.lock().await, []);
+        assert_eq!(*storage.posts.lock().unwrap(), []);
+
+        let queued_statuses = queue.queued_statuses.lock().unwrap();
+        let mut queued_texts = queued_statuses
+            .iter()
+            .map(|queued| queued.status.text.clone())
+            .collect::<Vec<_>>();
+        queued_texts.sort();
+        let mut titles = items
+            .get(feed)
+            .unwrap()
+            .iter()
+            .map(|item| item.title().unwrap().to_string())
+            .collect::<Vec<_>>();
+        titles.sort();
+
+        assert_eq!(queued_texts, titles);
+        assert!(queued_statuses
+            .iter()
+            .all(|queued| queued.account == Network::Mastodon.into()
+                && queued.state == QueueState::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_not_queue_the_feeds_that_opt_out_of_approval() {
+        let feed = "http://example.com/rss.xml";
+        let mut config = config(vec![feed.to_string()]);
+        config.rss.require_approval = true;
+        config.rss.feeds.insert(
+            feed.to_string(),
+            Feed {
+                require_approval: Some(false),
+                ..Default::default()
+            },
+        );
+
+        let client = StubRssClient::new(&gen_items(&[feed]));
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+        let queue = QueueStub::default();
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &SyndicatedPostStorageStub::default(),
+            &queue,
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
+
+        assert_eq!(
+            *target_calls.lock().await,
+            *gen_items(&[feed]).get(feed).unwrap()
+        );
+        assert_eq!(*queue.queued_statuses.lock().unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_publish_oldest_first_up_to_the_max_posts_per_run() {
+        let feed = "http://example.com/rss.xml";
+        let mut config = config(vec![feed.to_string()]);
+        config.mastodon.as_mut().unwrap().max_posts_per_run = Some(2);
+
+        let mut items = gen_items(&[feed]);
+        let now = Utc::now() - Duration::days(1);
+        for (item, hours) in items.get_mut(feed).unwrap().iter_mut().zip([2, 3, 1, 4]) {
+            item.pub_date = Some((now - Duration::hours(hours)).to_rfc2822());
+        }
+        let client = StubRssClient::new(&items);
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+
+        syndicate(
+            &config,
+            &client,
+            &targets,
+            &SyndicatedPostStorageStub::default(),
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
+
+        let items = items.get(feed).unwrap();
+        assert_eq!(
+            *target_calls.lock().await,
+            vec![items[3].clone(), items[1].clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_record_the_copy_of_an_interrupted_attempt() {
+        let feed = "http://example.com/rss.xml";
+        let config = config(vec![feed.to_string()]);
+        let items = gen_items(&[feed]);
+        let posts = items.get(feed).unwrap();
+        let storage = SyndicatedPostStorageStub::default();
+        storage
+            .store_attempt(Attempt::new(
+                Network::Mastodon.into(),
+                &identified_item(&posts[0]),
+                String::from("key"),
+            ))
+            .unwrap();
+        let mut stub_target = StubTarget::new(Network::Mastodon);
+        stub_target.recent_statuses = vec![PublishedStatus {
+            id: String::from("copy"),
+            links: vec![posts[0].link().unwrap().to_string()],
+        }];
+        let target_calls = Arc::clone(&stub_target.calls);
+        let targets = vec![stub_target.into()];
+
+        syndicate(
+            &config,
+            &StubRssClient::new(&items),
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
+
+        assert_eq!(
+            storage
+                .find(posts[0].guid().unwrap().value(), &Network::Mastodon.into())
+                .unwrap(),
+            Some(SyndicatedPost::new(
+                Network::Mastodon.into(),
+                "copy",
+                &identified_item(&posts[0])
+            ))
+        );
+        assert!(!target_calls.lock().await.contains(&posts[0]));
+        assert_eq!(target_calls.lock().await.len(), posts.len() - 1);
+        assert_eq!(*storage.attempts.lock().unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn test_syndycate_should_retry_an_interrupted_attempt_with_its_key() {
+        let feed = "http://example.com/rss.xml";
+        let config = config(vec![feed.to_string()]);
+        let items = gen_items(&[feed]);
+        let posts = items.get(feed).unwrap();
+        let storage = SyndicatedPostStorageStub::default();
+        storage
+            .store_attempt(Attempt::new(
+                Network::Mastodon.into(),
+                &identified_item(&posts[0]),
+                String::from("earlier"),
+            ))
+            .unwrap();
+        let stub_target = StubTarget::new(Network::Mastodon);
+        let idempotency_keys = Arc::clone(&stub_target.idempotency_keys);
+        let targets = vec![stub_target.into()];
+
+        syndicate(
+            &config,
+            &StubRssClient::new(&items),
+            &targets,
+            &storage,
+            &QueueStub::default(),
+            false,
+        )
+        .await
+        .expect("Should be Ok()");
+
+        let idempotency_keys = idempotency_keys.lock().await;
+        assert_eq!(idempotency_keys.len(), posts.len());
+        assert!(idempotency_keys.contains(&String::from("earlier")));
+        assert!(idempotency_keys.contains(&idempotency_key(
+            posts[1].guid().unwrap().value(),
+            &Network::Mastodon.into()
+        )));
+        assert_eq!(*storage.attempts.lock().unwrap(), []);
+    }
+
+    #[test]
+    fn test_chronological_should_order_the_items_oldest_first() {
+        let dated = |day: u32| Item {
+            pub_date: Some(format!("0{day} Jan 2024 10:00:00 +0000")),
+            ..Default::default()
+        };
+        let undated = |title: &str| Item {
+            title: Some(title.to_string()),
+            ..Default::default()
+        };
+        let items = [
+            dated(3),
+            undated("first"),
+            dated(1),
+            undated("second"),
+            dated(2),
+        ]
+        .map(|item| IdentifiedItem {
+            identity: String::new(),
+            link: String::new(),
+            item,
+        });
+
+        assert_eq!(
+            chronological(&items),
+            vec![&items[1], &items[3], &items[2], &items[4], &items[0]]
+        );
+    }
+
+    #[test]
+    fn test_without_old_items_should_drop_the_items_published_before_the_max_age() {
+        let now = DateTime::parse_from_rfc3339("2024-01-10T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let published = |days: i64| Item {
+            pub_date: Some((now - Duration::days(days)).to_rfc2822()),
+            ..Default::default()
+        };
+        let channel = || IdentifiedChannel {
+            channel: Channel::default(),
+            items: [published(1), published(8), Item::default(), published(6)]
+                .map(|item| IdentifiedItem {
+                    identity: String::new(),
+                    link: String::new(),
+                    item,
+                })
+                .to_vec(),
+        };
+        let items = |channel: IdentifiedChannel| {
+            channel
+                .items
+                .into_iter()
+                .map(|item| item.item)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            items(without_old_items(channel(), Some(7 * 24 * 60 * 60), now)),
+            vec![published(1), Item::default(), published(6)]
+        );
+        assert_eq!(
+            items(without_old_items(channel(), None, now)),
+            items(channel())
+        );
+    }
+
+    #[test]
+    fn test_due_at() {
+        let pub_date = DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let item = Item {
+            pub_date: Some(pub_date.to_rfc2822()),
+            extensions: create_iwt_extension_map(&[Network::Mastodon], None, &[]),
+            ..Default::default()
+        };
+        let mut extension = item.get_iwt_extension().unwrap().unwrap();
+
+        assert_eq!(due_at(&Item::default(), &extension, 600), None);
+        assert_eq!(
+            due_at(&item, &extension, 600),
+            Some(pub_date + Duration::minutes(10))
+        );
+
+        extension.publish_at = Some(pub_date + Duration::days(1));
+        assert_eq!(
+            due_at(&item, &extension, 600),
+            Some(pub_date + Duration::days(1))
+        );
+    }
 }
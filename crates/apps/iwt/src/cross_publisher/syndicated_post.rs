@@ -1,29 +1,105 @@
 use std::rc::Rc;
 
-use rss::Item;
+use chrono::{DateTime, Utc};
 use rusqlite::Connection;
 
-use crate::social::Network;
+use super::identity::{sha256_hex, IdentifiedItem};
+use crate::social::Account;
 
 #[derive(Debug, PartialEq, Clone)] // TODO: Clone is only needed for the tests
 pub struct SyndicatedPost {
-    pub social_network: Network,
+    pub account: Account,
     pub id: String,
     pub original_guid: String,
     pub original_uri: String,
 }
 
 impl SyndicatedPost {
-    pub fn new(social_network: Network, id: &str, item: &Item) -> Self {
+    pub fn new(account: Account, id: &str, item: &IdentifiedItem) -> Self {
         Self {
-            social_network,
+            account,
             id: String::from(id),
-            original_guid: String::from(item.guid().unwrap().value()),
-            original_uri: String::from(item.link().unwrap()),
+            original_guid: item.identity.clone(),
+            original_uri: item.link.clone(),
+        }
+    }
+
+    /// Records the item as syndicated without publishing it, the id is a placeholder
+    pub fn seen(account: Account, item: &IdentifiedItem) -> Self {
+        Self {
+            id: format!("{SEEN_ID_PREFIX}{}", item.identity),
+            ..Self::new(account, "", item)
+        }
+    }
+}
+
+/// Prefix of the placeholder ids of the posts that are marked as seen, see `iwt import-seen`
+pub const SEEN_ID_PREFIX: &str = "seen:";
+
+/// A post that is not due yet, it is published by a later run, or by the network when it is
+/// scheduled there
+#[derive(Debug, PartialEq, Clone)]
+pub struct PendingPost {
+    pub account: Account,
+    pub original_guid: String,
+    pub original_uri: String,
+    pub due_at: DateTime<Utc>,
+    /// Id of the status scheduled on the network
+    pub scheduled_id: Option<String>,
+}
+
+impl PendingPost {
+    pub fn new(account: Account, item: &IdentifiedItem, due_at: DateTime<Utc>) -> Self {
+        Self {
+            account,
+            original_guid: item.identity.clone(),
+            original_uri: item.link.clone(),
+            due_at,
+            scheduled_id: None,
         }
     }
 }
 
+/// A post that is being published, it is recorded before publishing, so a run that is interrupted
+/// before the syndicated post is stored can be reconciled instead of publishing it again
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attempt {
+    pub account: Account,
+    pub original_guid: String,
+    pub original_uri: String,
+    /// Sent along the post, so the network can recognise a retry
+    pub idempotency_key: String,
+    pub started_at: DateTime<Utc>,
+}
+
+impl Attempt {
+    pub fn new(account: Account, item: &IdentifiedItem, idempotency_key: String) -> Self {
+        Self {
+            account,
+            original_guid: item.identity.clone(),
+            original_uri: item.link.clone(),
+            idempotency_key,
+            started_at: Utc::now(),
+        }
+    }
+
+    /// The syndicated post of the attempt, once its status is found
+    pub fn syndicated(&self, id: &str) -> SyndicatedPost {
+        SyndicatedPost {
+            account: self.account.clone(),
+            id: String::from(id),
+            original_guid: self.original_guid.clone(),
+            original_uri: self.original_uri.clone(),
+        }
+    }
+}
+
+/// Deterministic key of publishing the post as the account, the same post gets the same key in
+/// every run
+pub fn idempotency_key(original_guid: &str, account: &Account) -> String {
+    sha256_hex(&[&account.to_string(), original_guid])
+}
+
 #[derive(Debug)]
 pub enum StorageError {
     PersistenceError(String),
@@ -32,7 +108,10 @@ pub enum StorageError {
 
 impl std::fmt::Display for StorageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "StorageError")
+        match self {
+            StorageError::PersistenceError(message) => write!(f, "StorageError: {message}"),
+            StorageError::SqlError(err) => write!(f, "StorageError: {err}"),
+        }
     }
 }
 
@@ -49,8 +128,26 @@ pub trait Storage {
     fn find(
         &self,
         original_guid: &str,
-        social_network: &Network,
+        account: &Account,
     ) -> Result<Option<SyndicatedPost>, StorageError>;
+    /// Inserts or updates the pending post
+    fn store_pending(&self, pending_post: PendingPost) -> Result<(), StorageError>;
+    fn find_pending(
+        &self,
+        original_guid: &str,
+        account: &Account,
+    ) -> Result<Option<PendingPost>, StorageError>;
+    fn remove_pending(&self, original_guid: &str, account: &Account) -> Result<(), StorageError>;
+    /// Inserts the attempt, or keeps the earlier one of the post
+    fn store_attempt(&self, attempt: Attempt) -> Result<(), StorageError>;
+    fn find_attempt(
+        &self,
+        original_guid: &str,
+        account: &Account,
+    ) -> Result<Option<Attempt>, StorageError>;
+    /// The attempts that were interrupted, e.g. by a crash
+    fn list_attempts(&self) -> Result<Vec<Attempt>, StorageError>;
+    fn remove_attempt(&self, original_guid: &str, account: &Account) -> Result<(), StorageError>;
 }
 
 pub struct SqliteSyndycatedPostStorage {
@@ -75,9 +172,75 @@ impl SqliteSyndycatedPostStorage {
             )",
                 (),
             )
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))?;
+
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS pending_post (
+              social_network VARCHAR(20) NOT NULL,
+              original_guid TEXT NOT NULL,
+              original_uri TEXT NOT NULL,
+              due_at TEXT NOT NULL,
+              scheduled_id VARCHAR(64),
+
+              PRIMARY KEY (original_guid, social_network)
+            )",
+                (),
+            )
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))?;
+
+        self.conn
+            .execute(
+                "CREATE TABLE IF NOT EXISTS attempt (
+              social_network VARCHAR(20) NOT NULL,
+              original_guid TEXT NOT NULL,
+              original_uri TEXT NOT NULL,
+              idempotency_key VARCHAR(64) NOT NULL,
+              started_at TEXT NOT NULL,
+
+              PRIMARY KEY (original_guid, social_network)
+            )",
+                (),
+            )
             .map(|_| ())
             .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
     }
+
+    fn query_attempts(
+        &self,
+        condition: &str,
+        params: &[(&str, &dyn rusqlite::ToSql)],
+    ) -> Result<Vec<Attempt>, StorageError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT social_network, original_guid, original_uri, idempotency_key, started_at
+            FROM attempt {condition} ORDER BY started_at"
+        ))?;
+
+        let rows = statement.query_map(params, |row| {
+            Ok((
+                row.get::<_, Account>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        rows.map(|row| {
+            let (account, original_guid, original_uri, idempotency_key, started_at) = row?;
+
+            DateTime::parse_from_rfc3339(&started_at)
+                .map(|started_at| Attempt {
+                    account,
+                    original_guid,
+                    original_uri,
+                    idempotency_key,
+                    started_at: started_at.with_timezone(&Utc),
+                })
+                .map_err(|err| StorageError::PersistenceError(format!("Invalid start date: {err}")))
+        })
+        .collect()
+    }
 }
 
 impl Storage for SqliteSyndycatedPostStorage {
@@ -88,10 +251,7 @@ impl Storage for SqliteSyndycatedPostStorage {
                  VALUES (:id, :social_network, :original_guid, :original_url)",
                 &[
                     (":id", &syndicated_post.id),
-                    (
-                        ":social_network",
-                        &syndicated_post.social_network.to_string(),
-                    ),
+                    (":social_network", &syndicated_post.account.to_string()),
                     (":original_guid", &syndicated_post.original_guid),
                     (":original_url", &syndicated_post.original_uri),
                 ],
@@ -103,7 +263,7 @@ impl Storage for SqliteSyndycatedPostStorage {
     fn find(
         &self,
         original_guid: &str,
-        social_network: &Network,
+        account: &Account,
     ) -> Result<Option<SyndicatedPost>, StorageError> {
         let mut statement = self.conn.prepare(
             "SELECT id, social_network, original_guid, original_uri FROM post
@@ -114,12 +274,12 @@ impl Storage for SqliteSyndycatedPostStorage {
             .query_map(
                 &[
                     (":original_guid", original_guid),
-                    (":social_network", social_network.to_string().as_str()),
+                    (":social_network", account.to_string().as_str()),
                 ],
                 |row| {
                     Ok(SyndicatedPost {
                         id: row.get(0).unwrap(),
-                        social_network: row.get(1).unwrap(),
+                        account: row.get(1).unwrap(),
                         original_guid: row.get(2).unwrap(),
                         original_uri: row.get(3).unwrap(),
                     })
@@ -134,19 +294,284 @@ impl Storage for SqliteSyndycatedPostStorage {
             })
             .map_err(|_| StorageError::PersistenceError(String::from("foo"))) // TODO: this needs some clean up
     }
+
+    fn store_pending(&self, pending_post: PendingPost) -> Result<(), StorageError> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO pending_post
+                 (social_network, original_guid, original_uri, due_at, scheduled_id)
+                 VALUES (:social_network, :original_guid, :original_uri, :due_at, :scheduled_id)",
+                rusqlite::named_params! {
+                    ":social_network": pending_post.account.to_string(),
+                    ":original_guid": pending_post.original_guid,
+                    ":original_uri": pending_post.original_uri,
+                    ":due_at": pending_post.due_at.to_rfc3339(),
+                    ":scheduled_id": pending_post.scheduled_id,
+                },
+            )
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+
+    fn find_pending(
+        &self,
+        original_guid: &str,
+        account: &Account,
+    ) -> Result<Option<PendingPost>, StorageError> {
+        let mut statement = self.conn.prepare(
+            "SELECT social_network, original_guid, original_uri, due_at, scheduled_id
+            FROM pending_post
+            WHERE original_guid = :original_guid AND social_network = :social_network",
+        )?;
+
+        let mut rows = statement.query_map(
+            &[
+                (":original_guid", original_guid),
+                (":social_network", account.to_string().as_str()),
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, Account>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                ))
+            },
+        )?;
+
+        rows.next()
+            .transpose()?
+            .map(
+                |(account, original_guid, original_uri, due_at, scheduled_id)| {
+                    DateTime::parse_from_rfc3339(&due_at)
+                        .map(|due_at| PendingPost {
+                            account,
+                            original_guid,
+                            original_uri,
+                            due_at: due_at.with_timezone(&Utc),
+                            scheduled_id,
+                        })
+                        .map_err(|err| {
+                            StorageError::PersistenceError(format!("Invalid due date: {err}"))
+                        })
+                },
+            )
+            .transpose()
+    }
+
+    fn remove_pending(&self, original_guid: &str, account: &Account) -> Result<(), StorageError> {
+        self.conn
+            .execute(
+                "DELETE FROM pending_post
+                 WHERE original_guid = :original_guid AND social_network = :social_network",
+                &[
+                    (":original_guid", original_guid),
+                    (":social_network", account.to_string().as_str()),
+                ],
+            )
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+
+    fn store_attempt(&self, attempt: Attempt) -> Result<(), StorageError> {
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO attempt
+                 (social_network, original_guid, original_uri, idempotency_key, started_at)
+                 VALUES (:social_network, :original_guid, :original_uri, :idempotency_key,
+                         :started_at)",
+                rusqlite::named_params! {
+                    ":social_network": attempt.account.to_string(),
+                    ":original_guid": attempt.original_guid,
+                    ":original_uri": attempt.original_uri,
+                    ":idempotency_key": attempt.idempotency_key,
+                    ":started_at": attempt.started_at.to_rfc3339(),
+                },
+            )
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+
+    fn find_attempt(
+        &self,
+        original_guid: &str,
+        account: &Account,
+    ) -> Result<Option<Attempt>, StorageError> {
+        Ok(self
+            .query_attempts(
+                "WHERE original_guid = :original_guid AND social_network = :social_network",
+                rusqlite::named_params! {
+                    ":original_guid": original_guid,
+                    ":social_network": account.to_string(),
+                },
+            )?
+            .into_iter()
+            .next())
+    }
+
+    fn list_attempts(&self) -> Result<Vec<Attempt>, StorageError> {
+        self.query_attempts("", &[])
+    }
+
+    fn remove_attempt(&self, original_guid: &str, account: &Account) -> Result<(), StorageError> {
+        self.conn
+            .execute(
+                "DELETE FROM attempt
+                 WHERE original_guid = :original_guid AND social_network = :social_network",
+                &[
+                    (":original_guid", original_guid),
+                    (":social_network", account.to_string().as_str()),
+                ],
+            )
+            .map(|_| ())
+            .map_err(|err| StorageError::PersistenceError(format!("{err:?}")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use chrono::{DateTime, Utc};
+    use rss::{GuidBuilder, ItemBuilder};
+    use rusqlite::Connection;
+
+    use crate::cross_publisher::stubs::identity::identified_item;
+    use crate::social::{Account, Network};
+
+    use super::{idempotency_key, Attempt, PendingPost, SqliteSyndycatedPostStorage, Storage};
+
+    #[test]
+    fn test_pending_posts_should_be_stored_updated_and_removed() {
+        let storage =
+            SqliteSyndycatedPostStorage::new(Rc::new(Connection::open_in_memory().unwrap()));
+        storage.init_table().unwrap();
+        let item = identified_item(
+            &ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value("guid").build()))
+                .link(Some(String::from("https://example.com/posts/1")))
+                .build(),
+        );
+        let due_at = DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let mastodon = Account::from(Network::Mastodon);
+        let mut pending_post = PendingPost::new(mastodon.clone(), &item, due_at);
+        storage.store_pending(pending_post.clone()).unwrap();
+        assert_eq!(
+            storage.find_pending("guid", &mastodon).unwrap(),
+            Some(pending_post.clone())
+        );
+        assert_eq!(
+            storage
+                .find_pending("guid", &Network::Twitter.into())
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            storage
+                .find_pending("guid", &Account::named(Network::Mastodon, "german"))
+                .unwrap(),
+            None
+        );
+
+        pending_post.scheduled_id = Some(String::from("3"));
+        storage.store_pending(pending_post.clone()).unwrap();
+        assert_eq!(
+            storage.find_pending("guid", &mastodon).unwrap(),
+            Some(pending_post)
+        );
+
+        storage.remove_pending("guid", &mastodon).unwrap();
+        assert_eq!(storage.find_pending("guid", &mastodon).unwrap(), None);
+    }
+
+    #[test]
+    fn test_attempts_should_be_stored_listed_and_removed() {
+        let storage =
+            SqliteSyndycatedPostStorage::new(Rc::new(Connection::open_in_memory().unwrap()));
+        storage.init_table().unwrap();
+        let item = identified_item(
+            &ItemBuilder::default()
+                .guid(Some(GuidBuilder::default().value("guid").build()))
+                .link(Some(String::from("https://example.com/posts/1")))
+                .build(),
+        );
+
+        let mastodon = Account::from(Network::Mastodon);
+        let attempt = Attempt::new(mastodon.clone(), &item, String::from("key"));
+        storage.store_attempt(attempt.clone()).unwrap();
+        // A retry keeps the first attempt
+        storage
+            .store_attempt(Attempt::new(mastodon.clone(), &item, String::from("other")))
+            .unwrap();
+
+        let mut stored = attempt.clone();
+        stored.started_at = DateTime::parse_from_rfc3339(&attempt.started_at.to_rfc3339())
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            storage.find_attempt("guid", &mastodon).unwrap(),
+            Some(stored.clone())
+        );
+        assert_eq!(storage.list_attempts().unwrap(), vec![stored]);
+        assert_eq!(
+            storage
+                .find_attempt("guid", &Network::Twitter.into())
+                .unwrap(),
+            None
+        );
+        // A named account of the network has its own attempts
+        let german = Attempt::new(
+            Account::named(Network::Mastodon, "german"),
+            &item,
+            String::from("german"),
+        );
+        storage.store_attempt(german.clone()).unwrap();
+        assert_eq!(
+            storage
+                .find_attempt("guid", &german.account)
+                .unwrap()
+                .map(|attempt| attempt.idempotency_key),
+            Some(String::from("german"))
+        );
+        storage.remove_attempt("guid", &german.account).unwrap();
+
+        storage.remove_attempt("guid", &mastodon).unwrap();
+        assert_eq!(storage.list_attempts().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_idempotency_key_should_be_deterministic() {
+        let mastodon = Account::from(Network::Mastodon);
+        let key = idempotency_key("guid", &mastodon);
+
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, idempotency_key("guid", &mastodon));
+        assert_ne!(
+            key,
+            idempotency_key("guid", &Account::named(Network::Mastodon, "german"))
+        );
+        assert_ne!(key, idempotency_key("guid", &Network::Twitter.into()));
+        assert_ne!(key, idempotency_key("guid2", &mastodon));
+    }
 }
 
 #[cfg(test)]
 pub mod stubs {
     use std::sync::Mutex;
 
-    use crate::social::Network;
+    use crate::social::Account;
 
-    use super::{Storage, SyndicatedPost};
+    use super::{Attempt, PendingPost, Storage, SyndicatedPost};
 
     #[derive(Default)]
     pub struct SyndicatedPostStorageStub {
         pub posts: Mutex<Vec<SyndicatedPost>>,
+        pub pending_posts: Mutex<Vec<PendingPost>>,
+        pub attempts: Mutex<Vec<Attempt>>,
     }
 
     impl Storage for SyndicatedPostStorageStub {
@@ -160,14 +585,88 @@ pub mod stubs {
         fn find(
             &self,
             original_guid: &str,
-            social_network: &Network,
+            account: &Account,
         ) -> Result<Option<SyndicatedPost>, super::StorageError> {
             let posts = self.posts.lock().unwrap();
 
             Ok(posts
                 .iter()
-                .find(|p| p.original_guid == *original_guid && p.social_network == *social_network)
+                .find(|p| p.original_guid == *original_guid && p.account == *account)
                 .map(|p| (*p).clone()))
         }
+
+        fn store_pending(&self, pending_post: PendingPost) -> Result<(), super::StorageError> {
+            let mut pending_posts = self.pending_posts.lock().unwrap();
+            pending_posts.retain(|p| {
+                p.original_guid != pending_post.original_guid || p.account != pending_post.account
+            });
+            pending_posts.push(pending_post);
+
+            Ok(())
+        }
+
+        fn find_pending(
+            &self,
+            original_guid: &str,
+            account: &Account,
+        ) -> Result<Option<PendingPost>, super::StorageError> {
+            let pending_posts = self.pending_posts.lock().unwrap();
+
+            Ok(pending_posts
+                .iter()
+                .find(|p| p.original_guid == *original_guid && p.account == *account)
+                .cloned())
+        }
+
+        fn remove_pending(
+            &self,
+            original_guid: &str,
+            account: &Account,
+        ) -> Result<(), super::StorageError> {
+            let mut pending_posts = self.pending_posts.lock().unwrap();
+            pending_posts.retain(|p| p.original_guid != *original_guid || p.account != *account);
+
+            Ok(())
+        }
+
+        fn store_attempt(&self, attempt: Attempt) -> Result<(), super::StorageError> {
+            let mut attempts = self.attempts.lock().unwrap();
+            if !attempts
+                .iter()
+                .any(|a| a.original_guid == attempt.original_guid && a.account == attempt.account)
+            {
+                attempts.push(attempt);
+            }
+
+            Ok(())
+        }
+
+        fn find_attempt(
+            &self,
+            original_guid: &str,
+            account: &Account,
+        ) -> Result<Option<Attempt>, super::StorageError> {
+            let attempts = self.attempts.lock().unwrap();
+
+            Ok(attempts
+                .iter()
+                .find(|a| a.original_guid == *original_guid && a.account == *account)
+                .cloned())
+        }
+
+        fn list_attempts(&self) -> Result<Vec<Attempt>, super::StorageError> {
+            Ok(self.attempts.lock().unwrap().clone())
+        }
+
+        fn remove_attempt(
+            &self,
+            original_guid: &str,
+            account: &Account,
+        ) -> Result<(), super::StorageError> {
+            let mut attempts = self.attempts.lock().unwrap();
+            attempts.retain(|a| a.original_guid != *original_guid || a.account != *account);
+
+            Ok(())
+        }
     }
 }
@@ -1,18 +1,141 @@
-use crate::social::Network;
+use crate::commons::text::LengthModel;
+use crate::social::{Account, Network, Visibility};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use rss::Item;
+use serde_derive::{Deserialize, Serialize};
 
-use super::{rss_item_ext::IwtRssExtension, syndicated_post::SyndicatedPost};
+use super::rss_item_ext::{Interaction, IwtRssExtension};
+
+/// A status rendered for a network, it is published as it is, e.g. after it is approved
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Status {
+    pub text: String,
+    pub content_warning: Option<String>,
+    /// The media attached to the status, the linked media is part of the text
+    pub media: Vec<Media>,
+    /// Replies, reposts and likes are published natively when the network knows the post
+    pub interaction: Option<Interaction>,
+    pub visibility: Option<Visibility>,
+    pub language: Option<String>,
+    pub sensitive: bool,
+}
+
+impl Status {
+    /// The status with the metadata of the item
+    #[must_use]
+    pub fn new(text: String, extension: &IwtRssExtension) -> Self {
+        Self {
+            text,
+            content_warning: extension.content_warning.clone(),
+            media: Vec::new(),
+            interaction: extension.interaction.clone(),
+            visibility: extension.visibility,
+            language: extension.language.clone(),
+            sensitive: extension.sensitive.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Media {
+    pub url: String,
+    pub mime_type: String,
+}
+
+/// A status published on the network, e.g. one shared by hand
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedStatus {
+    pub id: String,
+    /// The links cited in the status
+    pub links: Vec<String>,
+}
 
 #[async_trait(?Send)]
 pub trait Target {
+    /// Fetches what the target needs from the network before it can render posts, called when the
+    /// target is built
+    async fn prepare<'a>(&self) -> Result<(), Box<dyn std::error::Error + 'a>> {
+        Ok(())
+    }
+
+    /// Renders the status of the post without publishing it
+    async fn render<'a>(
+        &self,
+        post: &Item,
+        extension: &IwtRssExtension,
+    ) -> Result<Status, Box<dyn std::error::Error + 'a>>;
+
+    /// Publishes a rendered status, returns its id. Networks that support it recognise a retry
+    /// with the same idempotency key, and don't publish the status again
+    async fn publish_status<'a>(
+        &self,
+        status: &Status,
+        idempotency_key: &str,
+    ) -> Result<String, Box<dyn std::error::Error + 'a>>;
+
+    /// Renders and publishes the post, returns the id of its status
     async fn publish<'a>(
         &self,
         post: &Item,
         extension: &IwtRssExtension,
-    ) -> Result<SyndicatedPost, Box<dyn std::error::Error + 'a>>;
+        idempotency_key: &str,
+    ) -> Result<String, Box<dyn std::error::Error + 'a>> {
+        let status = self.render(post, extension).await?;
+
+        self.publish_status(&status, idempotency_key).await
+    }
+
+    /// Hands the scheduling of the post to the network, returns the id of the scheduled status.
+    /// `None` means that the post should be published by a later run
+    async fn schedule<'a>(
+        &self,
+        _post: &Item,
+        _extension: &IwtRssExtension,
+        _at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        Ok(None)
+    }
+
+    /// Returns the id of the status once the scheduled status is published
+    async fn find_scheduled<'a>(
+        &self,
+        _post: &Item,
+        _scheduled_id: &str,
+        _at: DateTime<Utc>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        Ok(None)
+    }
+
+    /// The recent statuses of the account, to find the posts that were shared by hand
+    async fn recent_statuses<'a>(
+        &self,
+    ) -> Result<Vec<PublishedStatus>, Box<dyn std::error::Error + 'a>> {
+        Ok(Vec::new())
+    }
+
+    /// Id of the recent status of the account that cites the link (or the permashort link) of
+    /// the post, e.g. one published by an interrupted run
+    async fn find_copy<'a>(
+        &self,
+        _link: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        Ok(None)
+    }
+
+    /// Id of the status of the network the url points to, e.g. of a `u-syndication` link
+    fn status_id(&self, _url: &str) -> Option<String> {
+        None
+    }
+
+    /// How the network counts the length of the status text, and the maximum length
+    async fn length_limit(&self) -> (LengthModel, usize);
 
     fn network(&self) -> Network;
+
+    /// The account the statuses are published as, the syndicated posts and the idempotency keys
+    /// are keyed by it
+    fn account(&self) -> Account;
 }
 
 #[cfg(test)]
@@ -23,46 +146,130 @@ pub mod stubs {
     use async_trait::async_trait;
     use rss::Item;
 
+    use crate::commons::text::LengthModel;
     use crate::cross_publisher::rss_item_ext::IwtRssExtension;
-    use crate::cross_publisher::syndicated_post::SyndicatedPost;
-    use crate::social::Network;
+    use crate::social::{Account, Network};
+
+    use super::{PublishedStatus, Status, Target};
 
-    use super::Target;
+    pub const STUB_LENGTH_LIMIT: (LengthModel, usize) = (
+        LengthModel::Mastodon {
+            characters_reserved_per_url: 23,
+        },
+        500,
+    );
 
     pub struct StubTarget {
         pub social_network: Network,
+        pub account: Account,
         pub calls: Arc<Mutex<Vec<Item>>>,
+        /// The statuses published directly, e.g. the approved ones
+        pub published: Arc<Mutex<Vec<Status>>>,
+        /// The statuses of the account, e.g. the ones shared by hand
+        pub recent_statuses: Vec<PublishedStatus>,
+        /// The idempotency keys of the published posts
+        pub idempotency_keys: Arc<Mutex<Vec<String>>>,
     }
 
     impl StubTarget {
         pub fn new(social_network: Network) -> Self {
             Self {
+                account: Account::from(social_network.clone()),
                 social_network,
                 calls: Arc::default(),
+                published: Arc::default(),
+                recent_statuses: Vec::new(),
+                idempotency_keys: Arc::default(),
+            }
+        }
+
+        /// The target of a named account of the network
+        pub fn named(social_network: Network, name: &str) -> Self {
+            Self {
+                account: Account::named(social_network.clone(), name),
+                ..Self::new(social_network)
             }
         }
+
+        /// Url of a status of the stub network
+        pub fn status_url(social_network: &Network, id: &str) -> String {
+            format!("https://{social_network}.example/statuses/{id}")
+        }
     }
 
     #[async_trait(?Send)]
     impl Target for StubTarget {
+        async fn render<'a>(
+            &self,
+            post: &Item,
+            extension: &IwtRssExtension,
+        ) -> Result<Status, Box<dyn std::error::Error + 'a>> {
+            Ok(Status::new(
+                post.title().unwrap_or_default().to_string(),
+                extension,
+            ))
+        }
+
+        async fn publish_status<'a>(
+            &self,
+            status: &Status,
+            _idempotency_key: &str,
+        ) -> Result<String, Box<dyn std::error::Error + 'a>> {
+            let mut published = self.published.lock().await;
+            let id = published.len();
+            published.push(status.clone());
+            Ok(id.to_string())
+        }
+
         async fn publish<'a>(
             &self,
             post: &Item,
             _extension: &IwtRssExtension,
-        ) -> Result<SyndicatedPost, Box<dyn std::error::Error + 'a>> {
+            idempotency_key: &str,
+        ) -> Result<String, Box<dyn std::error::Error + 'a>> {
+            self.idempotency_keys
+                .lock()
+                .await
+                .push(idempotency_key.to_string());
             let mut calls = self.calls.lock().await;
             let id = calls.len();
             calls.push(post.clone());
-            Ok(SyndicatedPost::new(
-                self.social_network.clone(),
-                &id.to_string(),
-                post,
-            ))
+            Ok(id.to_string())
+        }
+
+        async fn recent_statuses<'a>(
+            &self,
+        ) -> Result<Vec<PublishedStatus>, Box<dyn std::error::Error + 'a>> {
+            Ok(self.recent_statuses.clone())
+        }
+
+        async fn find_copy<'a>(
+            &self,
+            link: &str,
+        ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+            Ok(self
+                .recent_statuses
+                .iter()
+                .find(|status| status.links.iter().any(|l| l == link))
+                .map(|status| status.id.clone()))
+        }
+
+        fn status_id(&self, url: &str) -> Option<String> {
+            url.strip_prefix(&Self::status_url(&self.social_network, ""))
+                .map(String::from)
+        }
+
+        async fn length_limit(&self) -> (LengthModel, usize) {
+            STUB_LENGTH_LIMIT
         }
 
         fn network(&self) -> Network {
             self.social_network.clone()
         }
+
+        fn account(&self) -> Account {
+            self.account.clone()
+        }
     }
 
     impl From<StubTarget> for Box<dyn Target> {
@@ -86,17 +293,42 @@ pub mod stubs {
 
     #[async_trait(?Send)]
     impl Target for FailingStubTarget {
+        async fn render<'a>(
+            &self,
+            _post: &Item,
+            _extension: &IwtRssExtension,
+        ) -> Result<Status, Box<dyn std::error::Error + 'a>> {
+            Err(Box::new(TargetError))
+        }
+
+        async fn publish_status<'a>(
+            &self,
+            _status: &Status,
+            _idempotency_key: &str,
+        ) -> Result<String, Box<dyn std::error::Error + 'a>> {
+            Err(Box::new(TargetError))
+        }
+
         async fn publish<'a>(
             &self,
             _post: &Item,
             _extension: &IwtRssExtension,
-        ) -> Result<SyndicatedPost, Box<dyn std::error::Error + 'a>> {
+            _idempotency_key: &str,
+        ) -> Result<String, Box<dyn std::error::Error + 'a>> {
             Err(Box::new(TargetError))
         }
 
+        async fn length_limit(&self) -> (LengthModel, usize) {
+            STUB_LENGTH_LIMIT
+        }
+
         fn network(&self) -> Network {
             Network::Twitter
         }
+
+        fn account(&self) -> Account {
+            Account::from(Network::Twitter)
+        }
     }
 
     impl From<FailingStubTarget> for Box<dyn Target> {
@@ -1,55 +1,80 @@
 use std::rc::Rc;
 
-use crate::commons::permashort_link::PermashortCitation;
-use crate::commons::text;
+use crate::commons::rate_limit::RateLimiter;
+use crate::commons::text::LengthModel;
 use crate::IwtError;
+use async_mutex::Mutex;
 use async_trait::async_trait;
 
 use futures::TryFutureExt;
-use oauth2::{basic::BasicClient, AuthUrl, ClientId, TokenUrl};
-use reqwest::Client;
+use oauth2::basic::BasicClient;
+use reqwest::{Client, Request, Response};
 use rss::Item;
 
-use super::rss_item_ext::IwtRssExtension;
-use super::syndicated_post::SyndicatedPost;
-use super::target::Target;
+use super::adopt;
+use super::render::{self, StatusRenderer};
+use super::rss_item_ext::{Interaction, IwtRssExtension};
+use super::target::{PublishedStatus, Status, Target};
 use crate::commons::auth::oauth::AuthedClient;
 use crate::commons::auth::token_db::TokenDB;
 use crate::commons::url_shortener;
-use crate::social::Network;
+use crate::social::{Account, Network};
 
-pub struct Twitter<DB: TokenDB, USClient: url_shortener::Client> {
+const MAX_TWEET_LENGTH: usize = 280;
+
+pub struct Twitter<DB: TokenDB + ?Sized, USClient: url_shortener::Client + ?Sized> {
     authed_client: AuthedClient<DB>,
     http_client: Client,
     url_shortener_client: Rc<USClient>,
+    renderer: StatusRenderer,
+    // Needed for retweets and likes, looked up when it is first needed
+    user_id: Mutex<Option<String>>,
+    rate_limiter: RateLimiter,
 }
 
-impl<DB: TokenDB, USClient: url_shortener::Client> Twitter<DB, USClient> {
-    pub fn new(client_id: ClientId, db: Rc<DB>, url_shortener_client: Rc<USClient>) -> Self {
-        Self {
-            authed_client: AuthedClient::new(
-                Network::Twitter,
-                BasicClient::new(
-                    client_id,
-                    None,
-                    AuthUrl::new("https://twitter.com/i/oauth2/authorize".to_string())
-                        .expect("Twitter auth url is invalid."),
-                    Some(
-                        TokenUrl::new("https://api.twitter.com/2/oauth2/token".to_string())
-                            .expect("Twitter token url is invalid"),
-                    ),
-                ),
-                db,
-            ),
+impl<DB: TokenDB + ?Sized, USClient: url_shortener::Client + ?Sized> Twitter<DB, USClient> {
+    pub fn new(
+        oauth_client: BasicClient,
+        db: Rc<DB>,
+        url_shortener_client: Rc<USClient>,
+        renderer: StatusRenderer,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            authed_client: AuthedClient::new(Network::Twitter, oauth_client, db)?,
             http_client: Client::new(),
             url_shortener_client,
-        }
+            renderer,
+            user_id: Mutex::new(None),
+            rate_limiter: RateLimiter::default(),
+        })
     }
 }
 
 #[derive(serde::Serialize)]
 struct TweetsRequest {
     text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply: Option<TweetReply>,
+}
+
+#[derive(serde::Serialize)]
+struct TweetReply {
+    in_reply_to_tweet_id: String,
+}
+
+#[derive(serde::Serialize)]
+struct TweetActionRequest {
+    tweet_id: String,
+}
+
+#[derive(serde::Deserialize)]
+struct UserResponse {
+    data: UserResponseData,
+}
+
+#[derive(serde::Deserialize)]
+struct UserResponseData {
+    id: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -62,6 +87,49 @@ struct TweetResponseData {
     id: String,
 }
 
+#[derive(serde::Deserialize)]
+struct UserTweetsResponse {
+    /// Missing when the user has no tweets
+    #[serde(default)]
+    data: Vec<UserTweet>,
+}
+
+#[derive(serde::Deserialize)]
+struct UserTweet {
+    id: String,
+    entities: Option<TweetEntities>,
+}
+
+#[derive(serde::Deserialize)]
+struct TweetEntities {
+    #[serde(default)]
+    urls: Vec<TweetUrl>,
+}
+
+/// The links of tweets are shortened by t.co, the original link is the expanded one
+#[derive(serde::Deserialize)]
+struct TweetUrl {
+    expanded_url: String,
+}
+
+impl From<UserTweet> for PublishedStatus {
+    fn from(tweet: UserTweet) -> Self {
+        Self {
+            id: tweet.id,
+            links: tweet
+                .entities
+                .map(|entities| {
+                    entities
+                        .urls
+                        .into_iter()
+                        .map(|url| url.expanded_url)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
 #[derive(serde::Deserialize)]
 struct TwitterErrorResponse {
     errors: Vec<TwitterError>,
@@ -72,111 +140,317 @@ struct TwitterError {
     message: String,
 }
 
-impl<DB: TokenDB, USClient: url_shortener::Client> Twitter<DB, USClient> {
-    async fn try_publish<'a>(
-        &self,
-        post: &Item,
-        permashort_citation: &PermashortCitation,
-        tags: &[String],
-    ) -> Result<SyndicatedPost, Box<dyn std::error::Error + 'a>> {
-        let mut length = 280;
-        let mut success_or_gave_up = false;
-        let mut result = Err(Box::new(IwtError::new(
-            "You shouldn't see this, we're trying to publish now...",
-        )) as Box<dyn std::error::Error>);
-
-        while !success_or_gave_up {
-            let text = text::shorten_with_permashort_citation(
-                post.description().unwrap(),
-                length,
-                permashort_citation,
-                tags,
-            );
-
-            let request = self
-                .http_client
-                .post("https://api.twitter.com/2/tweets")
-                .json(&TweetsRequest { text });
-
-            result =
-                self.authed_client
-                    .authed_request(request.build().unwrap())
-                    .and_then(|response| async {
-                        log::info!("Twitter response: {:?}", &response);
-
-                        let status = response.status();
-
-                        let body = response.text().await.expect("Body should be available");
-
-                        if status.is_success() {
-                            success_or_gave_up = true;
-                            serde_json::from_str::<TweetResponse>(&body)
-                                .map(|response| {
-                                    SyndicatedPost::new(Network::Twitter, &response.data.id, post)
-                                })
-                                .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
-                        } else {
-                            match serde_json::from_str::<TwitterErrorResponse>(&body) {
-                                Ok(error) => {
-                                    let too_long_error = error.errors.iter().any(|e| {
-                                        e.message.starts_with("Your Tweet text is too long.")
-                                    });
-
-                                    if too_long_error && length > 210 {
-                                        log::info!(
-                                            "Length {} was too long, trying to reduce it...",
-                                            length
-                                        );
-
-                                        Err(Box::new(IwtError::new(
-                                            "You shouldn't see this, we're trying it again",
-                                        ))
-                                            as Box<dyn std::error::Error>)
-                                    } else {
-                                        success_or_gave_up = true;
-                                        Err(Box::new(IwtError::new(
-                                        "Couldn't reliably reduce the length of the post, gave up",
-                                    ))
-                                        as Box<dyn std::error::Error>)
-                                    }
-                                }
-                                Err(err) => {
-                                    success_or_gave_up = true;
-                                    Err(Box::new(err) as Box<dyn std::error::Error>)
-                                }
-                            }
-                        }
-
-                        // log::info!("Twitter response body: {}", &body);
-                    })
-                    .await;
-
-            length -= 5;
+/// Id of the tweet the url points to, e.g. `https://twitter.com/someone/status/1234`
+fn tweet_id(url: &str) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.");
+
+    if !["twitter.com", "mobile.twitter.com", "x.com"].contains(&host) {
+        return None;
+    }
+
+    match url.path_segments()?.collect::<Vec<_>>()[..] {
+        [_, "status", id, ..] if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) => {
+            Some(id.to_string())
         }
+        _ => None,
+    }
+}
 
-        result
+/// Turns an error response into an error, with the messages of Twitter when there are any
+fn rejection(action: &str, body: &str) -> Box<dyn std::error::Error> {
+    match serde_json::from_str::<TwitterErrorResponse>(body) {
+        Ok(error) => Box::new(IwtError::new(&format!(
+            "Twitter rejected the {action}: {}",
+            error
+                .errors
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+        Err(err) => Box::new(err),
+    }
+}
+
+impl<DB: TokenDB + ?Sized, USClient: url_shortener::Client + ?Sized> Twitter<DB, USClient> {
+    /// Sends the authorized request, unless the rate limit is exhausted
+    async fn send(&self, request: Request) -> Result<Response, Box<dyn std::error::Error>> {
+        self.rate_limiter.check()?;
+        let response = self.authed_client.authed_request(request).await?;
+        self.rate_limiter.record(&response)?;
+
+        Ok(response)
+    }
+
+    async fn user_id(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let mut user_id = self.user_id.lock().await;
+
+        if let Some(id) = user_id.as_ref() {
+            return Ok(id.clone());
+        }
+
+        let request = self
+            .http_client
+            .get("https://api.twitter.com/2/users/me")
+            .build()?;
+        let response = self.send(request).await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(rejection("user lookup", &body));
+        }
+
+        let id = serde_json::from_str::<UserResponse>(&body)?.data.id;
+        *user_id = Some(id.clone());
+
+        Ok(id)
+    }
+
+    /// Retweets or likes the tweet, the id of the tweet is returned, because retweets and likes
+    /// have no id of their own
+    async fn act_on_tweet(
+        &self,
+        tweet_id: &str,
+        action: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let request = self
+            .http_client
+            .post(format!(
+                "https://api.twitter.com/2/users/{}/{action}",
+                self.user_id().await?
+            ))
+            .json(&TweetActionRequest {
+                tweet_id: tweet_id.to_string(),
+            })
+            .build()?;
+        let response = self.send(request).await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if status.is_success() {
+            Ok(tweet_id.to_string())
+        } else {
+            Err(rejection(action, &body))
+        }
     }
 }
 
 #[async_trait(?Send)]
-impl<DB: TokenDB, WHClient: url_shortener::Client> Target for Twitter<DB, WHClient> {
-    async fn publish<'a>(
+impl<DB: TokenDB + ?Sized, WHClient: url_shortener::Client + ?Sized> Target
+    for Twitter<DB, WHClient>
+{
+    async fn render<'a>(
         &self,
         post: &Item,
         extension: &IwtRssExtension,
-    ) -> Result<SyndicatedPost, Box<dyn std::error::Error + 'a>> {
+    ) -> Result<Status, Box<dyn std::error::Error + 'a>> {
         log::debug!("processing post: {:?}", post);
 
-        let permashort_citation = self
-            .url_shortener_client
-            .put_uri(post.link.as_ref().unwrap())
-            .await?;
+        let link = post
+            .link()
+            .ok_or_else(|| IwtError::new("The post has no link to cite"))?;
+        let permashort_citation = self.url_shortener_client.put_uri(link).await?;
+
+        // Photos are linked, the tweet leads with them
+        let linked_media = render::photo(post, extension)
+            .map(|photo| photo.url().to_string())
+            .into_iter()
+            .collect::<Vec<_>>();
 
-        self.try_publish(post, &permashort_citation, &extension.tags)
+        let text = self.renderer.render(
+            post,
+            extension,
+            &permashort_citation,
+            &linked_media,
+            MAX_TWEET_LENGTH,
+            &LengthModel::Twitter,
+        )?;
+
+        // Content warnings, visibility, language and sensitive media are not supported
+        Ok(Status {
+            text,
+            content_warning: None,
+            media: Vec::new(),
+            interaction: extension.interaction.clone(),
+            visibility: None,
+            language: None,
+            sensitive: false,
+        })
+    }
+
+    async fn publish_status<'a>(
+        &self,
+        status: &Status,
+        _idempotency_key: &str,
+    ) -> Result<String, Box<dyn std::error::Error + 'a>> {
+        // Twitter doesn't accept idempotency keys, interrupted runs are reconciled by `find_copy`
+        // Responses to tweets are native replies, retweets and likes
+        let mut reply = None;
+        if let Some(interaction) = &status.interaction {
+            match (interaction, tweet_id(interaction.url())) {
+                (Interaction::Reply(_), Some(tweet_id)) => {
+                    reply = Some(TweetReply {
+                        in_reply_to_tweet_id: tweet_id,
+                    });
+                }
+                (Interaction::Repost(_), Some(tweet_id)) => {
+                    return self.act_on_tweet(&tweet_id, "retweets").await
+                }
+                (Interaction::Like(_), Some(tweet_id)) => {
+                    return self.act_on_tweet(&tweet_id, "likes").await
+                }
+                (_, None) => log::info!(
+                    "{} is not a tweet, posting a tweet instead",
+                    interaction.url()
+                ),
+            }
+        }
+
+        let request = self
+            .http_client
+            .post("https://api.twitter.com/2/tweets")
+            .json(&TweetsRequest {
+                text: status.text.clone(),
+                reply,
+            });
+
+        self.send(request.build().unwrap())
+            .and_then(|response| async {
+                log::info!("Twitter response: {:?}", &response);
+
+                let status = response.status();
+
+                let body = response.text().await.expect("Body should be available");
+
+                if status.is_success() {
+                    serde_json::from_str::<TweetResponse>(&body)
+                        .map(|response| response.data.id)
+                        .map_err(|err| Box::new(err) as Box<dyn std::error::Error>)
+                } else {
+                    Err(rejection("tweet", &body))
+                }
+            })
             .await
     }
 
+    async fn recent_statuses<'a>(
+        &self,
+    ) -> Result<Vec<PublishedStatus>, Box<dyn std::error::Error + 'a>> {
+        let request = self
+            .http_client
+            .get(format!(
+                "https://api.twitter.com/2/users/{}/tweets",
+                self.user_id().await?
+            ))
+            .query(&[
+                ("max_results", "100"),
+                ("exclude", "retweets"),
+                ("tweet.fields", "entities"),
+            ])
+            .build()?;
+        let response = self.send(request).await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(rejection("timeline lookup", &body));
+        }
+
+        Ok(serde_json::from_str::<UserTweetsResponse>(&body)?
+            .data
+            .into_iter()
+            .map(PublishedStatus::from)
+            .collect())
+    }
+
+    async fn find_copy<'a>(
+        &self,
+        link: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + 'a>> {
+        let citations = adopt::citations(link, self.url_shortener_client.as_ref()).await;
+
+        Ok(adopt::citing_status(
+            &self.recent_statuses().await?,
+            &citations,
+        ))
+    }
+
+    fn status_id(&self, url: &str) -> Option<String> {
+        tweet_id(url)
+    }
+
+    async fn length_limit(&self) -> (LengthModel, usize) {
+        (LengthModel::Twitter, MAX_TWEET_LENGTH)
+    }
+
     fn network(&self) -> Network {
         Network::Twitter
     }
+
+    fn account(&self) -> Account {
+        Account::from(Network::Twitter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cross_publisher::target::PublishedStatus;
+
+    use super::{tweet_id, UserTweetsResponse};
+
+    #[test]
+    fn test_tweet_id() {
+        assert_eq!(
+            tweet_id("https://twitter.com/someone/status/1234"),
+            Some(String::from("1234"))
+        );
+        assert_eq!(
+            tweet_id("https://x.com/someone/status/1234/photo/1"),
+            Some(String::from("1234"))
+        );
+        assert_eq!(
+            tweet_id("https://mobile.twitter.com/someone/status/1234?s=20"),
+            Some(String::from("1234"))
+        );
+        assert_eq!(tweet_id("https://twitter.com/someone"), None);
+        assert_eq!(tweet_id("https://twitter.com/someone/status/abc"), None);
+        assert_eq!(tweet_id("https://mastodon.social/@someone/1234"), None);
+    }
+
+    #[test]
+    fn test_user_tweets_should_cite_the_expanded_urls() {
+        let response: UserTweetsResponse = serde_json::from_str(
+            r#"{"data": [
+                {"id": "2", "text": "New post https://t.co/abc", "entities": {"urls": [
+                    {"url": "https://t.co/abc", "expanded_url": "https://vdx.hu/s/Df3l"}
+                ]}},
+                {"id": "1", "text": "Hello"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            response
+                .data
+                .into_iter()
+                .map(PublishedStatus::from)
+                .collect::<Vec<_>>(),
+            vec![
+                PublishedStatus {
+                    id: String::from("2"),
+                    links: vec![String::from("https://vdx.hu/s/Df3l")],
+                },
+                PublishedStatus {
+                    id: String::from("1"),
+                    links: Vec::new(),
+                }
+            ]
+        );
+
+        let empty: UserTweetsResponse =
+            serde_json::from_str(r#"{"meta": {"result_count": 0}}"#).unwrap();
+        assert!(empty.data.is_empty());
+    }
 }
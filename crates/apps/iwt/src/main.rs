@@ -7,6 +7,7 @@ use log::LevelFilter::{Debug, Info};
 use simple_logger::SimpleLogger;
 
 mod app_auth;
+mod auth;
 pub mod commons;
 pub mod config;
 mod cross_publisher;
@@ -42,12 +43,16 @@ impl std::error::Error for IwtError {}
 struct Cli {
     #[clap(subcommand)]
     command: Command,
-    /// Print debug information, including secrets!
+    /// Print debug information
     #[clap(short, long, action)]
     debug: bool,
-    /// Path to the config file
-    #[clap(long, value_parser, default_value_t = String::from("config.toml"))]
-    config: String,
+    /// Don't redact secrets (tokens, auth codes) in the logs
+    #[clap(long, action)]
+    log_secrets: bool,
+    /// Path to the config file, defaults to indieweb.toml if it exists. Values can be overridden
+    /// by IWT_* environment variables, e.g. IWT_MASTODON__ACCESS_TOKEN
+    #[clap(long, value_parser)]
+    config: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -57,10 +62,39 @@ enum Command {
         #[clap(subcommand)]
         sub_command: app_auth::AuthSubcommand,
     },
+    /// Stored auth token helpers
+    Auth {
+        #[clap(subcommand)]
+        sub_command: auth::AuthSubcommand,
+    },
     /// Cross publish posts
     CrossPublish {
         #[clap(long, action)]
         dry_run: bool,
+        /// Wait for a running cross-publish to finish, instead of exiting with code 75
+        #[clap(long, action)]
+        wait: bool,
+    },
+    /// Release the lock of a cross-publish that is gone, e.g. crashed on another host
+    Unlock,
+    /// Record the statuses that were published by hand as the syndicated copies of the feed items
+    Adopt(cross_publisher::adopt::AdoptArgs),
+    /// Mark the current feed items as syndicated without publishing them, e.g. before the first
+    /// run on an existing blog
+    ImportSeen(cross_publisher::import_seen::ImportSeenArgs),
+    /// Check the IWT extension of the items of a feed, e.g. before the feed is deployed
+    Lint(cross_publisher::lint::LintArgs),
+    /// Render the statuses of a post without publishing them
+    Preview(cross_publisher::preview::PreviewArgs),
+    /// Review the statuses that wait for approval
+    Queue {
+        #[clap(subcommand)]
+        sub_command: cross_publisher::queue::QueueSubcommand,
+    },
+    /// Config helpers
+    Config {
+        #[clap(subcommand)]
+        sub_command: config::ConfigSubcommand,
     },
 }
 
@@ -71,12 +105,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let log_level = if cli.debug { Debug } else { Info };
     SimpleLogger::new().with_level(log_level).init().unwrap();
 
-    let config = Config::from_file(&cli.config)?;
+    if cli.log_secrets {
+        commons::redact::enable_logging_secrets();
+    }
+
+    let config = Config::load(cli.config.as_deref())?;
 
-    match cli.command {
+    let result = match cli.command {
         Command::AppAuth { sub_command } => app_auth::execute(sub_command, &config).await,
-        Command::CrossPublish { dry_run } => cross_publisher::execute(&config, dry_run).await,
+        Command::Auth { sub_command } => auth::execute(&sub_command, &config),
+        Command::CrossPublish { dry_run, wait } => {
+            cross_publisher::execute(&config, dry_run, wait).await
+        }
+        Command::Unlock => cross_publisher::unlock(&config),
+        Command::Adopt(args) => cross_publisher::adopt::execute(args, &config).await,
+        Command::ImportSeen(args) => cross_publisher::import_seen::execute(args, &config).await,
+        Command::Lint(args) => cross_publisher::lint::execute(args, &config).await,
+        Command::Preview(args) => cross_publisher::preview::execute(args, &config).await,
+        Command::Queue { sub_command } => {
+            cross_publisher::queue::execute(sub_command, &config).await
+        }
+        Command::Config { sub_command } => config::execute(&sub_command, &config),
+    };
+
+    // Schedulers can tell a skipped run from a failed one
+    if let Some(locked) = result
+        .as_ref()
+        .err()
+        .and_then(|err| err.downcast_ref::<commons::run_lock::Locked>())
+    {
+        log::warn!("{locked}");
+        std::process::exit(commons::run_lock::LOCKED_EXIT_CODE);
     }
+
+    result
 }
 
 #[cfg(test)]
@@ -1,15 +1,28 @@
 use std::fmt::Display;
 
 use rusqlite::types::{FromSql, FromSqlError};
+use serde_derive::{Deserialize, Serialize};
 
 use crate::commons::SqlConversionError;
 
-#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Network {
     Twitter,
     Mastodon,
 }
 
+impl Network {
+    #[must_use]
+    pub fn parse(network: &str) -> Option<Self> {
+        match network {
+            "twitter" => Some(Network::Twitter),
+            "mastodon" => Some(Network::Mastodon),
+            _ => None,
+        }
+    }
+}
+
 impl Display for Network {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -19,14 +32,114 @@ impl Display for Network {
     }
 }
 
+/// An account of a network the statuses are published as. The section of the network configures
+/// its default account, without a name, the named ones are listed under its `accounts`. It is
+/// written as `mastodon` or `mastodon:german`
+#[derive(Debug, PartialEq, Eq, Clone, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Account {
+    pub network: Network,
+    pub name: Option<String>,
+}
+
+impl Account {
+    #[must_use]
+    pub fn named(network: Network, name: &str) -> Self {
+        Self {
+            network,
+            name: Some(name.to_string()),
+        }
+    }
+
+    #[must_use]
+    pub fn parse(account: &str) -> Option<Self> {
+        match account.split_once(':') {
+            Some((network, name)) if !name.is_empty() => {
+                Network::parse(network).map(|network| Self::named(network, name))
+            }
+            Some(_) => None,
+            None => Network::parse(account).map(Self::from),
+        }
+    }
+}
+
+/// The default account of the network
+impl From<Network> for Account {
+    fn from(network: Network) -> Self {
+        Self {
+            network,
+            name: None,
+        }
+    }
+}
+
+impl Display for Account {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}:{name}", self.network),
+            None => write!(f, "{}", self.network),
+        }
+    }
+}
+
+impl TryFrom<String> for Account {
+    type Error = String;
+
+    fn try_from(account: String) -> Result<Self, Self::Error> {
+        Self::parse(&account).ok_or_else(|| format!("Unknown account: {account}"))
+    }
+}
+
+impl From<Account> for String {
+    fn from(account: Account) -> Self {
+        account.to_string()
+    }
+}
+
+/// Audience of a status, see https://docs.joinmastodon.org/entities/Status/#visibility
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    Unlisted,
+    Private,
+    Direct,
+}
+
+impl Visibility {
+    #[must_use]
+    pub fn parse(visibility: &str) -> Option<Self> {
+        match visibility {
+            "public" => Some(Visibility::Public),
+            "unlisted" => Some(Visibility::Unlisted),
+            "private" => Some(Visibility::Private),
+            "direct" => Some(Visibility::Direct),
+            _ => None,
+        }
+    }
+}
+
 impl FromSql for Network {
     fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
-        value.as_str().and_then(|n| match n {
-            "twitter" => Ok(Network::Twitter),
-            "mastodon" => Ok(Network::Mastodon),
-            n => Err(FromSqlError::Other(Box::new(SqlConversionError {
-                message: format!("Unknown social network: {n}"),
-            }))),
+        value.as_str().and_then(|n| {
+            Network::parse(n).ok_or_else(|| {
+                FromSqlError::Other(Box::new(SqlConversionError {
+                    message: format!("Unknown social network: {n}"),
+                }))
+            })
+        })
+    }
+}
+
+/// The accounts are stored in the `social_network` columns, the default accounts as their network
+impl FromSql for Account {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value.as_str().and_then(|a| {
+            Account::parse(a).ok_or_else(|| {
+                FromSqlError::Other(Box::new(SqlConversionError {
+                    message: format!("Unknown account: {a}"),
+                }))
+            })
         })
     }
 }
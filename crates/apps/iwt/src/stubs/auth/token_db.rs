@@ -3,12 +3,13 @@ pub mod stubs {
 
     use oauth2::{AccessToken, RefreshToken};
 
-    use crate::commons::auth::token_db::TokenDB;
+    use crate::commons::auth::token_db::{TokenDB, TokenMetadata};
     use crate::social::Network;
 
     pub struct StubTokenDB {
         access_token: Mutex<AccessToken>,
         refresh_token: Mutex<RefreshToken>,
+        metadata: Mutex<TokenMetadata>,
     }
 
     impl StubTokenDB {
@@ -17,6 +18,15 @@ pub mod stubs {
             StubTokenDB {
                 access_token: Mutex::new(AccessToken::new(String::from("initial-access-token"))),
                 refresh_token: Mutex::new(RefreshToken::new(String::from("initial-refresh-token"))),
+                metadata: Mutex::new(TokenMetadata::default()),
+            }
+        }
+
+        #[must_use]
+        pub fn with_metadata(metadata: TokenMetadata) -> Self {
+            StubTokenDB {
+                metadata: Mutex::new(metadata),
+                ..Self::new()
             }
         }
     }
@@ -44,11 +54,20 @@ pub mod stubs {
             Ok((*guard).clone())
         }
 
+        fn get_metadata(
+            &self,
+            _social_network: &Network,
+        ) -> Result<TokenMetadata, Box<dyn std::error::Error>> {
+            let guard = self.metadata.lock().unwrap();
+            Ok((*guard).clone())
+        }
+
         fn store(
             &self,
             _social_network: &Network,
             access_token: &AccessToken,
             refresh_tokem: &RefreshToken,
+            metadata: &TokenMetadata,
         ) -> Result<(), Box<dyn std::error::Error>> {
             let mut guard = self.access_token.lock().unwrap();
             *guard = access_token.clone();
@@ -56,6 +75,9 @@ pub mod stubs {
             let mut guard = self.refresh_token.lock().unwrap();
             *guard = refresh_tokem.clone();
 
+            let mut guard = self.metadata.lock().unwrap();
+            *guard = metadata.clone();
+
             Ok(())
         }
     }
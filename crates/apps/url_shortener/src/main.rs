@@ -87,7 +87,7 @@ async fn get_short_url(
     state
         .db_conn
         .call(move |conn| {
-            if let Some(short) = find_url(&url, conn).unwrap() {
+            if let Some(short) = find_short(&url, conn).unwrap() {
                 Ok(short)
             } else {
                 Err(StatusCode::NOT_FOUND)